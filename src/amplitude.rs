@@ -0,0 +1,351 @@
+use ndarray::prelude::*;
+
+/// Reduces a section of image data to a single amplitude between 0 and 1.
+/// Empty sections are silent.
+pub trait AmplitudeStrategy: Send {
+    fn amplitude(&self, img_data: &ArrayView2<u8>) -> f32;
+}
+
+/// The average brightness of the section
+pub struct Mean;
+
+/// The brightest pixel in the section
+pub struct Max;
+
+/// The root mean square brightness of the section,
+/// which favors bright pixels more than `Mean` does
+pub struct Rms;
+
+/// The median brightness of the section
+pub struct Median;
+
+/// The brightness below which the given fraction (between 0 and 1)
+/// of the section's pixels fall, using the nearest-rank method
+pub struct Percentile(pub f32);
+
+/// The fraction of the section's pixels at least as bright as `threshold`
+pub struct Coverage {
+    pub threshold: u8,
+}
+
+/// Shapes of the mapping from brightness to gain
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransferCurve {
+    Linear,
+    /// Brightness raised to the given power. Values above 1 quiet
+    /// midtones, values below 1 bring them up.
+    Gamma(f32),
+    /// Brightness mapped linearly onto a range of decibels, e.g. -60 to 0.
+    /// Completely dark sections remain silent.
+    Decibels { floor: f32, ceiling: f32 },
+}
+
+/// Converts a reduced section brightness into an oscillator gain
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(default)]
+pub struct Transfer {
+    pub curve: TransferCurve,
+    /// Treat dark pixels as loud and bright pixels as quiet
+    pub inverted: bool,
+    /// Brightness (after any inversion) below which the section is silent
+    pub gate: f32,
+}
+
+impl Default for Transfer {
+    fn default() -> Transfer {
+        Transfer {
+            curve: TransferCurve::Linear,
+            inverted: false,
+            gate: 0.,
+        }
+    }
+}
+
+impl Transfer {
+    /// Whether the curve and gate are within their ranges
+    pub fn is_valid(&self) -> bool {
+        let curve_is_valid = match self.curve {
+            TransferCurve::Linear => true,
+            TransferCurve::Gamma(gamma) => gamma > 0.,
+            TransferCurve::Decibels { floor, ceiling } => ceiling <= 0. && floor < ceiling,
+        };
+        curve_is_valid && self.gate >= 0. && self.gate <= 1.
+    }
+
+    /// Map a brightness between 0 and 1 to a gain between 0 and 1
+    pub fn apply(&self, brightness: f32) -> f32 {
+        let brightness = if self.inverted { 1. - brightness } else { brightness };
+        if brightness < self.gate || brightness <= 0. {
+            return 0.;
+        }
+        match self.curve {
+            TransferCurve::Linear => brightness,
+            TransferCurve::Gamma(gamma) => brightness.powf(gamma),
+            TransferCurve::Decibels { floor, ceiling } => {
+                let db = floor + (brightness * (ceiling - floor));
+                10_f32.powf(db / 20.)
+            }
+        }
+    }
+}
+
+#[inline]
+pub fn amplitude_from_img_data(img_data: &ArrayView2<u8>) -> f32 {
+    if img_data.is_empty() {
+        return 0.;
+    }
+    let mut sum = 0.0;
+    for val in img_data {
+        sum += *val as f32;
+    }
+    (sum / img_data.len() as f32) / (u8::max_value() as f32)
+}
+
+/// Count how many times each possible pixel value occurs in `img_data`
+#[inline]
+fn histogram(img_data: &ArrayView2<u8>) -> [usize; 256] {
+    let mut counts = [0; 256];
+    for val in img_data {
+        counts[*val as usize] += 1;
+    }
+    counts
+}
+
+fn percentile_from_img_data(img_data: &ArrayView2<u8>, fraction: f32) -> f32 {
+    if img_data.is_empty() {
+        return 0.;
+    }
+    let rank = ((fraction * img_data.len() as f32).ceil() as usize).max(1);
+    let mut seen = 0;
+    for (val, count) in histogram(img_data).iter().enumerate() {
+        seen += *count;
+        if seen >= rank {
+            return val as f32 / (u8::max_value() as f32);
+        }
+    }
+    1.
+}
+
+impl AmplitudeStrategy for Mean {
+    fn amplitude(&self, img_data: &ArrayView2<u8>) -> f32 {
+        amplitude_from_img_data(img_data)
+    }
+}
+
+impl AmplitudeStrategy for Max {
+    fn amplitude(&self, img_data: &ArrayView2<u8>) -> f32 {
+        let max = img_data.iter().max().cloned().unwrap_or(0);
+        max as f32 / (u8::max_value() as f32)
+    }
+}
+
+impl AmplitudeStrategy for Rms {
+    fn amplitude(&self, img_data: &ArrayView2<u8>) -> f32 {
+        if img_data.is_empty() {
+            return 0.;
+        }
+        let mut sum_of_squares = 0.0;
+        for val in img_data {
+            sum_of_squares += (*val as f32).powi(2);
+        }
+        (sum_of_squares / img_data.len() as f32).sqrt() / (u8::max_value() as f32)
+    }
+}
+
+impl AmplitudeStrategy for Median {
+    fn amplitude(&self, img_data: &ArrayView2<u8>) -> f32 {
+        percentile_from_img_data(img_data, 0.5)
+    }
+}
+
+impl AmplitudeStrategy for Percentile {
+    fn amplitude(&self, img_data: &ArrayView2<u8>) -> f32 {
+        percentile_from_img_data(img_data, self.0)
+    }
+}
+
+impl AmplitudeStrategy for Coverage {
+    fn amplitude(&self, img_data: &ArrayView2<u8>) -> f32 {
+        if img_data.is_empty() {
+            return 0.;
+        }
+        let covered = img_data.iter().filter(|val| **val >= self.threshold).count();
+        covered as f32 / img_data.len() as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_utils::*;
+
+    #[test]
+    fn amplitude_from_img_data_all_zeros() {
+        let img_data = array![[0, 0], [0, 0]];
+        assert_almost_eq(amplitude_from_img_data(&img_data.view()), 0.);
+    }
+
+    #[test]
+    fn amplitude_from_img_data_all_max() {
+        let img_data = array![[255, 255], [255, 255]];
+        assert_almost_eq(amplitude_from_img_data(&img_data.view()), 1.);
+    }
+
+    #[test]
+    fn amplitude_from_img_data_avg_point_5() {
+        let img_data = array![[0, 127], [128, 255]];
+        assert_almost_eq(amplitude_from_img_data(&img_data.view()), 0.5);
+    }
+
+    // A thin bright line in an otherwise dark section
+    fn thin_line() -> Array2<u8> {
+        array![[0, 0, 255, 0], [0, 0, 255, 0], [0, 0, 255, 0]]
+    }
+
+    #[test]
+    fn mean_of_thin_line() {
+        assert_almost_eq(Mean.amplitude(&thin_line().view()), 0.25);
+    }
+
+    #[test]
+    fn max_of_thin_line() {
+        assert_almost_eq(Max.amplitude(&thin_line().view()), 1.);
+    }
+
+    #[test]
+    fn rms_of_thin_line() {
+        assert_almost_eq(Rms.amplitude(&thin_line().view()), 0.5);
+    }
+
+    #[test]
+    fn median_of_thin_line() {
+        assert_almost_eq(Median.amplitude(&thin_line().view()), 0.);
+    }
+
+    #[test]
+    fn median_of_mostly_bright() {
+        let img_data = array![[0, 51], [255, 255], [255, 102]];
+        assert_almost_eq(Median.amplitude(&img_data.view()), 0.4);
+    }
+
+    #[test]
+    fn percentiles() {
+        let img_data = array![[0, 51], [102, 153], [204, 255]];
+        assert_almost_eq(Percentile(0.).amplitude(&img_data.view()), 0.);
+        assert_almost_eq(Percentile(0.5).amplitude(&img_data.view()), 0.4);
+        assert_almost_eq(Percentile(0.9).amplitude(&img_data.view()), 1.);
+        assert_almost_eq(Percentile(1.).amplitude(&img_data.view()), 1.);
+    }
+
+    #[test]
+    fn empty_sections_are_silent() {
+        let img_data = Array2::<u8>::zeros((3, 0));
+        let strategies: Vec<Box<dyn AmplitudeStrategy>> = vec![
+            Box::new(Mean),
+            Box::new(Max),
+            Box::new(Rms),
+            Box::new(Median),
+            Box::new(Percentile(0.)),
+            Box::new(Percentile(1.)),
+            Box::new(Coverage { threshold: 0 }),
+        ];
+        for strategy in strategies {
+            assert_eq!(strategy.amplitude(&img_data.view()), 0.);
+        }
+    }
+
+    #[test]
+    fn default_transfer_is_identity() {
+        let transfer = Transfer::default();
+        assert_almost_eq(transfer.apply(0.), 0.);
+        assert_almost_eq(transfer.apply(0.3), 0.3);
+        assert_almost_eq(transfer.apply(1.), 1.);
+    }
+
+    #[test]
+    fn gamma_transfer() {
+        let transfer = Transfer { curve: TransferCurve::Gamma(2.), ..Transfer::default() };
+        assert_almost_eq(transfer.apply(0.5), 0.25);
+        assert_almost_eq(transfer.apply(1.), 1.);
+    }
+
+    #[test]
+    fn decibel_transfer() {
+        let transfer = Transfer {
+            curve: TransferCurve::Decibels { floor: -60., ceiling: 0. },
+            ..Transfer::default()
+        };
+        assert_almost_eq(transfer.apply(0.), 0.);
+        assert_almost_eq(transfer.apply(0.5), 0.031622775);
+        assert_almost_eq(transfer.apply(1.), 1.);
+    }
+
+    #[test]
+    fn inverted_transfer() {
+        let transfer = Transfer { inverted: true, ..Transfer::default() };
+        assert_almost_eq(transfer.apply(0.), 1.);
+        assert_almost_eq(transfer.apply(0.25), 0.75);
+        assert_almost_eq(transfer.apply(1.), 0.);
+    }
+
+    #[test]
+    fn gated_transfer() {
+        let transfer = Transfer { gate: 0.2, ..Transfer::default() };
+        assert_almost_eq(transfer.apply(0.1), 0.);
+        assert_almost_eq(transfer.apply(0.2), 0.2);
+        assert_almost_eq(transfer.apply(0.9), 0.9);
+    }
+
+    #[test]
+    fn gate_applies_after_inversion() {
+        let transfer = Transfer { inverted: true, gate: 0.2, ..Transfer::default() };
+        assert_almost_eq(transfer.apply(0.9), 0.);
+        assert_almost_eq(transfer.apply(0.1), 0.9);
+    }
+
+    #[test]
+    fn coverage_of_thin_line() {
+        let strategy = Coverage { threshold: 128 };
+        assert_almost_eq(strategy.amplitude(&thin_line().view()), 0.25);
+    }
+
+    #[test]
+    fn coverage_threshold_is_inclusive() {
+        let img_data = array![[127, 128]];
+        assert_almost_eq(Coverage { threshold: 128 }.amplitude(&img_data.view()), 0.5);
+    }
+}
+
+#[cfg(test)]
+mod benchmarks {
+    extern crate rand;
+    extern crate test;
+    use super::*;
+
+    #[bench]
+    fn bench_amplitude_from_image_data(b: &mut test::Bencher) {
+        let image_data = random_image_data(1_000, 1_000);
+        let image_view = image_data.view();
+        b.iter(|| {
+            amplitude_from_img_data(&image_view);
+        });
+    }
+
+    #[bench]
+    fn bench_median(b: &mut test::Bencher) {
+        let image_data = random_image_data(1_000, 1_000);
+        let image_view = image_data.view();
+        b.iter(|| {
+            Median.amplitude(&image_view);
+        });
+    }
+
+    fn random_image_data(width: usize, height: usize) -> Array2<u8> {
+        let mut random_array = Array2::zeros((width, height));
+        for element in random_array.iter_mut() {
+            *element = rand::random::<u8>();
+        }
+        random_array
+    }
+}
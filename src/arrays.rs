@@ -5,6 +5,7 @@ use std::mem;
 use ndarray::prelude::*;
 
 
+#[allow(dead_code)]
 #[inline]
 fn unsafe_memcpy(write_ptr: usize, src_ptr: usize, bytes: usize) {
     unsafe {
@@ -17,6 +18,7 @@ fn unsafe_memcpy(write_ptr: usize, src_ptr: usize, bytes: usize) {
 }
 
 
+#[allow(dead_code)]
 pub fn roll_vec<T>(src_vec: &Vec<T>, src_offset: usize, elements: usize) -> Vec<T> {
     let element_size = mem::size_of::<T>();
     let roll_offset = src_offset % src_vec.len();
@@ -31,7 +33,7 @@ pub fn roll_vec<T>(src_vec: &Vec<T>, src_offset: usize, elements: usize) -> Vec<
         src_ptr = src_vec.as_slice().split_at(roll_offset).1.as_ptr() as usize;
         let bytes = (src_vec.len() - roll_offset) * element_size;
         unsafe_memcpy(write_ptr, src_ptr, bytes);
-        write_ptr = write_ptr + bytes;
+        write_ptr += bytes;
         copied_elements = src_vec.len() - roll_offset;
     }
 
@@ -40,7 +42,7 @@ pub fn roll_vec<T>(src_vec: &Vec<T>, src_offset: usize, elements: usize) -> Vec<
     src_ptr = src_vec.as_slice().as_ptr() as usize;
     for _ in 0..full_rolls {
         unsafe_memcpy(write_ptr, src_ptr, bytes_per_copy);
-        write_ptr = write_ptr + bytes_per_copy;
+        write_ptr += bytes_per_copy;
     }
     copied_elements += full_rolls * src_vec.len();
 
@@ -65,6 +67,25 @@ pub fn multiply_over_linspace(data: &mut[f32], start: f32, end: f32) {
     }
 }
 
+/// Multiply `data` by a piecewise linear envelope
+///
+/// The envelope starts from `start` and ramps to the end value of each
+/// `(length, end)` segment in turn, reaching it on the segment's last element.
+/// Segment lengths must sum to `data.len()`.
+pub fn multiply_over_segments(data: &mut [f32], start: f32, segments: &[(usize, f32)]) {
+    debug_assert_eq!(segments.iter().map(|s| s.0).sum::<usize>(), data.len());
+    let mut index = 0;
+    let mut segment_start = start;
+    for &(len, end) in segments {
+        let step = (end - segment_start) / (len as f32);
+        for (i, element) in data[index..index + len].iter_mut().enumerate() {
+            *element *= segment_start + (step * (i + 1) as f32);
+        }
+        index += len;
+        segment_start = end;
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -119,4 +140,18 @@ mod tests {
         multiply_over_linspace(vec.as_mut_slice(), 0., 1.);
         assert_almost_eq_by_element(vec, vec![0., 1., 3.]);
     }
+
+    #[test]
+    fn test_multiply_over_segments_single() {
+        let mut vec = vec![1., 1., 1., 1.];
+        multiply_over_segments(vec.as_mut_slice(), 0., &[(4, 1.)]);
+        assert_almost_eq_by_element(vec, vec![0.25, 0.5, 0.75, 1.]);
+    }
+
+    #[test]
+    fn test_multiply_over_segments_multiple() {
+        let mut vec = vec![2., 2., 2., 2., 2., 2.];
+        multiply_over_segments(vec.as_mut_slice(), 1., &[(2, 0.), (1, 1.), (3, 1.)]);
+        assert_almost_eq_by_element(vec, vec![1., 0., 2., 2., 2., 2.]);
+    }
 }
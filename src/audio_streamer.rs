@@ -1,5 +1,6 @@
 use std::sync::mpsc::Receiver;
 
 pub trait AudioStreamer<T> {
-    fn stream(&self, chunk_receiver: Receiver<Vec<T>>);
+    /// Play or store the chunks as audio sampled at `sample_rate` Hz
+    fn stream(&self, chunk_receiver: Receiver<Vec<T>>, sample_rate: u32);
 }
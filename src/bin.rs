@@ -2,21 +2,27 @@ extern crate clap;
 extern crate num;
 extern crate spectrophoner;
 
-use std::time::Duration;
-use std::thread;
 use std::path::Path;
+use std::process;
+use std::thread;
+use std::time::Duration;
 
-use clap::{Arg, App, SubCommand, ArgMatches};
+use clap::{Arg, App};
 
 use spectrophoner::conductor;
-use spectrophoner::audio_streamer::AudioStreamer;
 use spectrophoner::portaudio_streamer::PortAudioStreamer;
+use spectrophoner::score::Score;
 use spectrophoner::wav_streamer::WavStreamer;
 
+const SCORE_PATH_ARG: &str = "SCORE_PATH";
 const OUT_PATH_ARG: &str = "OUT_PATH";
 
 pub fn main() {
     let matches = App::new("spectrophoner")
+        .arg(Arg::with_name(SCORE_PATH_ARG)
+             .help("Path to the score file to render, e.g. resources/ascending_line.toml")
+             .required(true)
+             .index(1))
         .arg(Arg::with_name(OUT_PATH_ARG)
              .short("o")
              .long("output")
@@ -25,13 +31,28 @@ pub fn main() {
              .takes_value(true))
         .get_matches();
 
-    match matches.value_of(OUT_PATH_ARG) {
+    let score_path = Path::new(matches.value_of(SCORE_PATH_ARG).unwrap());
+    let score = match Score::from_file(score_path) {
+        Ok(score) => score,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    };
+
+    let result = match matches.value_of(OUT_PATH_ARG) {
         Some(path) => {
-            conductor::conduct(WavStreamer::<f32>::new(path.to_string()));
+            conductor::conduct(score, WavStreamer::<f32>::new(path.to_string()))
         },
         None => {
-            conductor::conduct(PortAudioStreamer::new());
-            thread::sleep(Duration::from_millis(1000_000));
+            let result = conductor::conduct(score, PortAudioStreamer::new());
+            thread::sleep(Duration::from_millis(1_000_000));
+            result
         }
+    };
+
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        process::exit(1);
     }
 }
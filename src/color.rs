@@ -27,7 +27,33 @@ pub fn color_distance(left: Rgb<u8>, right: Rgb<u8>) -> f32 {
         .sqrt();
     let scaled = unscaled / MAX_UNSCALED_DISTANCE;
     debug_assert!(scaled <= 1.0);
-    return scaled;
+    scaled
+}
+
+/// Convert an RGB color to hue, saturation, and value, each between 0 and 1
+///
+/// Hue is measured in turns starting at red. Grays have a hue of 0.
+pub fn rgb_to_hsv(color: Rgb<u8>) -> (f32, f32, f32) {
+    let r = color.data[0] as f32 / 255.0;
+    let g = color.data[1] as f32 / 255.0;
+    let b = color.data[2] as f32 / 255.0;
+
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let chroma = max - min;
+
+    let hue_sixths = if chroma == 0.0 {
+        0.0
+    } else if max == r {
+        ((g - b) / chroma + 6.0) % 6.0
+    } else if max == g {
+        (b - r) / chroma + 2.0
+    } else {
+        (r - g) / chroma + 4.0
+    };
+    let saturation = if max == 0.0 { 0.0 } else { chroma / max };
+
+    (hue_sixths / 6.0, saturation, max)
 }
 
 #[cfg(test)]
@@ -36,7 +62,6 @@ mod tests {
     use super::*;
     use itertools::Itertools;
     use test_utils::*;
-    use std::ops::Range;
 
     #[test]
     fn test_zero_distance() {
@@ -48,6 +73,36 @@ mod tests {
         assert_almost_eq(color_distance(rgb(0, 0, 0), rgb(255, 255, 255)), 1.);
     }
 
+    #[test]
+    fn test_hsv_of_gray() {
+        let (h, s, v) = rgb_to_hsv(rgb(128, 128, 128));
+        assert_almost_eq(h, 0.);
+        assert_almost_eq(s, 0.);
+        assert_almost_eq(v, 128. / 255.);
+    }
+
+    #[test]
+    fn test_hsv_of_primaries() {
+        let (h, s, v) = rgb_to_hsv(rgb(255, 0, 0));
+        assert_almost_eq(h, 0.);
+        assert_almost_eq(s, 1.);
+        assert_almost_eq(v, 1.);
+        assert_almost_eq(rgb_to_hsv(rgb(0, 255, 0)).0, 1. / 3.);
+        assert_almost_eq(rgb_to_hsv(rgb(0, 0, 255)).0, 2. / 3.);
+    }
+
+    #[test]
+    fn test_hsv_of_magenta_wraps() {
+        assert_almost_eq(rgb_to_hsv(rgb(255, 0, 255)).0, 5. / 6.);
+    }
+
+    #[test]
+    fn test_hsv_half_saturation() {
+        let (_, s, v) = rgb_to_hsv(rgb(200, 100, 100));
+        assert_almost_eq(s, 0.5);
+        assert_almost_eq(v, 200. / 255.);
+    }
+
     #[bench]
     fn color_distances(b: &mut test::Bencher) {
         // ~10.5m crunches per second on shitty linux box
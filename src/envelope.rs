@@ -0,0 +1,224 @@
+/// How each envelope stage moves from its start level to its target
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EnvelopeCurve {
+    Linear,
+    /// Fast at first then slowing, like an analog envelope
+    Exponential,
+}
+
+impl Default for EnvelopeCurve {
+    fn default() -> EnvelopeCurve {
+        EnvelopeCurve::Linear
+    }
+}
+
+/// Steepness of `EnvelopeCurve::Exponential`
+const EXPONENTIAL_STEEPNESS: f32 = 5.;
+
+impl EnvelopeCurve {
+    /// Map progress through a stage to progress towards its target, both between 0 and 1
+    #[inline]
+    fn shape(&self, progress: f32) -> f32 {
+        match *self {
+            EnvelopeCurve::Linear => progress,
+            EnvelopeCurve::Exponential => {
+                (1. - (-EXPONENTIAL_STEEPNESS * progress).exp())
+                    / (1. - (-EXPONENTIAL_STEEPNESS).exp())
+            }
+        }
+    }
+}
+
+/// Attack, decay, and release times in seconds, and a sustain level between 0 and 1
+#[derive(Debug, Copy, Clone, Deserialize)]
+pub struct Adsr {
+    pub attack: f32,
+    pub decay: f32,
+    pub sustain: f32,
+    pub release: f32,
+    #[serde(default)]
+    pub curve: EnvelopeCurve,
+}
+
+impl Adsr {
+    /// Whether every time is non-negative and the sustain level is between 0 and 1
+    pub fn is_valid(&self) -> bool {
+        self.attack >= 0.
+            && self.decay >= 0.
+            && self.release >= 0.
+            && self.sustain >= 0.
+            && self.sustain <= 1.
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Stage {
+    Idle,
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+}
+
+/// An ADSR envelope generator, producing a level between 0 and 1 per sample
+///
+/// Each stage starts from wherever the level was when it began, so
+/// retriggering or releasing mid-stage never jumps.
+pub struct Envelope {
+    adsr: Adsr,
+    attack_samples: usize,
+    decay_samples: usize,
+    release_samples: usize,
+    stage: Stage,
+    // Samples elapsed in the current stage
+    stage_position: usize,
+    stage_start_level: f32,
+    level: f32,
+}
+
+impl Envelope {
+    pub fn new(adsr: Adsr, sample_rate: u32) -> Envelope {
+        assert!(adsr.is_valid(), "Invalid envelope: {:?}", adsr);
+        let to_samples = |seconds: f32| (seconds * sample_rate as f32).round() as usize;
+        Envelope {
+            attack_samples: to_samples(adsr.attack),
+            decay_samples: to_samples(adsr.decay),
+            release_samples: to_samples(adsr.release),
+            adsr,
+            stage: Stage::Idle,
+            stage_position: 0,
+            stage_start_level: 0.,
+            level: 0.,
+        }
+    }
+
+    /// Start (or restart) the attack
+    pub fn gate_on(&mut self) {
+        self.enter(Stage::Attack);
+    }
+
+    /// Start the release, if not already releasing
+    pub fn gate_off(&mut self) {
+        if self.is_open() {
+            self.enter(Stage::Release);
+        }
+    }
+
+    /// Whether the envelope has been gated on and not yet released
+    pub fn is_open(&self) -> bool {
+        match self.stage {
+            Stage::Attack | Stage::Decay | Stage::Sustain => true,
+            Stage::Idle | Stage::Release => false,
+        }
+    }
+
+    fn enter(&mut self, stage: Stage) {
+        self.stage = stage;
+        self.stage_position = 0;
+        self.stage_start_level = self.level;
+    }
+
+    #[inline]
+    pub fn next_level(&mut self) -> f32 {
+        let (target, duration, next_stage) = match self.stage {
+            Stage::Idle | Stage::Sustain => return self.level,
+            Stage::Attack => (1., self.attack_samples, Stage::Decay),
+            Stage::Decay => (self.adsr.sustain, self.decay_samples, Stage::Sustain),
+            Stage::Release => (0., self.release_samples, Stage::Idle),
+        };
+        self.stage_position += 1;
+        if self.stage_position >= duration {
+            self.level = target;
+            self.enter(next_stage);
+        } else {
+            let progress = self.adsr.curve.shape(self.stage_position as f32 / duration as f32);
+            self.level = self.stage_start_level + ((target - self.stage_start_level) * progress);
+        }
+        self.level
+    }
+
+    pub fn levels(&mut self, num: usize) -> Vec<f32> {
+        (0..num).map(|_| self.next_level()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_utils::*;
+
+    fn adsr(attack: f32, decay: f32, sustain: f32, release: f32) -> Adsr {
+        Adsr {
+            attack,
+            decay,
+            sustain,
+            release,
+            curve: EnvelopeCurve::Linear,
+        }
+    }
+
+    #[test]
+    fn idle_until_gated() {
+        let mut envelope = Envelope::new(adsr(0., 0., 1., 0.), 10);
+        assert_almost_eq_by_element(envelope.levels(3), vec![0., 0., 0.]);
+    }
+
+    #[test]
+    fn linear_stages() {
+        let mut envelope = Envelope::new(adsr(0.4, 0.2, 0.5, 0.4), 10);
+        envelope.gate_on();
+        let levels = envelope.levels(8);
+        assert_almost_eq_by_element(levels, vec![0.25, 0.5, 0.75, 1., 0.75, 0.5, 0.5, 0.5]);
+        envelope.gate_off();
+        assert_almost_eq_by_element(envelope.levels(5), vec![0.375, 0.25, 0.125, 0., 0.]);
+        assert!(!envelope.is_open());
+    }
+
+    #[test]
+    fn zero_attack_is_immediate() {
+        let mut envelope = Envelope::new(adsr(0., 0.2, 0., 0.), 10);
+        envelope.gate_on();
+        assert_almost_eq_by_element(envelope.levels(4), vec![1., 0.5, 0., 0.]);
+    }
+
+    #[test]
+    fn retrigger_attacks_from_current_level() {
+        let mut envelope = Envelope::new(adsr(0.4, 0., 1., 0.4), 10);
+        envelope.gate_on();
+        envelope.levels(4);
+        envelope.gate_off();
+        envelope.levels(2);
+        envelope.gate_on();
+        assert_almost_eq_by_element(envelope.levels(4), vec![0.625, 0.75, 0.875, 1.]);
+    }
+
+    #[test]
+    fn release_before_sustain() {
+        let mut envelope = Envelope::new(adsr(0.4, 0., 1., 0.2), 10);
+        envelope.gate_on();
+        envelope.levels(2);
+        envelope.gate_off();
+        assert_almost_eq_by_element(envelope.levels(3), vec![0.25, 0., 0.]);
+    }
+
+    #[test]
+    fn repeated_gate_off_keeps_releasing() {
+        let mut envelope = Envelope::new(adsr(0., 0., 1., 0.2), 10);
+        envelope.gate_on();
+        envelope.levels(1);
+        envelope.gate_off();
+        envelope.levels(1);
+        envelope.gate_off();
+        assert_almost_eq_by_element(envelope.levels(2), vec![0., 0.]);
+    }
+
+    #[test]
+    fn exponential_curve() {
+        let curve = EnvelopeCurve::Exponential;
+        assert_almost_eq(curve.shape(0.), 0.);
+        assert_almost_eq(curve.shape(1.), 1.);
+        // Most of the way there by half way through
+        assert!(curve.shape(0.5) > 0.9);
+    }
+}
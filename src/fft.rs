@@ -0,0 +1,108 @@
+use std::f64::consts;
+
+/// In-place iterative radix-2 Cooley-Tukey FFT over split real and imaginary parts
+///
+/// `re` and `im` must have the same power-of-two length.
+/// The inverse transform is scaled by `1 / len`, so a forward transform
+/// followed by an inverse one returns the original signal.
+pub fn fft(re: &mut [f64], im: &mut [f64], inverse: bool) {
+    let len = re.len();
+    assert_eq!(len, im.len());
+    assert!(len.is_power_of_two(), "FFT length must be a power of two, got {}", len);
+
+    // Bit-reversal permutation
+    let mut j = 0;
+    for i in 1..len {
+        let mut bit = len >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+
+    let direction = if inverse { 1. } else { -1. };
+    let mut block_len = 2;
+    while block_len <= len {
+        let angle = direction * 2. * consts::PI / block_len as f64;
+        let (step_im, step_re) = angle.sin_cos();
+        for block_start in (0..len).step_by(block_len) {
+            let mut twiddle_re = 1.;
+            let mut twiddle_im = 0.;
+            for k in 0..block_len / 2 {
+                let even = block_start + k;
+                let odd = even + block_len / 2;
+                let odd_re = re[odd] * twiddle_re - im[odd] * twiddle_im;
+                let odd_im = re[odd] * twiddle_im + im[odd] * twiddle_re;
+                re[odd] = re[even] - odd_re;
+                im[odd] = im[even] - odd_im;
+                re[even] += odd_re;
+                im[even] += odd_im;
+                let next_twiddle_re = twiddle_re * step_re - twiddle_im * step_im;
+                twiddle_im = twiddle_re * step_im + twiddle_im * step_re;
+                twiddle_re = next_twiddle_re;
+            }
+        }
+        block_len <<= 1;
+    }
+
+    if inverse {
+        for (r, i) in re.iter_mut().zip(im.iter_mut()) {
+            *r /= len as f64;
+            *i /= len as f64;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn impulse_has_flat_spectrum() {
+        let mut re = vec![1., 0., 0., 0., 0., 0., 0., 0.];
+        let mut im = vec![0.; 8];
+        fft(&mut re, &mut im, false);
+        for k in 0..8 {
+            assert!((re[k] - 1.).abs() < 1e-12);
+            assert!(im[k].abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn sine_lands_in_its_bin() {
+        let len = 64;
+        let mut re: Vec<f64> = (0..len)
+            .map(|i| (2. * consts::PI * 5. * i as f64 / len as f64).sin())
+            .collect();
+        let mut im = vec![0.; len];
+        fft(&mut re, &mut im, false);
+        for k in 0..len {
+            let magnitude = (re[k].powi(2) + im[k].powi(2)).sqrt();
+            if k == 5 || k == len - 5 {
+                assert!((magnitude - len as f64 / 2.).abs() < 1e-9);
+            } else {
+                assert!(magnitude < 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn inverse_round_trip() {
+        let original = vec![0.5, -1., 2., 0., 3.25, -0.75, 1., 0.125];
+        let mut re = original.clone();
+        let mut im = vec![0.; 8];
+        fft(&mut re, &mut im, false);
+        fft(&mut re, &mut im, true);
+        for (actual, expected) in re.iter().zip(original.iter()) {
+            assert!((actual - expected).abs() < 1e-12);
+        }
+        for val in im {
+            assert!(val.abs() < 1e-12);
+        }
+    }
+}
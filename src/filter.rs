@@ -0,0 +1,192 @@
+use std::f64::consts;
+
+/// How many samples pass between recalculations of swept coefficients
+const COEFFICIENT_INTERVAL: usize = 32;
+/// Lowest cutoff, below which coefficients lose precision
+const MIN_CUTOFF: f32 = 10.;
+/// Highest cutoff as a fraction of the sample rate, just below Nyquist
+const MAX_CUTOFF_RATIO: f32 = 0.49;
+const MIN_Q: f32 = 0.05;
+
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterKind {
+    LowPass,
+    HighPass,
+    /// Passes frequencies around the cutoff, with unity gain at its peak
+    BandPass,
+    /// Removes frequencies around the cutoff
+    Notch,
+}
+
+/// A resonant second-order filter, with the coefficients of
+/// Robert Bristow-Johnson's Audio EQ Cookbook
+///
+/// For band-pass and notch filters the cutoff is the center frequency.
+/// Q sets the resonance of low- and high-pass filters, with 0.707 the flattest
+/// response, and the narrowness of band-pass and notch filters.
+/// Cutoffs are kept between 10 Hz and just below the Nyquist frequency.
+pub struct Biquad {
+    kind: FilterKind,
+    sample_rate: u32,
+    b0: f64,
+    b1: f64,
+    b2: f64,
+    a1: f64,
+    a2: f64,
+    // The last two inputs and outputs
+    x1: f64,
+    x2: f64,
+    y1: f64,
+    y2: f64,
+}
+
+impl Biquad {
+    pub fn new(kind: FilterKind, cutoff: f32, q: f32, sample_rate: u32) -> Biquad {
+        let mut biquad = Biquad {
+            kind,
+            sample_rate,
+            b0: 1.,
+            b1: 0.,
+            b2: 0.,
+            a1: 0.,
+            a2: 0.,
+            x1: 0.,
+            x2: 0.,
+            y1: 0.,
+            y2: 0.,
+        };
+        biquad.set_parameters(cutoff, q);
+        biquad
+    }
+
+    /// Retune the filter without clearing its state
+    pub fn set_parameters(&mut self, cutoff: f32, q: f32) {
+        let max_cutoff = self.sample_rate as f32 * MAX_CUTOFF_RATIO;
+        let cutoff = cutoff.max(MIN_CUTOFF).min(max_cutoff) as f64;
+        let q = q.max(MIN_Q) as f64;
+        let w0 = 2. * consts::PI * cutoff / self.sample_rate as f64;
+        let (sin, cos) = (w0.sin(), w0.cos());
+        let alpha = sin / (2. * q);
+        let (b0, b1, b2) = match self.kind {
+            FilterKind::LowPass => ((1. - cos) / 2., 1. - cos, (1. - cos) / 2.),
+            FilterKind::HighPass => ((1. + cos) / 2., -(1. + cos), (1. + cos) / 2.),
+            FilterKind::BandPass => (alpha, 0., -alpha),
+            FilterKind::Notch => (1., -2. * cos, 1.),
+        };
+        let a0 = 1. + alpha;
+        self.b0 = b0 / a0;
+        self.b1 = b1 / a0;
+        self.b2 = b2 / a0;
+        self.a1 = (-2. * cos) / a0;
+        self.a2 = (1. - alpha) / a0;
+    }
+
+    /// Filter `samples` in place
+    pub fn process(&mut self, samples: &mut [f32]) {
+        for sample in samples.iter_mut() {
+            *sample = self.next_sample(*sample);
+        }
+    }
+
+    /// Filter `samples` in place while sweeping the cutoff and Q,
+    /// given one of each per sample
+    pub fn process_swept(&mut self, samples: &mut [f32], cutoffs: &[f32], qs: &[f32]) {
+        debug_assert!(cutoffs.len() == samples.len() && qs.len() == samples.len());
+        for (i, sample) in samples.iter_mut().enumerate() {
+            if i % COEFFICIENT_INTERVAL == 0 {
+                self.set_parameters(cutoffs[i], qs[i]);
+            }
+            *sample = self.next_sample(*sample);
+        }
+    }
+
+    #[inline]
+    fn next_sample(&mut self, input: f32) -> f32 {
+        let x0 = input as f64;
+        let y0 = (self.b0 * x0) + (self.b1 * self.x1) + (self.b2 * self.x2)
+            - (self.a1 * self.y1)
+            - (self.a2 * self.y2);
+        self.x2 = self.x1;
+        self.x1 = x0;
+        self.y2 = self.y1;
+        self.y1 = y0;
+        y0 as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32;
+
+    /// The peak level of a unit sine at `frequency` once the filter has settled
+    fn gain(biquad: &mut Biquad, frequency: f32) -> f32 {
+        let mut samples: Vec<f32> = (0..8820)
+            .map(|i| (2. * f32::consts::PI * frequency * i as f32 / 44100.).sin())
+            .collect();
+        biquad.process(&mut samples);
+        samples[4410..].iter().fold(0., |peak: f32, s| peak.max(s.abs()))
+    }
+
+    #[test]
+    fn low_pass_passes_lows() {
+        assert!((gain(&mut Biquad::new(FilterKind::LowPass, 1000., 0.707, 44100), 50.) - 1.).abs()
+            < 0.01);
+        assert!(gain(&mut Biquad::new(FilterKind::LowPass, 1000., 0.707, 44100), 10000.) < 0.02);
+    }
+
+    #[test]
+    fn high_pass_passes_highs() {
+        assert!(gain(&mut Biquad::new(FilterKind::HighPass, 1000., 0.707, 44100), 50.) < 0.01);
+        assert!(
+            (gain(&mut Biquad::new(FilterKind::HighPass, 1000., 0.707, 44100), 10000.) - 1.).abs()
+                < 0.02
+        );
+    }
+
+    #[test]
+    fn resonance_peaks_at_cutoff() {
+        let flat = gain(&mut Biquad::new(FilterKind::LowPass, 1000., 0.707, 44100), 1000.);
+        let resonant = gain(&mut Biquad::new(FilterKind::LowPass, 1000., 8., 44100), 1000.);
+        assert!((flat - 0.707).abs() < 0.01, "gain of {}", flat);
+        assert!((resonant - 8.).abs() < 0.1, "gain of {}", resonant);
+    }
+
+    #[test]
+    fn band_pass_passes_center() {
+        let biquad = || Biquad::new(FilterKind::BandPass, 1000., 4., 44100);
+        assert!((gain(&mut biquad(), 1000.) - 1.).abs() < 0.01);
+        assert!(gain(&mut biquad(), 100.) < 0.05);
+        assert!(gain(&mut biquad(), 10000.) < 0.05);
+    }
+
+    #[test]
+    fn notch_removes_center() {
+        let biquad = || Biquad::new(FilterKind::Notch, 1000., 4., 44100);
+        assert!(gain(&mut biquad(), 1000.) < 0.01);
+        assert!((gain(&mut biquad(), 100.) - 1.).abs() < 0.01);
+    }
+
+    #[test]
+    fn cutoff_is_clamped_below_nyquist() {
+        let mut biquad = Biquad::new(FilterKind::LowPass, 100000., 0.707, 44100);
+        let mut samples = vec![1.; 1000];
+        biquad.process(&mut samples);
+        assert!(samples.iter().all(|s| s.is_finite()));
+        assert!((samples[999] - 1.).abs() < 1e-3);
+    }
+
+    #[test]
+    fn sweep_opens_filter() {
+        let mut biquad = Biquad::new(FilterKind::LowPass, 100., 0.707, 44100);
+        let mut samples: Vec<f32> = (0..8820)
+            .map(|i| (2. * f32::consts::PI * 5000. * i as f32 / 44100.).sin())
+            .collect();
+        let cutoffs: Vec<f32> = (0..8820).map(|i| if i < 4410 { 100. } else { 20000. }).collect();
+        biquad.process_swept(&mut samples, &cutoffs, &vec![0.707; 8820]);
+        let peak = |samples: &[f32]| samples.iter().fold(0., |peak: f32, s| peak.max(s.abs()));
+        assert!(peak(&samples[2000..4410]) < 0.01);
+        assert!(peak(&samples[6000..]) > 0.95);
+    }
+}
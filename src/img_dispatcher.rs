@@ -1,13 +1,12 @@
 use std::collections::HashMap;
-use std::fs::File;
-use std::path::Path;
 use std::sync::mpsc::{channel, Receiver, Sender};
 
-use image;
 use image::imageops::colorops;
-use image::{GenericImage, ImageBuffer, Rgb, RgbImage, SubImage};
+use image::{GenericImage, ImageBuffer, Pixel, Rgb, Rgba, SubImage};
 use ndarray::prelude::*;
 
+use color;
+
 /// Type alias for image channel identifiers
 pub type ImgLayerId = u16;
 
@@ -17,8 +16,8 @@ pub type ImgLayerId = u16;
 /// represented as a 2D array of u8's.
 pub type ImgPacket = HashMap<ImgLayerId, Array2<u8>>;
 
-pub type RgbImage24Bit = ImageBuffer<Rgb<u8>, Vec<u8>>;
-pub type RgbImage24BitSlice<'a> = SubImage<'a, ImageBuffer<Rgb<u8>, Vec<u8>>>;
+pub type RgbaImage32Bit = ImageBuffer<Rgba<u8>, Vec<u8>>;
+pub type RgbaImage32BitSlice<'a> = SubImage<'a, ImageBuffer<Rgba<u8>, Vec<u8>>>;
 
 #[derive(Debug, Copy, Clone)]
 pub struct ImgLayerMetadata {
@@ -32,7 +31,18 @@ pub struct ImgLayerMetadata {
     pub total_img_height: usize,
 }
 
-type LayerExtractorFn = fn(&RgbImage24BitSlice) -> Array2<u8>;
+/// A function producing a layer's image data from a slice of the source image
+pub type LayerExtractorFn = Box<dyn Fn(&RgbaImage32BitSlice) -> Array2<u8> + Send>;
+
+/// Describes a channel to be dispatched: a horizontal band of the image
+/// and the layers to extract for it
+///
+/// Like `ImgLayerMetadata`, coordinates are relative to the complete image.
+pub struct ChannelSpec {
+    pub y_start: usize,
+    pub y_end: usize,
+    pub layer_extractors: HashMap<ImgLayerId, LayerExtractorFn>,
+}
 
 pub struct ChannelExporter {
     pub receiver: Receiver<ImgPacket>,
@@ -48,7 +58,7 @@ struct ChannelHandler {
 }
 
 impl ChannelHandler {
-    fn dispatch_channel(&self, img: &RgbImage24BitSlice) {
+    fn dispatch_channel(&self, img: &RgbaImage32BitSlice) {
         let mut packet = ImgPacket::new();
         for (layer_id, layer_extractor_fn) in &self.layer_extractors {
             packet.insert(*layer_id, layer_extractor_fn(img));
@@ -60,18 +70,21 @@ impl ChannelHandler {
 /// Responsible for managing a series of channels via ChannelHandlers
 pub struct StaticImgDispatcher {
     channel_handlers: Vec<ChannelHandler>,
-    img: RgbImage24Bit,
+    img: RgbaImage32Bit,
     chunk_width: u32,
 }
 
 impl StaticImgDispatcher {
-    pub fn new(path: &Path, chunk_width: u32) -> (StaticImgDispatcher, Vec<ChannelExporter>) {
-        let img = image::open(path).unwrap().to_rgb();
-
+    pub fn new(
+        img: RgbaImage32Bit,
+        chunk_width: u32,
+        channel_specs: Vec<ChannelSpec>,
+    ) -> (StaticImgDispatcher, Vec<ChannelExporter>) {
+        assert!(chunk_width > 0, "chunk_width must be positive");
         let mut channel_handlers = Vec::<ChannelHandler>::new();
         let mut channel_exporters = Vec::<ChannelExporter>::new();
 
-        for (handler, exporter) in Self::generate_channels(&img) {
+        for (handler, exporter) in Self::generate_channels(&img, channel_specs) {
             channel_handlers.push(handler);
             channel_exporters.push(exporter);
         }
@@ -86,28 +99,38 @@ impl StaticImgDispatcher {
         )
     }
 
-    fn generate_channels(img: &RgbImage24Bit) -> Vec<(ChannelHandler, ChannelExporter)> {
-        // naive initial implementation using just 1 channel with just 1 layer
-        let (sender, receiver) = channel::<ImgPacket>();
-        let layers_metadata = vec![ImgLayerMetadata {
-            img_layer_id: 0,
-            y_start: 0,
-            y_end: img.height() as usize,
-            total_img_height: img.height() as usize,
-        }];
-        let mut layer_extractors = HashMap::<ImgLayerId, LayerExtractorFn>::new();
-        layer_extractors.insert(0, naive_layer_extractor);
-
-        vec![(
-            ChannelHandler {
-                sender,
-                layer_extractors,
-            },
-            ChannelExporter {
-                receiver,
-                layers_metadata,
-            },
-        )]
+    fn generate_channels(
+        img: &RgbaImage32Bit,
+        channel_specs: Vec<ChannelSpec>,
+    ) -> Vec<(ChannelHandler, ChannelExporter)> {
+        let total_img_height = img.height() as usize;
+        let mut channels = Vec::<(ChannelHandler, ChannelExporter)>::new();
+        for channel_spec in channel_specs {
+            debug_assert!(channel_spec.y_start <= channel_spec.y_end);
+            debug_assert!(channel_spec.y_end <= total_img_height);
+            let (sender, receiver) = channel::<ImgPacket>();
+            let layers_metadata = channel_spec
+                .layer_extractors
+                .keys()
+                .map(|layer_id| ImgLayerMetadata {
+                    img_layer_id: *layer_id,
+                    y_start: channel_spec.y_start,
+                    y_end: channel_spec.y_end,
+                    total_img_height,
+                })
+                .collect();
+            channels.push((
+                ChannelHandler {
+                    sender,
+                    layer_extractors: channel_spec.layer_extractors,
+                },
+                ChannelExporter {
+                    receiver,
+                    layers_metadata,
+                },
+            ));
+        }
+        channels
     }
 
     /// Send chunks of image data through channels until the image is fully consumed.
@@ -135,7 +158,7 @@ impl StaticImgDispatcher {
     }
 }
 
-pub fn naive_layer_extractor(img: &RgbImage24BitSlice) -> Array2<u8> {
+pub fn naive_layer_extractor(img: &RgbaImage32BitSlice) -> Array2<u8> {
     let grayscale = colorops::grayscale(img);
     Array::from_shape_vec(
         (img.width() as usize, img.height() as usize).strides((1, img.width() as usize)),
@@ -143,12 +166,127 @@ pub fn naive_layer_extractor(img: &RgbImage24BitSlice) -> Array2<u8> {
     ).unwrap()
 }
 
+/// Build a layer by applying `pixel_fn` to every pixel of `img`
+#[inline]
+fn map_pixels<F>(img: &RgbaImage32BitSlice, pixel_fn: F) -> Array2<u8>
+where
+    F: Fn(Rgba<u8>) -> u8,
+{
+    let mut layer = Array2::<u8>::zeros((img.width() as usize, img.height() as usize));
+    for (x, y, pixel) in img.pixels() {
+        layer[[x as usize, y as usize]] = pixel_fn(pixel);
+    }
+    layer
+}
+
+#[inline]
+fn unit_to_u8(val: f32) -> u8 {
+    (val * 255.).round() as u8
+}
+
+pub fn red_layer_extractor(img: &RgbaImage32BitSlice) -> Array2<u8> {
+    map_pixels(img, |pixel| pixel.data[0])
+}
+
+pub fn green_layer_extractor(img: &RgbaImage32BitSlice) -> Array2<u8> {
+    map_pixels(img, |pixel| pixel.data[1])
+}
+
+pub fn blue_layer_extractor(img: &RgbaImage32BitSlice) -> Array2<u8> {
+    map_pixels(img, |pixel| pixel.data[2])
+}
+
+/// Hue, with 0 at red increasing through green and blue. Grays have a hue of 0.
+pub fn hue_layer_extractor(img: &RgbaImage32BitSlice) -> Array2<u8> {
+    map_pixels(img, |pixel| unit_to_u8(color::rgb_to_hsv(pixel.to_rgb()).0))
+}
+
+pub fn saturation_layer_extractor(img: &RgbaImage32BitSlice) -> Array2<u8> {
+    map_pixels(img, |pixel| unit_to_u8(color::rgb_to_hsv(pixel.to_rgb()).1))
+}
+
+/// HSV value, i.e. the brightest of each pixel's RGB channels
+pub fn value_layer_extractor(img: &RgbaImage32BitSlice) -> Array2<u8> {
+    map_pixels(img, |pixel| unit_to_u8(color::rgb_to_hsv(pixel.to_rgb()).2))
+}
+
+/// Pixel opacity, where fully transparent pixels are 0
+pub fn alpha_layer_extractor(img: &RgbaImage32BitSlice) -> Array2<u8> {
+    map_pixels(img, |pixel| pixel.data[3])
+}
+
+/// Wrap `extractor` so its output is scaled by each pixel's opacity,
+/// silencing fully transparent regions.
+pub fn alpha_masked_layer_extractor(extractor: LayerExtractorFn) -> LayerExtractorFn {
+    Box::new(move |img: &RgbaImage32BitSlice| {
+        let mut layer = extractor(img);
+        let alpha = alpha_layer_extractor(img);
+        for (val, opacity) in layer.iter_mut().zip(alpha.iter()) {
+            *val = ((*val as u16 * *opacity as u16) / (u8::max_value() as u16)) as u8;
+        }
+        layer
+    })
+}
+
+/// Build a layer extractor measuring how close each pixel is to the color `key`
+///
+/// Intensity is `1 - color_distance(pixel, key) / tolerance`, floored at 0,
+/// so a `tolerance` of 1 accepts every color in proportion to its closeness
+/// while smaller values only pass colors near `key`.
+pub fn color_key_layer_extractor(key: Rgb<u8>, tolerance: f32) -> LayerExtractorFn {
+    assert!(tolerance > 0., "Invalid color key tolerance: {}", tolerance);
+    Box::new(move |img: &RgbaImage32BitSlice| {
+        map_pixels(img, |pixel| {
+            let closeness = 1. - (color::color_distance(pixel.to_rgb(), key) / tolerance);
+            unit_to_u8(closeness.max(0.))
+        })
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use image;
     use test_utils::*;
 
+    #[test]
+    fn test_dispatch_channels_per_band() {
+        let img = image::ImageBuffer::<Rgba<u8>, Vec<u8>>::new(5, 10);
+        let mut top_extractors = HashMap::<ImgLayerId, LayerExtractorFn>::new();
+        top_extractors.insert(0, Box::new(naive_layer_extractor));
+        let mut bottom_extractors = HashMap::<ImgLayerId, LayerExtractorFn>::new();
+        bottom_extractors.insert(1, Box::new(naive_layer_extractor));
+        bottom_extractors.insert(2, Box::new(alpha_layer_extractor));
+        let channel_specs = vec![
+            ChannelSpec { y_start: 0, y_end: 3, layer_extractors: top_extractors },
+            ChannelSpec { y_start: 3, y_end: 10, layer_extractors: bottom_extractors },
+        ];
+
+        let (mut dispatcher, exporters) = StaticImgDispatcher::new(img, 2, channel_specs);
+        dispatcher.begin_dispatch();
+        drop(dispatcher);
+
+        assert_eq!(exporters.len(), 2);
+        let top = &exporters[0];
+        assert_eq!(top.layers_metadata.len(), 1);
+        assert_eq!(top.layers_metadata[0].img_layer_id, 0);
+        assert_eq!(top.layers_metadata[0].y_start, 0);
+        assert_eq!(top.layers_metadata[0].y_end, 3);
+        let bottom = &exporters[1];
+        assert_eq!(bottom.layers_metadata.len(), 2);
+        for layer_metadata in &bottom.layers_metadata {
+            assert_eq!(layer_metadata.y_start, 3);
+            assert_eq!(layer_metadata.y_end, 10);
+            assert_eq!(layer_metadata.total_img_height, 10);
+        }
+
+        // 5 pixel columns in chunks of 2 gives 3 packets per channel
+        assert_eq!(top.receiver.iter().count(), 3);
+        for packet in bottom.receiver.iter() {
+            assert!(packet.contains_key(&1));
+            assert!(packet.contains_key(&2));
+        }
+    }
+
     #[test]
     fn test_naive_layer_extractor() {
         // also test some assumptions about image -> ndarray mappings
@@ -156,19 +294,19 @@ mod tests {
         let width = 3;
         let height = 2;
 
-        let mut buffer = image::ImageBuffer::<Rgb<u8>, Vec<u8>>::new(width, height);
-        buffer.put_pixel(0, 0, image::Rgb([0, 0, 0]));
-        buffer.put_pixel(1, 0, image::Rgb([1, 1, 1]));
-        buffer.put_pixel(2, 0, image::Rgb([2, 2, 2]));
-        buffer.put_pixel(0, 1, image::Rgb([3, 3, 3]));
-        buffer.put_pixel(1, 1, image::Rgb([4, 4, 4]));
-        buffer.put_pixel(2, 1, image::Rgb([5, 5, 5]));
+        let mut buffer = image::ImageBuffer::<Rgba<u8>, Vec<u8>>::new(width, height);
+        buffer.put_pixel(0, 0, image::Rgba([0, 0, 0, 255]));
+        buffer.put_pixel(1, 0, image::Rgba([1, 1, 1, 255]));
+        buffer.put_pixel(2, 0, image::Rgba([2, 2, 2, 255]));
+        buffer.put_pixel(0, 1, image::Rgba([3, 3, 3, 255]));
+        buffer.put_pixel(1, 1, image::Rgba([4, 4, 4, 255]));
+        buffer.put_pixel(2, 1, image::Rgba([5, 5, 5, 255]));
 
         let full_size_slice = buffer.sub_image(0, 0, width, height);
 
         let extracted_layer = naive_layer_extractor(&full_size_slice);
 
-        #[rustfmt_skip]
+        #[rustfmt::skip]
         let expected_layer_array = array![
             [0, 3],
             [1, 4],
@@ -186,4 +324,104 @@ mod tests {
         assert_eq!(extracted_layer.get((1, 1)).unwrap(), &4u8);
         assert_eq!(extracted_layer.get((2, 1)).unwrap(), &5u8);
     }
+
+    fn two_pixel_slice_test_buffer() -> RgbaImage32Bit {
+        let mut buffer = image::ImageBuffer::<Rgba<u8>, Vec<u8>>::new(2, 1);
+        buffer.put_pixel(0, 0, image::Rgba([200, 100, 0, 255]));
+        buffer.put_pixel(1, 0, image::Rgba([10, 20, 40, 255]));
+        buffer
+    }
+
+    #[test]
+    fn test_rgb_channel_layer_extractors() {
+        let mut buffer = two_pixel_slice_test_buffer();
+        let slice = buffer.sub_image(0, 0, 2, 1);
+
+        let red = red_layer_extractor(&slice);
+        let green = green_layer_extractor(&slice);
+        let blue = blue_layer_extractor(&slice);
+
+        assert_img_data_eq_by_element(red.view(), array![[200], [10]].view());
+        assert_img_data_eq_by_element(green.view(), array![[100], [20]].view());
+        assert_img_data_eq_by_element(blue.view(), array![[0], [40]].view());
+    }
+
+    #[test]
+    fn test_hsv_layer_extractors() {
+        let mut buffer = two_pixel_slice_test_buffer();
+        let slice = buffer.sub_image(0, 0, 2, 1);
+
+        let hue = hue_layer_extractor(&slice);
+        let saturation = saturation_layer_extractor(&slice);
+        let value = value_layer_extractor(&slice);
+
+        // (200, 100, 0) has a hue of 30 degrees, (10, 20, 40) 220 degrees
+        assert_img_data_eq_by_element(hue.view(), array![[21], [156]].view());
+        assert_img_data_eq_by_element(saturation.view(), array![[255], [191]].view());
+        assert_img_data_eq_by_element(value.view(), array![[200], [40]].view());
+    }
+
+    #[test]
+    fn test_alpha_layer_extractor() {
+        let mut buffer = image::ImageBuffer::<Rgba<u8>, Vec<u8>>::new(2, 1);
+        buffer.put_pixel(0, 0, image::Rgba([255, 255, 255, 0]));
+        buffer.put_pixel(1, 0, image::Rgba([0, 0, 0, 128]));
+        let slice = buffer.sub_image(0, 0, 2, 1);
+
+        let alpha = alpha_layer_extractor(&slice);
+
+        assert_img_data_eq_by_element(alpha.view(), array![[0], [128]].view());
+    }
+
+    #[test]
+    fn test_alpha_masked_layer_extractor() {
+        let mut buffer = image::ImageBuffer::<Rgba<u8>, Vec<u8>>::new(3, 1);
+        buffer.put_pixel(0, 0, image::Rgba([255, 255, 255, 0]));
+        buffer.put_pixel(1, 0, image::Rgba([255, 255, 255, 255]));
+        buffer.put_pixel(2, 0, image::Rgba([200, 200, 200, 128]));
+        let slice = buffer.sub_image(0, 0, 3, 1);
+
+        let extractor = alpha_masked_layer_extractor(Box::new(red_layer_extractor));
+        let masked = extractor(&slice);
+
+        assert_img_data_eq_by_element(masked.view(), array![[0], [255], [100]].view());
+    }
+
+    #[test]
+    fn test_color_key_layer_extractor() {
+        let mut buffer = image::ImageBuffer::<Rgba<u8>, Vec<u8>>::new(3, 2);
+        buffer.put_pixel(0, 0, image::Rgba([255, 0, 0, 255]));
+        buffer.put_pixel(1, 0, image::Rgba([0, 0, 255, 255]));
+        buffer.put_pixel(2, 0, image::Rgba([250, 5, 5, 255]));
+        buffer.put_pixel(0, 1, image::Rgba([0, 0, 0, 255]));
+        buffer.put_pixel(1, 1, image::Rgba([255, 255, 255, 255]));
+        buffer.put_pixel(2, 1, image::Rgba([255, 0, 0, 255]));
+        let slice = buffer.sub_image(0, 0, 3, 2);
+
+        let extractor = color_key_layer_extractor(image::Rgb([255, 0, 0]), 0.5);
+        let extracted_layer = extractor(&slice);
+
+        assert_eq!(extracted_layer.len_of(Axis(0)), 3);
+        assert_eq!(extracted_layer.len_of(Axis(1)), 2);
+        assert_eq!(extracted_layer[[0, 0]], 255);
+        assert_eq!(extracted_layer[[1, 0]], 0);
+        assert!(extracted_layer[[2, 0]] > 200);
+        assert_eq!(extracted_layer[[0, 1]], 0);
+        assert_eq!(extracted_layer[[1, 1]], 0);
+        assert_eq!(extracted_layer[[2, 1]], 255);
+    }
+
+    #[test]
+    fn test_color_key_layer_extractor_full_tolerance() {
+        let mut buffer = image::ImageBuffer::<Rgba<u8>, Vec<u8>>::new(2, 1);
+        buffer.put_pixel(0, 0, image::Rgba([0, 0, 0, 255]));
+        buffer.put_pixel(1, 0, image::Rgba([255, 255, 255, 255]));
+        let slice = buffer.sub_image(0, 0, 2, 1);
+
+        let extractor = color_key_layer_extractor(image::Rgb([0, 0, 0]), 1.);
+        let extracted_layer = extractor(&slice);
+
+        assert_eq!(extracted_layer[[0, 0]], 255);
+        assert_eq!(extracted_layer[[1, 0]], 0);
+    }
 }
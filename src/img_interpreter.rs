@@ -1,14 +1,31 @@
-#[macro_use(array)]
 use std::collections::HashMap;
 use std::sync::mpsc::{Receiver, Sender};
 
 use ndarray::prelude::*;
-use stopwatch::Stopwatch;
 
-use img_dispatcher::{ImgLayerId, ImgLayerMetadata, ImgPacket};
+use amplitude::{AmplitudeStrategy, Mean, Transfer};
+use arrays;
+use envelope::Envelope;
+use filter::{Biquad, FilterKind};
+use img_dispatcher::{ImgLayerId, ImgPacket};
+use instrument::Instrument;
+use lfo::Lfo;
 use mixer;
 use synth::Oscillator;
 
+/// Turns one layer's image data into samples, one chunk at a time
+pub trait LayerInterpreter: Send {
+    /// Interpret the layer's `img_data` for a chunk into `num_samples` samples.
+    /// `img_packet` holds every layer of the chunk, including data-only layers.
+    fn interpret(
+        &mut self,
+        num_samples: usize,
+        img_data: &Array2<u8>,
+        img_packet: &ImgPacket,
+    ) -> Vec<f32>;
+}
+
+/// Voices a fixed horizontal section of a layer with a fixed-pitch oscillator
 pub struct SectionInterpreter {
     pub oscillator: Oscillator,
     // Coordinates are relative to the complete image's space,
@@ -16,23 +33,122 @@ pub struct SectionInterpreter {
     // within the bounds of the image section.
     pub y_start: usize,
     pub y_end: usize,
+    // How many pixel columns are reduced into each amplitude breakpoint
+    columns_per_breakpoint: usize,
+    amplitude_strategy: Box<dyn AmplitudeStrategy>,
+    transfer: Transfer,
     last_amplitude: f32,
+    // Layer of the same packet whose mean value sets the oscillator's timbre
+    timbre_layer: Option<ImgLayerId>,
+    envelope: Option<Envelope>,
+    onset_threshold: f32,
+    // Amplitude of the breakpoint which last gated the envelope on
+    velocity: f32,
+    vibrato: Option<Modulation>,
+    tremolo: Option<Modulation>,
+    filter: Option<AutomatedFilter>,
+}
+
+/// Split `img_data` into groups of `columns_per_breakpoint` columns,
+/// returning `(sample_count, reduce(columns))` envelope segments for each
+fn column_segments<F>(
+    num_samples: usize,
+    img_data: &ArrayView2<u8>,
+    columns_per_breakpoint: usize,
+    reduce: F,
+) -> Vec<(usize, f32)>
+where
+    F: Fn(&ArrayView2<u8>) -> f32,
+{
+    let columns = img_data.len_of(Axis(0));
+    if columns == 0 {
+        return vec![];
+    }
+    let samples_per_column = num_samples / columns;
+    let mut segments = Vec::<(usize, f32)>::new();
+    let mut column_start = 0;
+    while column_start < columns {
+        let column_end = (column_start + columns_per_breakpoint).min(columns);
+        let columns_slice = img_data.slice(s![column_start..column_end, ..]);
+        segments.push((
+            (column_end - column_start) * samples_per_column,
+            reduce(&columns_slice),
+        ));
+        column_start = column_end;
+    }
+    segments
+}
+
+/// The mean value of rows `y_start..y_end` of `layer_data` for each sample,
+/// interpolated between breakpoints from `last_mean`, which is updated to the
+/// last breakpoint's mean
+fn region_means(
+    num_samples: usize,
+    layer_data: &Array2<u8>,
+    y_start: usize,
+    y_end: usize,
+    columns_per_breakpoint: usize,
+    last_mean: &mut f32,
+) -> Vec<f32> {
+    let slice = layer_data.slice(s![.., y_start..y_end]);
+    let segments = column_segments(num_samples, &slice, columns_per_breakpoint, |columns| {
+        Mean.amplitude(columns)
+    });
+    let mut means = vec![1.; num_samples];
+    arrays::multiply_over_segments(&mut means, *last_mean, &segments);
+    if let Some(&(_, end_mean)) = segments.last() {
+        *last_mean = end_mean;
+    }
+    means
+}
+
+/// Slow periodic modulation of a voice by an `Lfo`, such as vibrato or tremolo
+///
+/// Its rate, in cycles per second, and depth may each be scaled by the
+/// mean value of the voice's section of another layer in the same packet.
+pub struct Modulation {
+    lfo: Lfo,
+    rate: f32,
+    depth: f32,
+    rate_layer: Option<ImgLayerId>,
+    depth_layer: Option<ImgLayerId>,
+    // Layer means reached at the end of the last chunk
+    last_rate_scale: f32,
+    last_depth_scale: f32,
+}
+
+/// A resonant `Biquad` filtering a section, a layer or the mixed output
+///
+/// Its cutoff may follow the mean value of the filtered region of another
+/// layer in the same packet, moving evenly in pitch from a dark cutoff where
+/// that layer is black to `cutoff` where it is white. Its Q may be scaled by
+/// the mean value of another.
+pub struct AutomatedFilter {
+    biquad: Biquad,
+    cutoff: f32,
+    q: f32,
+    cutoff_layer: Option<ImgLayerId>,
+    dark_cutoff: f32,
+    q_layer: Option<ImgLayerId>,
+    // Layer means reached at the end of the last chunk
+    last_cutoff_scale: f32,
+    last_q_scale: f32,
+}
+
+/// Filters the mixed output of every interpreter of a layer
+pub struct FilteredInterpreter {
+    interpreters: Vec<Box<dyn LayerInterpreter>>,
+    filter: AutomatedFilter,
+    // The rows read by the filter's layers, relative to the complete image
+    y_start: usize,
+    y_end: usize,
 }
 
 pub struct ImgInterpreter {
     img_packet_receiver: Receiver<ImgPacket>,
     samples_sender: Sender<Vec<f32>>,
     samples_per_pixel: usize,
-    layer_handlers: HashMap<ImgLayerId, Vec<SectionInterpreter>>,
-}
-
-#[inline]
-fn amplitude_from_img_data(img_data: &ArrayView2<u8>) -> f32 {
-    let mut sum = 0.0;
-    for val in img_data {
-        sum += *val as f32;
-    }
-    (sum / img_data.len() as f32) / (u8::max_value() as f32)
+    layer_handlers: HashMap<ImgLayerId, Vec<Box<dyn LayerInterpreter>>>,
 }
 
 impl SectionInterpreter {
@@ -41,38 +157,702 @@ impl SectionInterpreter {
             oscillator,
             y_start,
             y_end,
+            columns_per_breakpoint: 1,
+            amplitude_strategy: Box::new(Mean),
+            transfer: Transfer::default(),
             last_amplitude: 0.,
+            timbre_layer: None,
+            envelope: None,
+            onset_threshold: 0.5,
+            velocity: 0.,
+            vibrato: None,
+            tremolo: None,
+            filter: None,
         }
     }
 
-    fn horizontally_slice_img_data<'a>(
-        img_data: &'a Array2<u8>,
+    /// Set how many pixel columns are reduced into each amplitude breakpoint.
+    /// Amplitude is interpolated linearly between breakpoints.
+    pub fn with_columns_per_breakpoint(mut self, columns_per_breakpoint: usize) -> Self {
+        assert!(columns_per_breakpoint > 0, "columns_per_breakpoint must be positive");
+        self.columns_per_breakpoint = columns_per_breakpoint;
+        self
+    }
+
+    /// Set how image data is reduced to amplitudes. Defaults to `Mean`.
+    pub fn with_amplitude_strategy(
+        mut self,
+        amplitude_strategy: Box<dyn AmplitudeStrategy>,
+    ) -> Self {
+        self.amplitude_strategy = amplitude_strategy;
+        self
+    }
+
+    /// Set the curve mapping reduced brightness to gain. Defaults to linear.
+    pub fn with_transfer(mut self, transfer: Transfer) -> Self {
+        self.transfer = transfer;
+        self
+    }
+
+    /// Drive the oscillator's timbre from the mean value of this section of
+    /// another layer in the same packet. See `Oscillator::get_samples_with_timbre_envelope`.
+    pub fn with_timbre_layer(mut self, timbre_layer: ImgLayerId) -> Self {
+        self.timbre_layer = Some(timbre_layer);
+        self
+    }
+
+    /// Shape each note with `envelope` instead of ramping between breakpoints.
+    /// A note starts when a breakpoint's amplitude reaches `onset_threshold`
+    /// and is released when one falls below it, and is scaled by the
+    /// amplitude it started with.
+    pub fn with_envelope(mut self, envelope: Envelope, onset_threshold: f32) -> Self {
+        assert!(onset_threshold > 0., "onset_threshold must be positive");
+        self.envelope = Some(envelope);
+        self.onset_threshold = onset_threshold;
+        self
+    }
+
+    /// Modulate the oscillator's frequency by `vibrato`, whose depth is in semitones
+    pub fn with_vibrato(mut self, vibrato: Modulation) -> Self {
+        self.vibrato = Some(vibrato);
+        self
+    }
+
+    /// Modulate amplitude by `tremolo`, whose depth is the fraction of
+    /// amplitude removed at its troughs, between 0 and 1
+    pub fn with_tremolo(mut self, tremolo: Modulation) -> Self {
+        self.tremolo = Some(tremolo);
+        self
+    }
+
+    /// Pass the section's samples through `filter`, whose layers are read
+    /// over the section
+    pub fn with_filter(mut self, filter: AutomatedFilter) -> Self {
+        self.filter = Some(filter);
+        self
+    }
+
+    fn horizontally_slice_img_data(
+        img_data: &Array2<u8>,
         y_start: usize,
         y_end: usize,
-    ) -> ArrayView2<u8> {
+    ) -> ArrayView2<'_, u8> {
         img_data.slice(s![.., y_start..y_end])
     }
 
-    fn interpret(&mut self, num_samples: usize, img_data: &Array2<u8>) -> Vec<f32> {
+    fn segments<F>(
+        &self,
+        num_samples: usize,
+        img_data: &ArrayView2<u8>,
+        reduce: F,
+    ) -> Vec<(usize, f32)>
+    where
+        F: Fn(&ArrayView2<u8>) -> f32,
+    {
+        column_segments(num_samples, img_data, self.columns_per_breakpoint, reduce)
+    }
+
+    fn amplitude_segments(
+        &self,
+        num_samples: usize,
+        img_data: &ArrayView2<u8>,
+    ) -> Vec<(usize, f32)> {
+        self.segments(num_samples, img_data, |columns| {
+            self.transfer.apply(self.amplitude_strategy.amplitude(columns))
+        })
+    }
+
+    fn mean_segments(&self, num_samples: usize, img_data: &ArrayView2<u8>) -> Vec<(usize, f32)> {
+        self.segments(num_samples, img_data, |columns| Mean.amplitude(columns))
+    }
+
+    /// The mean value of this section of `layer` for each sample, interpolated
+    /// between breakpoints from `last_scale`, or all 1 without a layer
+    fn layer_scales(
+        &self,
+        num_samples: usize,
+        layer: Option<ImgLayerId>,
+        last_scale: &mut f32,
+        img_packet: &ImgPacket,
+    ) -> Vec<f32> {
+        match layer {
+            Some(layer) => region_means(
+                num_samples,
+                &img_packet[&layer],
+                self.y_start,
+                self.y_end,
+                self.columns_per_breakpoint,
+                last_scale,
+            ),
+            None => vec![1.; num_samples],
+        }
+    }
+
+    /// `(value, depth)` of `modulation` for each sample, with values between -1 and 1
+    fn modulation_values(
+        &self,
+        modulation: &mut Modulation,
+        num_samples: usize,
+        img_packet: &ImgPacket,
+    ) -> Vec<(f32, f32)> {
+        let rate_scales = self.layer_scales(
+            num_samples,
+            modulation.rate_layer,
+            &mut modulation.last_rate_scale,
+            img_packet,
+        );
+        let depth_scales = self.layer_scales(
+            num_samples,
+            modulation.depth_layer,
+            &mut modulation.last_depth_scale,
+            img_packet,
+        );
+        let rates: Vec<f32> = rate_scales.iter().map(|scale| scale * modulation.rate).collect();
+        modulation
+            .lfo
+            .values(&rates)
+            .into_iter()
+            .zip(depth_scales.iter().map(|scale| scale * modulation.depth))
+            .collect()
+    }
+}
+
+impl Modulation {
+    pub fn new(lfo: Lfo, rate: f32, depth: f32) -> Modulation {
+        Modulation {
+            lfo,
+            rate,
+            depth,
+            rate_layer: None,
+            depth_layer: None,
+            last_rate_scale: 0.,
+            last_depth_scale: 0.,
+        }
+    }
+
+    /// Scale the rate by the mean value of the voice's section of `rate_layer`
+    pub fn with_rate_layer(mut self, rate_layer: ImgLayerId) -> Self {
+        self.rate_layer = Some(rate_layer);
+        self
+    }
+
+    /// Scale the depth by the mean value of the voice's section of `depth_layer`
+    pub fn with_depth_layer(mut self, depth_layer: ImgLayerId) -> Self {
+        self.depth_layer = Some(depth_layer);
+        self
+    }
+}
+
+impl AutomatedFilter {
+    pub fn new(kind: FilterKind, cutoff: f32, q: f32, sample_rate: u32) -> AutomatedFilter {
+        assert!(cutoff > 0., "Invalid cutoff: {}", cutoff);
+        assert!(q > 0., "Invalid Q: {}", q);
+        AutomatedFilter {
+            biquad: Biquad::new(kind, cutoff, q, sample_rate),
+            cutoff,
+            q,
+            cutoff_layer: None,
+            dark_cutoff: cutoff,
+            q_layer: None,
+            last_cutoff_scale: 0.,
+            last_q_scale: 0.,
+        }
+    }
+
+    /// Move the cutoff from `dark_cutoff` to `cutoff` as `cutoff_layer` brightens
+    pub fn with_cutoff_layer(mut self, cutoff_layer: ImgLayerId, dark_cutoff: f32) -> Self {
+        assert!(dark_cutoff > 0., "Invalid dark cutoff: {}", dark_cutoff);
+        self.cutoff_layer = Some(cutoff_layer);
+        self.dark_cutoff = dark_cutoff;
+        self
+    }
+
+    /// Scale Q by the mean value of `q_layer`
+    pub fn with_q_layer(mut self, q_layer: ImgLayerId) -> Self {
+        self.q_layer = Some(q_layer);
+        self
+    }
+
+    /// Filter `samples` in place, reading rows `y_start..y_end` of its layers
+    pub fn apply(
+        &mut self,
+        samples: &mut [f32],
+        y_start: usize,
+        y_end: usize,
+        img_packet: &ImgPacket,
+    ) {
+        if self.cutoff_layer.is_none() && self.q_layer.is_none() {
+            self.biquad.process(samples);
+            return;
+        }
+        let num_samples = samples.len();
+        let means = |layer: Option<ImgLayerId>, last_mean: &mut f32| match layer {
+            Some(layer) => {
+                region_means(num_samples, &img_packet[&layer], y_start, y_end, 1, last_mean)
+            }
+            None => vec![1.; num_samples],
+        };
+        let cutoff_scales = means(self.cutoff_layer, &mut self.last_cutoff_scale);
+        let q_scales = means(self.q_layer, &mut self.last_q_scale);
+        let cutoff_ratio = self.cutoff / self.dark_cutoff;
+        let cutoffs: Vec<f32> = cutoff_scales
+            .iter()
+            .map(|scale| self.dark_cutoff * cutoff_ratio.powf(*scale))
+            .collect();
+        let qs: Vec<f32> = q_scales.iter().map(|scale| scale * self.q).collect();
+        self.biquad.process_swept(samples, &cutoffs, &qs);
+    }
+}
+
+impl FilteredInterpreter {
+    pub fn new(
+        interpreters: Vec<Box<dyn LayerInterpreter>>,
+        filter: AutomatedFilter,
+        y_start: usize,
+        y_end: usize,
+    ) -> FilteredInterpreter {
+        FilteredInterpreter {
+            interpreters,
+            filter,
+            y_start,
+            y_end,
+        }
+    }
+}
+
+impl LayerInterpreter for FilteredInterpreter {
+    fn interpret(
+        &mut self,
+        num_samples: usize,
+        img_data: &Array2<u8>,
+        img_packet: &ImgPacket,
+    ) -> Vec<f32> {
+        let mut mixed_samples = vec![0.; num_samples];
+        for interpreter in self.interpreters.iter_mut() {
+            let samples = interpreter.interpret(num_samples, img_data, img_packet);
+            mixer::add_chunk_to(&samples, &mut mixed_samples);
+        }
+        self.filter.apply(&mut mixed_samples, self.y_start, self.y_end, img_packet);
+        mixed_samples
+    }
+}
+
+impl LayerInterpreter for SectionInterpreter {
+    fn interpret(
+        &mut self,
+        num_samples: usize,
+        img_data: &Array2<u8>,
+        img_packet: &ImgPacket,
+    ) -> Vec<f32> {
         let slice = Self::horizontally_slice_img_data(img_data, self.y_start, self.y_end);
-        let end_amplitude = amplitude_from_img_data(&slice);
+        let segments = self.amplitude_segments(num_samples, &slice);
 
-        let samples = self.oscillator.get_samples_with_interpolated_amp(
+        let timbre_segments = self.timbre_layer.map(|timbre_layer| {
+            let timbre_data = &img_packet[&timbre_layer];
+            let timbre_slice =
+                Self::horizontally_slice_img_data(timbre_data, self.y_start, self.y_end);
+            self.mean_segments(num_samples, &timbre_slice)
+        });
+        let frequency_ratios = self.vibrato.take().map(|mut vibrato| {
+            let values = self.modulation_values(&mut vibrato, num_samples, img_packet);
+            self.vibrato = Some(vibrato);
+            values
+                .iter()
+                .map(|&(value, semitones)| 2f32.powf(value * semitones / 12.))
+                .collect::<Vec<f32>>()
+        });
+        let mut samples = self.oscillator.get_samples_with_modulation(
             num_samples,
-            self.last_amplitude,
-            end_amplitude,
+            timbre_segments.as_ref().map(Vec::as_slice),
+            frequency_ratios.as_ref().map(Vec::as_slice),
         );
-        self.last_amplitude = end_amplitude;
+        match self.envelope {
+            Some(ref mut envelope) => {
+                let mut segment_start = 0;
+                for &(sample_count, amplitude) in &segments {
+                    if amplitude >= self.onset_threshold {
+                        if !envelope.is_open() {
+                            envelope.gate_on();
+                            self.velocity = amplitude;
+                        }
+                    } else {
+                        envelope.gate_off();
+                    }
+                    for sample in &mut samples[segment_start..segment_start + sample_count] {
+                        *sample *= envelope.next_level() * self.velocity;
+                    }
+                    segment_start += sample_count;
+                }
+            }
+            None => arrays::multiply_over_segments(&mut samples, self.last_amplitude, &segments),
+        }
+        if let Some(mut tremolo) = self.tremolo.take() {
+            let values = self.modulation_values(&mut tremolo, num_samples, img_packet);
+            for (sample, (value, depth)) in samples.iter_mut().zip(values) {
+                *sample *= 1. - (depth * (1. - value) / 2.);
+            }
+            self.tremolo = Some(tremolo);
+        }
+        if let Some(ref mut filter) = self.filter {
+            filter.apply(&mut samples, self.y_start, self.y_end, img_packet);
+        }
+        if let Some(&(_, end_amplitude)) = segments.last() {
+            self.last_amplitude = end_amplitude;
+        }
         samples
     }
 }
 
+/// Plays notes on an `Instrument` from a fixed horizontal section of a layer
+///
+/// A note starts, with the breakpoint's amplitude as its velocity, whenever
+/// the section's amplitude rises to `onset_threshold`, and is released when
+/// it falls back below.
+pub struct TriggeredInterpreter {
+    instrument: Box<dyn Instrument>,
+    // Coordinates are relative to the complete image's space,
+    // like `SectionInterpreter`'s
+    y_start: usize,
+    y_end: usize,
+    columns_per_breakpoint: usize,
+    amplitude_strategy: Box<dyn AmplitudeStrategy>,
+    transfer: Transfer,
+    onset_threshold: f32,
+    note_is_on: bool,
+    filter: Option<AutomatedFilter>,
+}
+
+impl TriggeredInterpreter {
+    pub fn new(
+        instrument: Box<dyn Instrument>,
+        y_start: usize,
+        y_end: usize,
+        onset_threshold: f32,
+    ) -> TriggeredInterpreter {
+        assert!(onset_threshold > 0., "onset_threshold must be positive");
+        TriggeredInterpreter {
+            instrument,
+            y_start,
+            y_end,
+            columns_per_breakpoint: 1,
+            amplitude_strategy: Box::new(Mean),
+            transfer: Transfer::default(),
+            onset_threshold,
+            note_is_on: false,
+            filter: None,
+        }
+    }
+
+    /// Set how many pixel columns are reduced into each amplitude breakpoint,
+    /// and so how often notes can start
+    pub fn with_columns_per_breakpoint(mut self, columns_per_breakpoint: usize) -> Self {
+        assert!(columns_per_breakpoint > 0, "columns_per_breakpoint must be positive");
+        self.columns_per_breakpoint = columns_per_breakpoint;
+        self
+    }
+
+    /// Set how image data is reduced to amplitudes. Defaults to `Mean`.
+    pub fn with_amplitude_strategy(
+        mut self,
+        amplitude_strategy: Box<dyn AmplitudeStrategy>,
+    ) -> Self {
+        self.amplitude_strategy = amplitude_strategy;
+        self
+    }
+
+    /// Set the curve mapping reduced brightness to gain. Defaults to linear.
+    pub fn with_transfer(mut self, transfer: Transfer) -> Self {
+        self.transfer = transfer;
+        self
+    }
+
+    /// Pass the instrument's samples through `filter`, whose layers are read
+    /// over the section
+    pub fn with_filter(mut self, filter: AutomatedFilter) -> Self {
+        self.filter = Some(filter);
+        self
+    }
+}
+
+impl LayerInterpreter for TriggeredInterpreter {
+    fn interpret(
+        &mut self,
+        num_samples: usize,
+        img_data: &Array2<u8>,
+        img_packet: &ImgPacket,
+    ) -> Vec<f32> {
+        let slice = img_data.slice(s![.., self.y_start..self.y_end]);
+        let segments = {
+            let amplitude_strategy = &self.amplitude_strategy;
+            let transfer = &self.transfer;
+            column_segments(num_samples, &slice, self.columns_per_breakpoint, |columns| {
+                transfer.apply(amplitude_strategy.amplitude(columns))
+            })
+        };
+        let mut samples = Vec::<f32>::with_capacity(num_samples);
+        for (sample_count, amplitude) in segments {
+            if amplitude >= self.onset_threshold {
+                if !self.note_is_on {
+                    self.instrument.note_on(amplitude);
+                    self.note_is_on = true;
+                }
+            } else if self.note_is_on {
+                self.instrument.note_off();
+                self.note_is_on = false;
+            }
+            samples.append(&mut self.instrument.get_samples(sample_count));
+        }
+        if let Some(ref mut filter) = self.filter {
+            filter.apply(&mut samples, self.y_start, self.y_end, img_packet);
+        }
+        samples
+    }
+}
+
+/// Follows bright lines up and down a region of a layer, voicing each
+/// with a single oscillator which glides to the line's height in every column
+///
+/// With one oscillator, the brightness-weighted centroid of each column is
+/// followed. With more, the brightest runs of lit pixels in each column are
+/// followed, each by the oscillator whose line was nearest it in the previous
+/// breakpoint. Oscillators fade in over the breakpoint where a line starts,
+/// and out over the one where it ends.
+/// A line's amplitude is the transfer of its brightest pixel.
+pub struct PitchTrackingInterpreter {
+    voices: Vec<TrackedVoice>,
+    // Coordinates are relative to the complete image's space,
+    // like `SectionInterpreter`'s
+    y_start: usize,
+    y_end: usize,
+    // Frequencies at the bottom and top of the region, mapped geometrically between
+    low_frequency: f32,
+    high_frequency: f32,
+    // How many pixel columns are averaged into each breakpoint
+    columns_per_breakpoint: usize,
+    transfer: Transfer,
+}
+
+struct TrackedVoice {
+    oscillator: Oscillator,
+    last_amplitude: f32,
+    // Row of the line followed at the last breakpoint, if there was one
+    last_row: Option<f32>,
+}
+
+impl PitchTrackingInterpreter {
+    /// Follow as many lines as there are `oscillators`, which are retuned as they glide
+    pub fn new(
+        oscillators: Vec<Oscillator>,
+        y_start: usize,
+        y_end: usize,
+        low_frequency: f32,
+        high_frequency: f32,
+    ) -> PitchTrackingInterpreter {
+        assert!(!oscillators.is_empty(), "At least one oscillator is required");
+        assert!(
+            0. < low_frequency && 0. < high_frequency,
+            "Invalid frequency range: {} to {}",
+            low_frequency,
+            high_frequency
+        );
+        PitchTrackingInterpreter {
+            voices: oscillators
+                .into_iter()
+                .map(|oscillator| TrackedVoice {
+                    oscillator,
+                    last_amplitude: 0.,
+                    last_row: None,
+                })
+                .collect(),
+            y_start,
+            y_end,
+            low_frequency,
+            high_frequency,
+            columns_per_breakpoint: 1,
+            transfer: Transfer::default(),
+        }
+    }
+
+    /// Set how many pixel columns are averaged into each pitch and amplitude breakpoint
+    pub fn with_columns_per_breakpoint(mut self, columns_per_breakpoint: usize) -> Self {
+        assert!(columns_per_breakpoint > 0, "columns_per_breakpoint must be positive");
+        self.columns_per_breakpoint = columns_per_breakpoint;
+        self
+    }
+
+    /// Set the curve mapping line brightness to gain. Defaults to linear.
+    pub fn with_transfer(mut self, transfer: Transfer) -> Self {
+        self.transfer = transfer;
+        self
+    }
+
+    /// Frequency at `row`, counted in pixels from the top of the region
+    fn frequency_at(&self, row: f32) -> f32 {
+        let fraction = row / (self.y_end - self.y_start) as f32;
+        self.high_frequency * (self.low_frequency / self.high_frequency).powf(fraction)
+    }
+
+    /// Find up to one `(row, brightness)` line per voice in a column's `profile`.
+    /// Brightness is between 0 and 1.
+    fn find_lines(&self, profile: &[f32]) -> Vec<(f32, f32)> {
+        if self.voices.len() == 1 {
+            let total: f32 = profile.iter().sum();
+            if total <= 0. {
+                return vec![];
+            }
+            let weighted: f32 = profile.iter().enumerate().map(|(y, v)| (y as f32 + 0.5) * v).sum();
+            let peak = profile.iter().cloned().fold(0., f32::max);
+            return vec![(weighted / total, peak / 255.)];
+        }
+
+        // (start, end) of each run of lit pixels
+        let mut runs = Vec::<(usize, usize)>::new();
+        for (y, value) in profile.iter().enumerate() {
+            if *value <= 0. {
+                continue;
+            }
+            match runs.last_mut() {
+                Some(run) if run.1 == y => run.1 = y + 1,
+                _ => runs.push((y, y + 1)),
+            }
+        }
+        let mut lines: Vec<(f32, f32)> = runs
+            .into_iter()
+            .map(|(start, end)| {
+                let run = &profile[start..end];
+                let total: f32 = run.iter().sum();
+                let weighted: f32 = run
+                    .iter()
+                    .enumerate()
+                    .map(|(y, v)| ((start + y) as f32 + 0.5) * v)
+                    .sum();
+                let peak = run.iter().cloned().fold(0., f32::max);
+                (weighted / total, peak / 255.)
+            })
+            .collect();
+        lines.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+        lines.truncate(self.voices.len());
+        lines.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        lines
+    }
+
+    /// Give each of `lines` to a voice, from the `rows` each voice followed at the
+    /// last breakpoint. Lines continue on the voices whose rows were nearest,
+    /// nearest pairs first, and any left start on voices which had no line.
+    fn assign_lines(lines: &[(f32, f32)], rows: &[Option<f32>]) -> Vec<Option<(f32, f32)>> {
+        let mut pairs = Vec::<(f32, usize, usize)>::new();
+        for (line, &(line_row, _)) in lines.iter().enumerate() {
+            for (voice, row) in rows.iter().enumerate() {
+                if let Some(row) = *row {
+                    pairs.push(((line_row - row).abs(), line, voice));
+                }
+            }
+        }
+        pairs.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+
+        let mut assigned = vec![None; rows.len()];
+        let mut line_taken = vec![false; lines.len()];
+        for (_, line, voice) in pairs {
+            if !line_taken[line] && assigned[voice].is_none() {
+                assigned[voice] = Some(lines[line]);
+                line_taken[line] = true;
+            }
+        }
+        let silent_voices = (0..rows.len()).filter(|&voice| rows[voice].is_none());
+        let new_lines = lines.iter().zip(line_taken).filter(|&(_, taken)| !taken);
+        for (voice, (&line, _)) in silent_voices.zip(new_lines) {
+            assigned[voice] = Some(line);
+        }
+        assigned
+    }
+}
+
+impl LayerInterpreter for PitchTrackingInterpreter {
+    fn interpret(
+        &mut self,
+        num_samples: usize,
+        img_data: &Array2<u8>,
+        _img_packet: &ImgPacket,
+    ) -> Vec<f32> {
+        let slice = img_data.slice(s![.., self.y_start..self.y_end]);
+        let columns = slice.len_of(Axis(0));
+        if columns == 0 {
+            return vec![0.; num_samples];
+        }
+        let samples_per_column = num_samples / columns;
+
+        // For each voice, `(sample_count, Some((frequency, amplitude)))` per breakpoint,
+        // or `None` where it has no line to follow
+        let mut voice_segments = vec![Vec::<(usize, Option<(f32, f32)>)>::new(); self.voices.len()];
+        let mut rows: Vec<Option<f32>> = self.voices.iter().map(|voice| voice.last_row).collect();
+        let mut column_start = 0;
+        while column_start < columns {
+            let column_end = (column_start + self.columns_per_breakpoint).min(columns);
+            let group = slice.slice(s![column_start..column_end, ..]);
+            let group_len = (column_end - column_start) as f32;
+            let profile: Vec<f32> = group
+                .axis_iter(Axis(1))
+                .map(|row| row.iter().map(|v| *v as f32).sum::<f32>() / group_len)
+                .collect();
+            let lines = Self::assign_lines(&self.find_lines(&profile), &rows);
+            let sample_count = (column_end - column_start) * samples_per_column;
+            for (segments, line) in voice_segments.iter_mut().zip(lines.iter()) {
+                let line = line.map(|(row, brightness)| {
+                    (self.frequency_at(row), self.transfer.apply(brightness))
+                });
+                segments.push((sample_count, line));
+            }
+            rows = lines.iter().map(|line| line.map(|(row, _)| row)).collect();
+            column_start = column_end;
+        }
+        for (voice, row) in self.voices.iter_mut().zip(rows) {
+            voice.last_row = row;
+        }
+
+        let mut mixed_samples = vec![0.; num_samples];
+        for (voice, segments) in self.voices.iter_mut().zip(voice_segments.iter()) {
+            let mut samples = Vec::<f32>::with_capacity(num_samples);
+            let mut amplitude_segments = Vec::<(usize, f32)>::with_capacity(segments.len());
+            let mut previous_amplitude = voice.last_amplitude;
+            for &(sample_count, line) in segments {
+                match line {
+                    Some((frequency, amplitude)) => {
+                        // Jump rather than glide to lines appearing from silence
+                        let start_frequency = if previous_amplitude > 0. {
+                            voice.oscillator.frequency()
+                        } else {
+                            frequency
+                        };
+                        samples.append(&mut voice.oscillator.get_samples_with_interpolated_freq(
+                            sample_count,
+                            start_frequency,
+                            frequency,
+                        ));
+                        amplitude_segments.push((sample_count, amplitude));
+                        previous_amplitude = amplitude;
+                    }
+                    None => {
+                        // Hold pitch while fading out
+                        samples.append(&mut voice.oscillator.get_samples(sample_count, 1.));
+                        amplitude_segments.push((sample_count, 0.));
+                        previous_amplitude = 0.;
+                    }
+                }
+            }
+            arrays::multiply_over_segments(&mut samples, voice.last_amplitude, &amplitude_segments);
+            voice.last_amplitude = previous_amplitude;
+            mixer::add_chunk_to(&samples, &mut mixed_samples);
+        }
+        mixed_samples
+    }
+}
+
 impl ImgInterpreter {
     pub fn new(
         img_packet_receiver: Receiver<ImgPacket>,
         samples_sender: Sender<Vec<f32>>,
         samples_per_pixel: usize,
-        layer_handlers: HashMap<ImgLayerId, Vec<SectionInterpreter>>,
+        layer_handlers: HashMap<ImgLayerId, Vec<Box<dyn LayerInterpreter>>>,
     ) -> ImgInterpreter {
         ImgInterpreter {
         img_packet_receiver,
@@ -89,14 +869,16 @@ impl ImgInterpreter {
             let samples_needed =
                 img_packet.values().nth(0).unwrap().len_of(Axis(0)) * self.samples_per_pixel;
             let mut mixed_samples = vec![0.; samples_needed];
-            for (layer_id, img_data) in img_packet {
-                let mut interpreters_for_layer = self.layer_handlers.get_mut(&layer_id).unwrap();
-                for section_interpreter in interpreters_for_layer.iter_mut() {
-                    let section_samples = section_interpreter.interpret(samples_needed, &img_data);
-                    mixer::add_chunk_to(&section_samples, &mut mixed_samples);
+            // Data-only layers in the packet have no handlers of their own
+            for (layer_id, interpreters_for_layer) in self.layer_handlers.iter_mut() {
+                let img_data = &img_packet[layer_id];
+                for layer_interpreter in interpreters_for_layer.iter_mut() {
+                    let layer_samples =
+                        layer_interpreter.interpret(samples_needed, img_data, &img_packet);
+                    mixer::add_chunk_to(&layer_samples, &mut mixed_samples);
                 }
             }
-            &self.samples_sender.send(mixed_samples);
+            let _ = self.samples_sender.send(mixed_samples);
         }
     }
 }
@@ -104,24 +886,360 @@ impl ImgInterpreter {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use amplitude::Max;
+    use envelope::{Adsr, EnvelopeCurve};
+    use std::sync::{Arc, Mutex};
+    use synth::Waveform;
     use test_utils::*;
 
     #[test]
-    fn amplitude_from_img_data_all_zeros() {
-        let img_data = array![[0, 0], [0, 0]];
-        assert_almost_eq(amplitude_from_img_data(&img_data.view()), 0.);
+    fn amplitude_segments_per_column() {
+        let oscillator = Oscillator::new(Waveform::Sine, 10., 100);
+        let interpreter = SectionInterpreter::new(oscillator, 0, 2);
+        let img_data = array![[0, 0], [255, 255], [0, 255]];
+        let segments = interpreter.amplitude_segments(30, &img_data.view());
+        assert_eq!(segments.len(), 3);
+        assert_eq!(segments[0].0, 10);
+        assert_almost_eq(segments[0].1, 0.);
+        assert_almost_eq(segments[1].1, 1.);
+        assert_almost_eq(segments[2].1, 0.5);
     }
 
     #[test]
-    fn amplitude_from_img_data_all_max() {
-        let img_data = array![[255, 255], [255, 255]];
-        assert_almost_eq(amplitude_from_img_data(&img_data.view()), 1.);
+    fn amplitude_segments_grouped_columns() {
+        let oscillator = Oscillator::new(Waveform::Sine, 10., 100);
+        let interpreter =
+            SectionInterpreter::new(oscillator, 0, 1).with_columns_per_breakpoint(2);
+        let img_data = array![[0], [255], [255]];
+        let segments = interpreter.amplitude_segments(30, &img_data.view());
+        assert_eq!(segments.len(), 2);
+        assert_eq!(segments[0].0, 20);
+        assert_almost_eq(segments[0].1, 0.5);
+        assert_eq!(segments[1].0, 10);
+        assert_almost_eq(segments[1].1, 1.);
     }
 
     #[test]
-    fn amplitude_from_img_data_avg_point_5() {
-        let img_data = array![[0, 127], [128, 255]];
-        assert_almost_eq(amplitude_from_img_data(&img_data.view()), 0.5);
+    fn amplitude_segments_of_empty_chunk() {
+        let oscillator = Oscillator::new(Waveform::Sine, 10., 100);
+        let interpreter = SectionInterpreter::new(oscillator, 0, 1);
+        let img_data = Array2::<u8>::zeros((0, 1));
+        assert!(interpreter.amplitude_segments(0, &img_data.view()).is_empty());
+    }
+
+    #[test]
+    fn amplitude_segments_use_strategy() {
+        let oscillator = Oscillator::new(Waveform::Sine, 10., 100);
+        let interpreter =
+            SectionInterpreter::new(oscillator, 0, 4).with_amplitude_strategy(Box::new(Max));
+        let img_data = array![[0, 0, 255, 0], [0, 0, 0, 0]];
+        let segments = interpreter.amplitude_segments(20, &img_data.view());
+        assert_almost_eq(segments[0].1, 1.);
+        assert_almost_eq(segments[1].1, 0.);
+    }
+
+    #[test]
+    fn amplitude_segments_use_transfer() {
+        let oscillator = Oscillator::new(Waveform::Sine, 10., 100);
+        let transfer = Transfer { inverted: true, gate: 0.6, ..Transfer::default() };
+        let interpreter = SectionInterpreter::new(oscillator, 0, 1).with_transfer(transfer);
+        let img_data = array![[0], [255], [153]];
+        let segments = interpreter.amplitude_segments(30, &img_data.view());
+        assert_almost_eq(segments[0].1, 1.);
+        assert_almost_eq(segments[1].1, 0.);
+        assert_almost_eq(segments[2].1, 0.);
+    }
+
+    #[test]
+    fn mean_segments_ignore_strategy() {
+        let oscillator = Oscillator::new(Waveform::Sine, 10., 100);
+        let interpreter =
+            SectionInterpreter::new(oscillator, 0, 2).with_amplitude_strategy(Box::new(Max));
+        let img_data = array![[0, 255], [255, 255]];
+        let segments = interpreter.mean_segments(20, &img_data.view());
+        assert_almost_eq(segments[0].1, 0.5);
+        assert_almost_eq(segments[1].1, 1.);
+    }
+
+    #[test]
+    fn interpret_reads_timbre_layer() {
+        let morph = Waveform::Morph(vec![Waveform::Sine, Waveform::Square]);
+        let oscillator = Oscillator::new(morph, 441., 44100);
+        let mut interpreter = SectionInterpreter::new(oscillator, 0, 1).with_timbre_layer(1);
+        let mut img_packet = ImgPacket::new();
+        img_packet.insert(0, array![[255], [255]]);
+        img_packet.insert(1, array![[255], [255]]);
+        let samples = interpreter.interpret(200, &img_packet[&0], &img_packet);
+
+        // Both amplitude and timbre reach 1 by the end of the first column
+        let mut square = Oscillator::new(Waveform::Square, 441., 44100);
+        square.get_samples(100, 1.);
+        assert_almost_eq_by_element(samples[100..].to_vec(), square.get_samples(100, 1.));
+    }
+
+    #[test]
+    fn interpret_with_envelope_attacks_on_onset() {
+        let oscillator = Oscillator::new(Waveform::Square, 441., 44100);
+        let adsr = Adsr {
+            attack: 0.,
+            decay: 0.,
+            sustain: 1.,
+            release: 0.,
+            curve: EnvelopeCurve::Linear,
+        };
+        let mut interpreter = SectionInterpreter::new(oscillator, 0, 1)
+            .with_envelope(Envelope::new(adsr, 44100), 0.5);
+        let img_data = array![[0], [204], [255], [51]];
+        let samples = interpreter.interpret(400, &img_data, &ImgPacket::new());
+
+        // Silent until the onset, then straight to the onset's amplitude
+        // instead of ramping, holding it until the release
+        let mut square = Oscillator::new(Waveform::Square, 441., 44100);
+        let expected: Vec<f32> = square
+            .get_samples(400, 1.)
+            .iter()
+            .enumerate()
+            .map(|(i, s)| if i >= 100 && i < 300 { s * 0.8 } else { 0. })
+            .collect();
+        assert_almost_eq_by_element(samples, expected);
+    }
+
+    #[test]
+    fn interpret_with_envelope_releases_into_darkness() {
+        let oscillator = Oscillator::new(Waveform::Square, 441., 44100);
+        let adsr = Adsr {
+            attack: 0.,
+            decay: 0.,
+            sustain: 1.,
+            release: 100. / 44100.,
+            curve: EnvelopeCurve::Linear,
+        };
+        let mut interpreter = SectionInterpreter::new(oscillator, 0, 1)
+            .with_envelope(Envelope::new(adsr, 44100), 0.5);
+        interpreter.interpret(100, &array![[255]], &ImgPacket::new());
+        let samples = interpreter.interpret(200, &array![[0], [0]], &ImgPacket::new());
+        assert!(samples[..100].iter().any(|s| s.abs() > 0.5));
+        assert!(samples[100..].iter().all(|s| *s == 0.));
+    }
+
+    #[test]
+    fn interpret_with_vibrato() {
+        // Held at its peak, a vibrato an octave deep doubles the frequency
+        let vibrato = Modulation::new(Lfo::new(44100).with_phase(0.25), 0., 12.);
+        let oscillator = Oscillator::new(Waveform::Saw, 441., 44100);
+        let mut interpreter = SectionInterpreter::new(oscillator, 0, 1).with_vibrato(vibrato);
+        let img_data = array![[255], [255]];
+        let samples = interpreter.interpret(200, &img_data, &ImgPacket::new());
+
+        let doubled = Oscillator::new(Waveform::Saw, 882., 44100);
+        let mut plain = SectionInterpreter::new(doubled, 0, 1);
+        assert_almost_eq_by_element(samples, plain.interpret(200, &img_data, &ImgPacket::new()));
+        assert_almost_eq(interpreter.oscillator.frequency(), 441.);
+    }
+
+    #[test]
+    fn interpret_with_tremolo_depth_layer() {
+        // Held at its trough, a tremolo half as deep as its depth layer
+        let tremolo =
+            Modulation::new(Lfo::new(44100).with_phase(0.75), 0., 0.5).with_depth_layer(1);
+        let oscillator = Oscillator::new(Waveform::Sine, 441., 44100);
+        let mut interpreter = SectionInterpreter::new(oscillator, 0, 1).with_tremolo(tremolo);
+        let mut img_packet = ImgPacket::new();
+        img_packet.insert(0, array![[255], [255]]);
+        img_packet.insert(1, array![[255], [255]]);
+        let samples = interpreter.interpret(200, &img_packet[&0], &img_packet);
+
+        let mut plain = SectionInterpreter::new(Oscillator::new(Waveform::Sine, 441., 44100), 0, 1);
+        let expected: Vec<f32> = plain
+            .interpret(200, &img_packet[&0], &img_packet)
+            .iter()
+            .map(|s| s * 0.5)
+            .collect();
+        assert_almost_eq_by_element(samples[100..].to_vec(), expected[100..].to_vec());
+    }
+
+    fn peak(samples: &[f32]) -> f32 {
+        samples.iter().fold(0., |peak: f32, s| peak.max(s.abs()))
+    }
+
+    #[test]
+    fn interpret_with_filter_cutoff_layer() {
+        // The cutoff layer brightens halfway through, opening the filter
+        let filter = AutomatedFilter::new(FilterKind::LowPass, 20000., 0.707, 44100)
+            .with_cutoff_layer(1, 50.);
+        let oscillator = Oscillator::new(Waveform::Sine, 5000., 44100);
+        let mut interpreter = SectionInterpreter::new(oscillator, 0, 1).with_filter(filter);
+        let mut img_packet = ImgPacket::new();
+        img_packet.insert(0, Array2::from_elem((20, 1), 255));
+        let cutoff_layer: Vec<u8> = (0..20).map(|i| if i < 10 { 0 } else { 255 }).collect();
+        img_packet.insert(1, Array2::from_shape_vec((20, 1), cutoff_layer).unwrap());
+        let samples = interpreter.interpret(8820, &img_packet[&0], &img_packet);
+        assert!(peak(&samples[2000..4410]) < 0.01);
+        assert!(peak(&samples[6000..]) > 0.95);
+    }
+
+    #[test]
+    fn filtered_interpreter_mixes_then_filters() {
+        let sections = |kind, cutoff| {
+            let interpreters: Vec<Box<dyn LayerInterpreter>> = (0..2)
+                .map(|_| {
+                    let oscillator = Oscillator::new(Waveform::Sine, 100., 44100);
+                    Box::new(SectionInterpreter::new(oscillator, 0, 1)) as Box<dyn LayerInterpreter>
+                })
+                .collect();
+            let filter = AutomatedFilter::new(kind, cutoff, 0.707, 44100);
+            FilteredInterpreter::new(interpreters, filter, 0, 1)
+        };
+        let img_data = Array2::from_elem((10, 1), 255);
+        let passed = sections(FilterKind::LowPass, 20000.)
+            .interpret(8820, &img_data, &ImgPacket::new());
+        assert!(peak(&passed[4410..]) > 1.95);
+        let removed = sections(FilterKind::HighPass, 5000.)
+            .interpret(8820, &img_data, &ImgPacket::new());
+        assert!(peak(&removed[4410..]) < 0.01);
+    }
+
+    /// Records its notes, sounding its velocity while a note is on
+    struct RecordingInstrument {
+        notes: Arc<Mutex<Vec<f32>>>,
+        velocity: Option<f32>,
+    }
+
+    impl Instrument for RecordingInstrument {
+        fn note_on(&mut self, velocity: f32) {
+            self.notes.lock().unwrap().push(velocity);
+            self.velocity = Some(velocity);
+        }
+
+        fn note_off(&mut self) {
+            self.velocity = None;
+        }
+
+        fn get_samples(&mut self, num: usize) -> Vec<f32> {
+            vec![self.velocity.unwrap_or(0.); num]
+        }
+    }
+
+    #[test]
+    fn triggered_interpreter_plays_notes_on_onsets() {
+        let notes = Arc::new(Mutex::new(vec![]));
+        let instrument = RecordingInstrument {
+            notes: notes.clone(),
+            velocity: None,
+        };
+        let mut interpreter = TriggeredInterpreter::new(Box::new(instrument), 0, 1, 0.5);
+        let img_data = array![[0], [204], [255], [51], [153]];
+        let samples = interpreter.interpret(50, &img_data, &ImgPacket::new());
+        assert_almost_eq_by_element(notes.lock().unwrap().clone(), vec![0.8, 0.6]);
+        let expected: Vec<f32> = [0., 0.8, 0.8, 0., 0.6]
+            .iter()
+            .flat_map(|level| vec![*level; 10])
+            .collect();
+        assert_almost_eq_by_element(samples, expected);
+
+        // A note held across chunks is not restarted
+        interpreter.interpret(10, &array![[255]], &ImgPacket::new());
+        assert_eq!(notes.lock().unwrap().len(), 2);
+    }
+
+    fn tracker(lines: usize, height: usize) -> PitchTrackingInterpreter {
+        let oscillators = (0..lines)
+            .map(|_| Oscillator::new(Waveform::Sine, 100., 44100))
+            .collect();
+        PitchTrackingInterpreter::new(oscillators, 0, height, 100., 400.)
+    }
+
+    #[test]
+    fn tracking_frequency_is_geometric() {
+        let interpreter = tracker(1, 4);
+        assert_almost_eq(interpreter.frequency_at(0.), 400.);
+        assert_almost_eq(interpreter.frequency_at(2.), 200.);
+        assert_almost_eq(interpreter.frequency_at(4.), 100.);
+    }
+
+    #[test]
+    fn find_single_line_by_centroid() {
+        let lines = tracker(1, 4).find_lines(&[0., 255., 255., 0.]);
+        assert_eq!(lines.len(), 1);
+        assert_almost_eq(lines[0].0, 2.);
+        assert_almost_eq(lines[0].1, 1.);
+    }
+
+    #[test]
+    fn find_no_lines_in_dark_column() {
+        assert!(tracker(1, 4).find_lines(&[0.; 4]).is_empty());
+        assert!(tracker(2, 4).find_lines(&[0.; 4]).is_empty());
+    }
+
+    #[test]
+    fn find_brightest_lines_from_top() {
+        let lines = tracker(2, 6).find_lines(&[255., 0., 51., 0., 102., 102.]);
+        assert_eq!(lines.len(), 2);
+        assert_almost_eq(lines[0].0, 0.5);
+        assert_almost_eq(lines[0].1, 1.);
+        assert_almost_eq(lines[1].0, 5.);
+        assert_almost_eq(lines[1].1, 0.4);
+    }
+
+    #[test]
+    fn assign_lines_to_nearest_voices() {
+        let lines = [(1., 1.), (6., 0.5)];
+        // Both voices continue the lines nearest them
+        let assigned = PitchTrackingInterpreter::assign_lines(&lines, &[Some(5.), Some(2.)]);
+        assert_eq!(assigned, vec![Some((6., 0.5)), Some((1., 1.))]);
+        // A new line starts on the voice without one
+        let assigned = PitchTrackingInterpreter::assign_lines(&lines, &[None, Some(0.)]);
+        assert_eq!(assigned, vec![Some((6., 0.5)), Some((1., 1.))]);
+        // A voice whose line ended goes silent rather than taking over another
+        let assigned = PitchTrackingInterpreter::assign_lines(&lines[..1], &[Some(5.), Some(2.)]);
+        assert_eq!(assigned, vec![None, Some((1., 1.))]);
+    }
+
+    #[test]
+    fn tracking_keeps_voices_on_their_lines() {
+        // A line starts above one which continues, then the lower one ends
+        let img_data = array![
+            [0, 0, 0, 0, 255, 0],
+            [255, 0, 0, 0, 255, 0],
+            [255, 0, 0, 0, 0, 0],
+        ];
+        let mut interpreter = tracker(2, 6);
+        let (lower, upper) = (interpreter.frequency_at(4.5), interpreter.frequency_at(0.5));
+        let first_columns = img_data.slice(s![..2, ..]).to_owned();
+        interpreter.interpret(200, &first_columns, &ImgPacket::new());
+        assert_almost_eq(interpreter.voices[0].oscillator.frequency(), lower);
+        assert_almost_eq(interpreter.voices[1].oscillator.frequency(), upper);
+        let last_column = img_data.slice(s![2.., ..]).to_owned();
+        let samples = interpreter.interpret(100, &last_column, &ImgPacket::new());
+        // The ended line's voice holds its pitch while fading out
+        assert_almost_eq(interpreter.voices[0].oscillator.frequency(), lower);
+        assert_eq!(interpreter.voices[0].last_row, None);
+        assert_eq!(interpreter.voices[0].last_amplitude, 0.);
+        assert!(samples.iter().any(|sample| *sample != 0.));
+    }
+
+    #[test]
+    fn tracking_glides_along_line() {
+        // A line descending one row per column
+        let img_data = array![
+            [255, 0, 0, 0],
+            [0, 255, 0, 0],
+            [0, 0, 255, 0],
+            [0, 0, 0, 255],
+        ];
+        let mut interpreter = tracker(1, 4);
+        let samples = interpreter.interpret(400, &img_data, &ImgPacket::new());
+        assert_eq!(samples.len(), 400);
+        assert!(samples.iter().any(|sample| *sample != 0.));
+        let end_frequency = interpreter.voices[0].oscillator.frequency();
+        assert_almost_eq(end_frequency, interpreter.frequency_at(3.5));
+    }
+
+    #[test]
+    fn tracking_silent_without_lines() {
+        let img_data = Array2::<u8>::zeros((3, 4));
+        let samples = tracker(2, 4).interpret(300, &img_data, &ImgPacket::new());
+        assert!(samples.iter().all(|sample| *sample == 0.));
     }
 
     #[test]
@@ -140,27 +1258,3 @@ mod tests {
         assert_img_data_eq_by_element(slice, expected.view());
     }
 }
-
-#[cfg(test)]
-mod benchmarks {
-    extern crate rand;
-    extern crate test;
-    use super::*;
-
-    #[bench]
-    fn bench_amplitude_from_image_data(b: &mut test::Bencher) {
-        let image_data = random_image_data(1_000, 1_000);
-        let image_view = image_data.view();
-        b.iter(|| {
-            amplitude_from_img_data(&image_view);
-        });
-    }
-
-    fn random_image_data(width: usize, height: usize) -> Array2<u8> {
-        let mut random_array = Array2::zeros((width, height));
-        for element in random_array.iter_mut() {
-            *element = rand::random::<u8>();
-        }
-        random_array
-    }
-}
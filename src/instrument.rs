@@ -0,0 +1,12 @@
+/// A voice which sounds notes when triggered, rather than continuously
+///
+/// See `TriggeredInterpreter`.
+pub trait Instrument: Send {
+    /// Start a note, with a velocity between 0 and 1
+    fn note_on(&mut self, velocity: f32);
+
+    /// Release the current note
+    fn note_off(&mut self);
+
+    fn get_samples(&mut self, num: usize) -> Vec<f32>;
+}
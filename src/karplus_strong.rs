@@ -0,0 +1,194 @@
+use rand;
+use rand::{Rng, XorShiftRng};
+
+use instrument::Instrument;
+
+/// Shortest fractional delay given to the tuning allpass, which is
+/// poorly behaved as its delay approaches 0
+const MIN_ALLPASS_DELAY: f32 = 0.1;
+
+/// A Karplus-Strong plucked string
+///
+/// A burst of noise circulates through a delay line one period long.
+/// On every trip it loses `damping` of its level and passes through a
+/// two-point averaging filter weighted by `stretch`, so that high harmonics
+/// die away before low ones. A first-order allpass tunes the loop to
+/// fractional periods (Jaffe and Smith, 1983).
+///
+/// Notes ring until they decay or the string is plucked again;
+/// `note_off` has no effect.
+pub struct PluckedString {
+    delay_line: Vec<f32>,
+    position: usize,
+    // The sample read from the delay line before the current one
+    last_read: f32,
+    // Gain of each trip around the loop
+    loss: f32,
+    stretch: f32,
+    allpass_coefficient: f32,
+    allpass_last_input: f32,
+    allpass_last_output: f32,
+    rng: XorShiftRng,
+}
+
+impl PluckedString {
+    /// `damping` is the fraction of level lost each period, between 0 and 1.
+    /// `stretch` weights the averaging filter between the current and previous
+    /// sample, between 0 and 1. High harmonics are damped most at 0.5,
+    /// and ring longer towards either end.
+    pub fn new(frequency: f32, sample_rate: u32, damping: f32, stretch: f32) -> PluckedString {
+        assert!(frequency > 0., "Invalid frequency: {}", frequency);
+        assert!(damping >= 0. && damping <= 1., "Invalid damping: {}", damping);
+        assert!(stretch >= 0. && stretch <= 1., "Invalid stretch: {}", stretch);
+        // The averaging filter delays the loop by `stretch` samples,
+        // the allpass by the fraction remaining after the delay line
+        let loop_delay = (sample_rate as f32 / frequency) - stretch;
+        let delay_line_len = ((loop_delay - MIN_ALLPASS_DELAY).floor() as usize).max(1);
+        let allpass_delay = (loop_delay - delay_line_len as f32)
+            .max(MIN_ALLPASS_DELAY)
+            .min(1. + MIN_ALLPASS_DELAY);
+        PluckedString {
+            delay_line: vec![0.; delay_line_len],
+            position: 0,
+            last_read: 0.,
+            loss: 1. - damping,
+            stretch,
+            allpass_coefficient: (1. - allpass_delay) / (1. + allpass_delay),
+            allpass_last_input: 0.,
+            allpass_last_output: 0.,
+            rng: rand::weak_rng(),
+        }
+    }
+
+    /// Fill the string with a burst of noise peaking at `strength`
+    pub fn pluck(&mut self, strength: f32) {
+        let len = self.delay_line.len();
+        let mut excitation: Vec<f32> = (0..len).map(|_| self.rng.gen_range(-1., 1.)).collect();
+        // Without DC the string settles to silence
+        let mean = excitation.iter().sum::<f32>() / len as f32;
+        let peak = excitation.iter().fold(0., |peak: f32, s| peak.max((s - mean).abs()));
+        let scale = if peak > 0. { strength / peak } else { 0. };
+        for sample in excitation.iter_mut() {
+            *sample = (*sample - mean) * scale;
+        }
+        self.delay_line = excitation;
+        self.position = 0;
+        self.last_read = 0.;
+        self.allpass_last_input = 0.;
+        self.allpass_last_output = 0.;
+    }
+
+    #[inline]
+    fn next_sample(&mut self) -> f32 {
+        let current = self.delay_line[self.position];
+        let averaged =
+            self.loss * (((1. - self.stretch) * current) + (self.stretch * self.last_read));
+        self.last_read = current;
+        let tuned = (self.allpass_coefficient * (averaged - self.allpass_last_output))
+            + self.allpass_last_input;
+        self.allpass_last_input = averaged;
+        self.allpass_last_output = tuned;
+        self.delay_line[self.position] = tuned;
+        self.position = (self.position + 1) % self.delay_line.len();
+        current
+    }
+}
+
+impl Instrument for PluckedString {
+    fn note_on(&mut self, velocity: f32) {
+        self.pluck(velocity);
+    }
+
+    fn note_off(&mut self) {}
+
+    fn get_samples(&mut self, num: usize) -> Vec<f32> {
+        (0..num).map(|_| self.next_sample()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_utils::*;
+
+    fn power(samples: &[f32]) -> f32 {
+        samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32
+    }
+
+    /// The lag between `min_lag` and `max_lag` at which `samples` best match themselves
+    fn best_lag(samples: &[f32], min_lag: usize, max_lag: usize) -> usize {
+        let correlation = |lag: usize| -> f32 {
+            samples.iter().zip(samples[lag..].iter()).map(|(a, b)| a * b).sum()
+        };
+        (min_lag..max_lag)
+            .max_by(|a, b| correlation(*a).partial_cmp(&correlation(*b)).unwrap())
+            .unwrap()
+    }
+
+    #[test]
+    fn silent_until_plucked() {
+        let mut string = PluckedString::new(441., 44100, 0.01, 0.5);
+        assert_almost_eq_by_element(string.get_samples(10), vec![0.; 10]);
+    }
+
+    #[test]
+    fn pluck_peaks_at_strength() {
+        let mut string = PluckedString::new(441., 44100, 0.01, 0.5);
+        string.note_on(0.6);
+        let delay_line_len = string.delay_line.len();
+        let first_period = string.get_samples(delay_line_len);
+        let peak = first_period.iter().fold(0., |peak: f32, s| peak.max(s.abs()));
+        assert_almost_eq(peak, 0.6);
+        assert!(first_period.iter().sum::<f32>().abs() < 1e-4);
+    }
+
+    #[test]
+    fn plucks_decay() {
+        let mut string = PluckedString::new(441., 44100, 0.01, 0.5);
+        string.pluck(1.);
+        let early = power(&string.get_samples(1000));
+        string.get_samples(10000);
+        let late = power(&string.get_samples(1000));
+        assert!(late < early / 10.);
+    }
+
+    #[test]
+    fn more_damping_decays_faster() {
+        let mut light = PluckedString::new(441., 44100, 0.001, 0.5);
+        let mut heavy = PluckedString::new(441., 44100, 0.05, 0.5);
+        light.pluck(1.);
+        heavy.pluck(1.);
+        light.get_samples(4410);
+        heavy.get_samples(4410);
+        assert!(power(&heavy.get_samples(1000)) < power(&light.get_samples(1000)) / 10.);
+    }
+
+    #[test]
+    fn full_damping_silences_after_one_period() {
+        let mut string = PluckedString::new(441., 44100, 1., 0.5);
+        string.pluck(1.);
+        string.get_samples(100);
+        assert_almost_eq_by_element(string.get_samples(100), vec![0.; 100]);
+    }
+
+    #[test]
+    fn rings_at_fractional_periods() {
+        // A period of 102.56 samples
+        let mut string = PluckedString::new(430., 44100, 0.001, 0.5);
+        string.pluck(1.);
+        string.get_samples(1000);
+        let samples = string.get_samples(4000);
+        // Twenty periods later the error is more than a sample if untuned
+        let twenty_periods = best_lag(&samples, 2000, 2150);
+        assert!((twenty_periods as f32 - 2051.2).abs() <= 1., "lag of {}", twenty_periods);
+    }
+
+    #[test]
+    fn note_off_lets_ring() {
+        let mut string = PluckedString::new(441., 44100, 0.01, 0.5);
+        string.note_on(1.);
+        string.note_off();
+        string.get_samples(1000);
+        assert!(power(&string.get_samples(1000)) > 1e-3);
+    }
+}
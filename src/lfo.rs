@@ -0,0 +1,71 @@
+use std::f64::consts;
+
+use rand;
+
+/// A sine low-frequency oscillator for slow modulation like vibrato and tremolo
+///
+/// Unlike `Oscillator` its rate may change every sample, and it starts at a
+/// random phase so that many voices modulated at the same rate drift
+/// independently rather than in lockstep.
+pub struct Lfo {
+    // Position within the cycle, between 0 and 1
+    phase: f64,
+    sample_rate: u32,
+}
+
+impl Lfo {
+    pub fn new(sample_rate: u32) -> Lfo {
+        Lfo {
+            phase: rand::random::<f64>(),
+            sample_rate,
+        }
+    }
+
+    /// Start at `phase`, between 0 and 1, instead of a random one
+    pub fn with_phase(mut self, phase: f64) -> Self {
+        self.phase = phase - phase.floor();
+        self
+    }
+
+    /// One value between -1 and 1 for each of `rates`, in cycles per second
+    pub fn values(&mut self, rates: &[f32]) -> Vec<f32> {
+        rates
+            .iter()
+            .map(|rate| {
+                let value = (self.phase * 2. * consts::PI).sin() as f32;
+                self.phase += *rate as f64 / self.sample_rate as f64;
+                self.phase -= self.phase.floor();
+                value
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_utils::*;
+
+    #[test]
+    fn values_follow_sine() {
+        let mut lfo = Lfo::new(8).with_phase(0.);
+        assert_almost_eq_by_element(
+            lfo.values(&[1.; 4]),
+            vec![0., 0.70710677, 1., 0.70710677],
+        );
+        assert_almost_eq_by_element(lfo.values(&[2.; 2]), vec![0., -1.]);
+    }
+
+    #[test]
+    fn zero_rate_holds() {
+        let mut lfo = Lfo::new(8).with_phase(0.25);
+        assert_almost_eq_by_element(lfo.values(&[0.; 3]), vec![1., 1., 1.]);
+    }
+
+    #[test]
+    fn random_phases_differ() {
+        let first = Lfo::new(44100).values(&[1.]);
+        let second = Lfo::new(44100).values(&[1.]);
+        assert!(first != second);
+    }
+}
@@ -1,30 +1,45 @@
-#![feature(custom_attribute)]
-#![feature(test)]
+#![cfg_attr(test, feature(test))]
 
 extern crate rand;
 extern crate image;
-#[macro_use(array)]
-#[macro_use(s)]
+#[macro_use]
 extern crate ndarray;
 extern crate portaudio;
 extern crate stopwatch;
 extern crate hound;
 extern crate num;
 extern crate itertools;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate toml;
 
 mod arrays;
-mod img_dispatcher;
-mod img_interpreter;
-mod mixer;
 mod sample_buffer;
-mod synth;
-mod pitch;
 mod color;
+mod fft;
 
+pub mod amplitude;
+pub mod envelope;
+pub mod filter;
+pub mod img_dispatcher;
+pub mod img_interpreter;
+pub mod instrument;
+pub mod karplus_strong;
+pub mod lfo;
+pub mod mixer;
+pub mod noise;
+pub mod synth;
+pub mod wavetable;
+pub mod pitch;
+pub mod sampler;
+pub mod soundfont;
 pub mod audio_streamer;
 pub mod portaudio_streamer;
 pub mod wav_streamer;
 pub mod conductor;
+pub mod pipeline;
+pub mod score;
 
 #[cfg(test)]
 mod test_utils;
@@ -1,8 +1,5 @@
 use std::sync::mpsc::{channel, Receiver};
 use std::thread;
-use std::time::Duration;
-
-use stopwatch::Stopwatch;
 
 pub type Chunk = Vec<f32>;
 
@@ -11,7 +8,7 @@ fn add_chunk_to_maybe_empty(src: &Chunk, dest: &mut Chunk) {
     debug_assert!(dest.is_empty() || dest.len() == src.len());
     if dest.is_empty() {
         dest.reserve_exact(src.len());
-        for i in 0..src.len() {
+        for _ in 0..src.len() {
             dest.push(0.);
         }
     }
@@ -35,7 +32,7 @@ pub fn add_chunk_to(src: &Chunk, dest: &mut Chunk) {
 #[inline]
 fn compress(uncompressed_samples: &mut Vec<f32>, expected_max_amp: f32) {
     for sample in uncompressed_samples {
-        *sample = (*sample) / expected_max_amp;
+        *sample /= expected_max_amp;
     }
 }
 
@@ -49,14 +46,14 @@ pub fn mix(receivers: Vec<Receiver<Chunk>>, expected_max_amp: f32) -> Receiver<V
             for receiver in &receivers {
                 match receiver.recv() {
                     Ok(chunk) => add_chunk_to_maybe_empty(&chunk, &mut combined_samples),
-                    Err(RecvError) => return,
+                    Err(_) => return,
                 }
             }
             compress(&mut combined_samples, expected_max_amp);
             mixed_chunk_sender.send(combined_samples).unwrap();
             // println!("mixed samples in {:?}", sw.elapsed());
         }
-    });
+    }).unwrap();
 
     mixed_chunk_receiver
 }
@@ -0,0 +1,1907 @@
+use std::cmp::Ordering::*;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+use std::thread::JoinHandle;
+
+use hound;
+use image;
+
+use audio_streamer::AudioStreamer;
+use envelope::Envelope;
+use img_dispatcher::{
+    ChannelSpec, ImgLayerId, ImgLayerMetadata, ImgPacket, LayerExtractorFn, RgbaImage32Bit,
+    StaticImgDispatcher,
+};
+use img_interpreter::{
+    AutomatedFilter, FilteredInterpreter, ImgInterpreter, LayerInterpreter, Modulation,
+    PitchTrackingInterpreter, SectionInterpreter, TriggeredInterpreter,
+};
+use lfo::Lfo;
+use mixer;
+use mixer::Chunk;
+use score::{
+    Amplitude, BandSpec, Extractor, FilterSpec, InstrumentSpec, LayerSpec, LfoSpec, PitchMap,
+    Synthesis, Tracking,
+};
+use soundfont::SoundFontError;
+use synth::{Oscillator, Waveform, WaveformTables};
+
+pub const DEFAULT_SAMPLE_RATE: u32 = 44100;
+pub const DEFAULT_SAMPLES_PER_PIXEL: usize = 4410;
+pub const DEFAULT_CHUNK_WIDTH: u32 = 100;
+
+/// A fully configured render, ready to be started with `Pipeline::render`
+///
+/// ```no_run
+/// use spectrophoner::pipeline::Pipeline;
+/// use spectrophoner::score::{LayerSpec, PitchMap};
+///
+/// let render = Pipeline::builder()
+///     .image("resources/ascending_line.png")
+///     .layer(LayerSpec::new(60))
+///     .pitch_map(PitchMap::HarmonicSeries { fundamental: 2. })
+///     .sample_rate(44100)
+///     .build()
+///     .unwrap()
+///     .render();
+/// let samples = render.collect_samples();
+/// ```
+pub struct Pipeline {
+    img: RgbaImage32Bit,
+    sample_rate: u32,
+    samples_per_pixel: usize,
+    chunk_width: u32,
+    bands: Vec<BandSpec>,
+    filter: Option<FilterSpec>,
+}
+
+pub struct PipelineBuilder {
+    image: Option<PathBuf>,
+    sample_rate: u32,
+    samples_per_pixel: usize,
+    chunk_width: u32,
+    pitch_map: Option<PitchMap>,
+    layers: Vec<LayerSpec>,
+    bands: Vec<BandSpec>,
+    filter: Option<FilterSpec>,
+}
+
+/// A handle to a running render
+///
+/// Mixed samples arrive through `samples` as they are produced, at `sample_rate` Hz.
+/// The channel is closed once the whole image has been rendered.
+pub struct Render {
+    pub samples: Receiver<Chunk>,
+    pub sample_rate: u32,
+    threads: Vec<JoinHandle<()>>,
+}
+
+#[derive(Debug)]
+pub enum PipelineError {
+    MissingImage,
+    NoLayers,
+    /// The sample rate is 0
+    InvalidSampleRate,
+    /// `samples_per_pixel` is 0
+    InvalidSamplesPerPixel,
+    /// `chunk_width` is 0
+    InvalidChunkWidth,
+    /// The band at the given index does not lie within the image,
+    /// or is less than a pixel tall
+    InvalidBand(usize),
+    /// The layer at the given index has more sections than its band has rows of pixels
+    TooManySections(usize),
+    /// The layer at the given index, or one of the layers its sections read,
+    /// has a color key with a non-positive tolerance
+    InvalidExtractor(usize),
+    /// The layer at the given index reduces no columns into each breakpoint
+    InvalidColumnsPerBreakpoint(usize),
+    /// The layer at the given index has a percentile amplitude outside 0 to 1
+    InvalidAmplitude(usize),
+    /// The layer at the given index has a non-positive gamma, a decibel range
+    /// above 0 dB or with its floor not below its ceiling, or a gate outside 0 to 1
+    InvalidTransfer(usize),
+    /// The layer at the given index has no pitch map and no default was given
+    MissingPitchMap(usize),
+    /// The pitch map of the layer at the given index has a non-positive frequency
+    InvalidPitchMap(usize),
+    Image(image::ImageError),
+    /// The wavetable of the layer at the given index could not be read
+    Wavetable(usize, hound::Error),
+    /// The layer at the given index morphs between no waveforms
+    EmptyMorph(usize),
+    /// The layer at the given index has a pulse duty outside 0 to 1,
+    /// or a negative FM ratio or index
+    InvalidWaveform(usize),
+    /// The layer at the given index has no sections or tracked lines to voice
+    NoVoices(usize),
+    /// The layer at the given index lists a different number of section waveforms
+    /// than it has sections
+    InvalidSectionWaveforms(usize),
+    /// The layer at the given index tracks lines over a non-positive frequency range,
+    /// or sets an envelope, vibrato, tremolo, timbre, section waveforms or amplitude,
+    /// which tracked lines ignore
+    InvalidTracking(usize),
+    /// The envelope of the layer at the given index has negative times,
+    /// a sustain level outside 0 to 1, or a non-positive onset threshold
+    InvalidEnvelope(usize),
+    /// The layer at the given index has a negative vibrato or tremolo rate or depth,
+    /// or a tremolo deeper than 1
+    InvalidModulation(usize),
+    /// The layer at the given index has an instrument with invalid parameters,
+    /// a non-positive onset threshold, or also tracks lines or sets an envelope,
+    /// vibrato, tremolo, timbre or section waveforms, which instruments ignore
+    InvalidInstrument(usize),
+    /// The recording played by the layer at the given index could not be read
+    Recording(usize, hound::Error),
+    /// The SoundFont played by the layer at the given index could not be read
+    SoundFont(usize, SoundFontError),
+    /// The SoundFont played by the layer at the given index has no samples
+    /// for the chosen preset
+    MissingPreset(usize),
+    /// The layer at the given index has a filter with a non-positive cutoff or Q,
+    /// or filters the sections of tracked lines
+    InvalidFilter(usize),
+    /// The filter on the mixed output has a non-positive cutoff or Q,
+    /// or one of its layers has an invalid extractor
+    InvalidBusFilter,
+    /// The layer at the given index voices noise bands while tracking lines,
+    /// playing an instrument or setting section waveforms
+    InvalidSynthesis(usize),
+}
+
+impl Pipeline {
+    pub fn builder() -> PipelineBuilder {
+        PipelineBuilder {
+            image: None,
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            samples_per_pixel: DEFAULT_SAMPLES_PER_PIXEL,
+            chunk_width: DEFAULT_CHUNK_WIDTH,
+            pitch_map: None,
+            layers: vec![],
+            bands: vec![],
+            filter: None,
+        }
+    }
+
+    /// Spawn the dispatcher, interpreter, and mixer threads and begin rendering
+    ///
+    /// Each band is dispatched on its own channel and interpreted on its own thread.
+    pub fn render(self) -> Render {
+        let img_height = self.img.height() as usize;
+        let sound_layer_count: usize = self.bands.iter().map(|band| band.layers.len()).sum();
+        // Layer ids are unique across all bands. Ids below `sound_layer_count` are
+        // indices into `layers`; the rest are data-only layers, listed in `data_layer_ids`
+        // under the id of the layer whose sections read them.
+        let mut layers = Vec::<LayerSpec>::new();
+        let mut data_layer_ids = HashMap::<ImgLayerId, DataLayerIds>::new();
+        let mut next_data_layer_id = sound_layer_count;
+        let mut channel_specs = Vec::<ChannelSpec>::new();
+        for band in self.bands {
+            let (y_start, y_end) = band.pixel_range(img_height);
+            let mut layer_extractors = HashMap::<ImgLayerId, LayerExtractorFn>::new();
+            for layer in band.layers {
+                let layer_id = layers.len() as ImgLayerId;
+                layer_extractors.insert(layer_id, layer.layer_extractor());
+                let mut add_data_layer = |extractor: Option<Extractor>| {
+                    extractor.map(|extractor| {
+                        let data_layer_id = next_data_layer_id as ImgLayerId;
+                        next_data_layer_id += 1;
+                        layer_extractors.insert(data_layer_id, extractor.layer_extractor());
+                        data_layer_id
+                    })
+                };
+                let section_filter = layer.section_filter;
+                let ids = DataLayerIds {
+                    timbre: add_data_layer(layer.timbre),
+                    vibrato_rate: add_data_layer(layer.vibrato.and_then(|lfo| lfo.rate_layer)),
+                    vibrato_depth: add_data_layer(layer.vibrato.and_then(|lfo| lfo.depth_layer)),
+                    tremolo_rate: add_data_layer(layer.tremolo.and_then(|lfo| lfo.rate_layer)),
+                    tremolo_depth: add_data_layer(layer.tremolo.and_then(|lfo| lfo.depth_layer)),
+                    filter_cutoff: add_data_layer(layer.filter.and_then(|f| f.cutoff_layer)),
+                    filter_q: add_data_layer(layer.filter.and_then(|f| f.q_layer)),
+                    section_filter_cutoff: add_data_layer(
+                        section_filter.and_then(|f| f.cutoff_layer),
+                    ),
+                    section_filter_q: add_data_layer(section_filter.and_then(|f| f.q_layer)),
+                };
+                data_layer_ids.insert(layer_id, ids);
+                layers.push(layer);
+            }
+            channel_specs.push(ChannelSpec {
+                y_start,
+                y_end,
+                layer_extractors,
+            });
+        }
+        // The bus filter's layers are read over the whole image, on a channel of their own
+        let mut bus_layer_extractors = HashMap::<ImgLayerId, LayerExtractorFn>::new();
+        let (bus_cutoff_layer_id, bus_q_layer_id) = {
+            let mut add_data_layer = |extractor: Option<Extractor>| {
+                extractor.map(|extractor| {
+                    let data_layer_id = next_data_layer_id as ImgLayerId;
+                    next_data_layer_id += 1;
+                    bus_layer_extractors.insert(data_layer_id, extractor.layer_extractor());
+                    data_layer_id
+                })
+            };
+            (
+                add_data_layer(self.filter.and_then(|f| f.cutoff_layer)),
+                add_data_layer(self.filter.and_then(|f| f.q_layer)),
+            )
+        };
+        let has_bus_channel = !bus_layer_extractors.is_empty();
+        if has_bus_channel {
+            channel_specs.push(ChannelSpec {
+                y_start: 0,
+                y_end: img_height,
+                layer_extractors: bus_layer_extractors,
+            });
+        }
+
+        let voice_count: usize = layers.iter().map(|layer| layer.voice_count()).sum();
+        let expected_max_amp = (voice_count as f32) * 0.3;
+        let (mut img_dispatcher, mut channel_exporters) =
+            StaticImgDispatcher::new(self.img, self.chunk_width, channel_specs);
+        let bus_exporter = if has_bus_channel { channel_exporters.pop() } else { None };
+
+        let mut interpreter_sample_receivers = Vec::<Receiver<Chunk>>::new();
+        let mut threads = Vec::<JoinHandle<()>>::new();
+
+        for channel_exporter in channel_exporters {
+            let layers_metadata = channel_exporter.layers_metadata;
+            let img_layers_receiver = channel_exporter.receiver;
+            let (samples_sender, samples_receiver) = channel::<Vec<f32>>();
+            interpreter_sample_receivers.push(samples_receiver);
+            let layer_handlers = derive_layer_handlers(
+                &layers,
+                &data_layer_ids,
+                layers_metadata,
+                self.sample_rate,
+            );
+            let mut interpreter = ImgInterpreter::new(
+                img_layers_receiver,
+                samples_sender,
+                self.samples_per_pixel,
+                layer_handlers,
+            );
+
+            threads.push(
+                thread::Builder::new()
+                    .name("ImgInterpreter".to_string())
+                    .spawn(move || {
+                        interpreter.interpret();
+                    })
+                    .unwrap(),
+            );
+        }
+
+        threads.push(
+            thread::Builder::new()
+                .name("StaticImgDispatcher".to_string())
+                .spawn(move || {
+                    img_dispatcher.begin_dispatch();
+                })
+                .unwrap(),
+        );
+
+        let mut samples = mixer::mix(interpreter_sample_receivers, expected_max_amp);
+        if let Some(filter_spec) = self.filter {
+            let filter =
+                generate_filter(filter_spec, bus_cutoff_layer_id, bus_q_layer_id, self.sample_rate);
+            let img_packets = bus_exporter.map(|exporter| exporter.receiver);
+            let (filtered_sender, filtered_receiver) = channel::<Chunk>();
+            threads.push(
+                thread::Builder::new()
+                    .name("BusFilter".to_string())
+                    .spawn(move || {
+                        filter_bus(samples, img_packets, filter, img_height, filtered_sender);
+                    })
+                    .unwrap(),
+            );
+            samples = filtered_receiver;
+        }
+
+        Render {
+            samples,
+            sample_rate: self.sample_rate,
+            threads,
+        }
+    }
+}
+
+impl PipelineBuilder {
+    /// Set the path of the image to render. Required.
+    pub fn image<P: Into<PathBuf>>(mut self, path: P) -> PipelineBuilder {
+        self.image = Some(path.into());
+        self
+    }
+
+    /// Add a layer spanning the full height of the image
+    pub fn layer(mut self, layer: LayerSpec) -> PipelineBuilder {
+        self.layers.push(layer);
+        self
+    }
+
+    /// Add a horizontal band with its own layers, rendered on its own channel
+    pub fn band(mut self, band: BandSpec) -> PipelineBuilder {
+        self.bands.push(band);
+        self
+    }
+
+    /// Filter the mixed output of every band
+    pub fn filter(mut self, filter: FilterSpec) -> PipelineBuilder {
+        self.filter = Some(filter);
+        self
+    }
+
+    /// Set the pitch map used by layers which do not specify their own
+    pub fn pitch_map(mut self, pitch_map: PitchMap) -> PipelineBuilder {
+        self.pitch_map = Some(pitch_map);
+        self
+    }
+
+    pub fn sample_rate(mut self, sample_rate: u32) -> PipelineBuilder {
+        self.sample_rate = sample_rate;
+        self
+    }
+
+    pub fn samples_per_pixel(mut self, samples_per_pixel: usize) -> PipelineBuilder {
+        self.samples_per_pixel = samples_per_pixel;
+        self
+    }
+
+    pub fn chunk_width(mut self, chunk_width: u32) -> PipelineBuilder {
+        self.chunk_width = chunk_width;
+        self
+    }
+
+    /// Validate the configuration and load the image and any wavetables
+    pub fn build(self) -> Result<Pipeline, PipelineError> {
+        let image_path = self.image.ok_or(PipelineError::MissingImage)?;
+        if self.sample_rate == 0 {
+            return Err(PipelineError::InvalidSampleRate);
+        }
+        if self.samples_per_pixel == 0 {
+            return Err(PipelineError::InvalidSamplesPerPixel);
+        }
+        if self.chunk_width == 0 {
+            return Err(PipelineError::InvalidChunkWidth);
+        }
+        let mut bands = self.bands;
+        if !self.layers.is_empty() {
+            bands.insert(0, BandSpec::full_image(self.layers));
+        }
+        if bands.iter().all(|band| band.layers.is_empty()) {
+            return Err(PipelineError::NoLayers);
+        }
+        let img = image::open(image_path)?.to_rgba();
+        let img_height = img.height() as usize;
+        for (i, band) in bands.iter().enumerate() {
+            let (y_start, y_end) = band.pixel_range(img_height);
+            if !(0. <= band.top && band.top < band.bottom && band.bottom <= 1. && y_start < y_end) {
+                return Err(PipelineError::InvalidBand(i));
+            }
+        }
+        let bus_filter_is_valid = self.filter.map_or(true, |filter| {
+            filter.is_valid() && filter.extractors().iter().all(Extractor::is_valid)
+        });
+        if !bus_filter_is_valid {
+            return Err(PipelineError::InvalidBusFilter);
+        }
+        let layers = bands.iter_mut().flat_map(|band| {
+            let (y_start, y_end) = band.pixel_range(img_height);
+            band.layers.iter_mut().map(move |layer| (layer, y_end - y_start))
+        });
+        for (i, (layer, band_height)) in layers.enumerate() {
+            if layer.voice_count() == 0 {
+                return Err(PipelineError::NoVoices(i));
+            }
+            if layer.tracking.is_none() && layer.sections > band_height {
+                return Err(PipelineError::TooManySections(i));
+            }
+            if let Some(ref waveforms) = layer.section_waveforms {
+                if waveforms.len() != layer.sections {
+                    return Err(PipelineError::InvalidSectionWaveforms(i));
+                }
+            }
+            if layer.columns_per_breakpoint == 0 {
+                return Err(PipelineError::InvalidColumnsPerBreakpoint(i));
+            }
+            if !layer.amplitude.is_valid() {
+                return Err(PipelineError::InvalidAmplitude(i));
+            }
+            if !layer.transfer.is_valid() {
+                return Err(PipelineError::InvalidTransfer(i));
+            }
+            if !layer.extractors().iter().all(Extractor::is_valid) {
+                return Err(PipelineError::InvalidExtractor(i));
+            }
+            if let Some(tracking) = layer.tracking {
+                let is_valid = tracking.low > 0.
+                    && tracking.high > 0.
+                    && !layer.uses_section_voicing()
+                    && layer.amplitude == Amplitude::Mean;
+                if !is_valid {
+                    return Err(PipelineError::InvalidTracking(i));
+                }
+            } else {
+                let pitch_map = match layer.pitch {
+                    Some(pitch_map) => pitch_map,
+                    None => self.pitch_map.ok_or(PipelineError::MissingPitchMap(i))?,
+                };
+                if !pitch_map.is_valid() {
+                    return Err(PipelineError::InvalidPitchMap(i));
+                }
+                layer.pitch = Some(pitch_map);
+            }
+            if let Some(envelope) = layer.envelope {
+                if !(envelope.is_valid() && layer.onset_threshold > 0.) {
+                    return Err(PipelineError::InvalidEnvelope(i));
+                }
+            }
+            let vibrato_is_valid =
+                layer.vibrato.map_or(true, |lfo| lfo.rate >= 0. && lfo.depth >= 0.);
+            let tremolo_is_valid = layer.tremolo.map_or(true, |lfo| {
+                lfo.rate >= 0. && lfo.depth >= 0. && lfo.depth <= 1.
+            });
+            if !(vibrato_is_valid && tremolo_is_valid) {
+                return Err(PipelineError::InvalidModulation(i));
+            }
+            if let Some(ref instrument) = layer.instrument {
+                let is_valid = instrument.is_valid()
+                    && layer.onset_threshold > 0.
+                    && layer.tracking.is_none()
+                    && !layer.uses_section_voicing();
+                if !is_valid {
+                    return Err(PipelineError::InvalidInstrument(i));
+                }
+            }
+            if layer.synthesis == Synthesis::NoiseBand
+                && (layer.tracking.is_some()
+                    || layer.instrument.is_some()
+                    || layer.section_waveforms.is_some())
+            {
+                return Err(PipelineError::InvalidSynthesis(i));
+            }
+            let filters_are_valid = layer.filter.map_or(true, |f| f.is_valid())
+                && layer.section_filter.map_or(true, |f| f.is_valid())
+                && !(layer.section_filter.is_some() && layer.tracking.is_some());
+            if !filters_are_valid {
+                return Err(PipelineError::InvalidFilter(i));
+            }
+            for waveform in layer.waveforms_mut() {
+                if let Waveform::Morph(ref waveforms) = *waveform {
+                    if waveforms.is_empty() {
+                        return Err(PipelineError::EmptyMorph(i));
+                    }
+                }
+                if !waveform.is_valid() {
+                    return Err(PipelineError::InvalidWaveform(i));
+                }
+                for wavetable in waveform.wavetables_mut() {
+                    wavetable.load().map_err(|err| PipelineError::Wavetable(i, err))?;
+                }
+            }
+            if let Some(recording) = layer.instrument.as_mut().and_then(|i| i.recording_mut()) {
+                recording.load().map_err(|err| PipelineError::Recording(i, err))?;
+            }
+            if let Some(soundfont) = layer.instrument.as_mut().and_then(|i| i.soundfont_mut()) {
+                soundfont.load().map_err(|err| PipelineError::SoundFont(i, err))?;
+            }
+            if layer.instrument.as_ref().map_or(false, |i| i.has_missing_preset()) {
+                return Err(PipelineError::MissingPreset(i));
+            }
+        }
+        Ok(Pipeline {
+            img,
+            sample_rate: self.sample_rate,
+            samples_per_pixel: self.samples_per_pixel,
+            chunk_width: self.chunk_width,
+            bands,
+            filter: self.filter,
+        })
+    }
+}
+
+impl Render {
+    /// Block until the render is complete, returning every mixed sample
+    pub fn collect_samples(self) -> Vec<f32> {
+        let mut collected = Vec::<f32>::new();
+        for mut chunk in &self.samples {
+            collected.append(&mut chunk);
+        }
+        self.join();
+        collected
+    }
+
+    /// Pass the rendered samples to `output_streamer` at the render's sample rate
+    pub fn stream<T>(self, output_streamer: &T) where T: AudioStreamer<f32> {
+        output_streamer.stream(self.samples, self.sample_rate);
+        for thread in self.threads {
+            thread.join().unwrap();
+        }
+    }
+
+    /// Wait for the dispatcher and interpreter threads to finish.
+    ///
+    /// Samples still queued in `samples` are discarded.
+    pub fn join(self) {
+        for thread in self.threads {
+            thread.join().unwrap();
+        }
+    }
+}
+
+impl fmt::Display for PipelineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PipelineError::MissingImage => write!(f, "No image was given"),
+            PipelineError::NoLayers => write!(f, "At least one layer is required"),
+            PipelineError::InvalidSampleRate => write!(f, "The sample rate must be positive"),
+            PipelineError::InvalidSamplesPerPixel => {
+                write!(f, "samples_per_pixel must be positive")
+            }
+            PipelineError::InvalidChunkWidth => write!(f, "chunk_width must be positive"),
+            PipelineError::InvalidBand(band) => write!(
+                f,
+                "Band {} must satisfy 0 <= top < bottom <= 1 and be at least a pixel tall",
+                band
+            ),
+            PipelineError::TooManySections(layer) => {
+                write!(f, "Layer {} has more sections than rows of pixels", layer)
+            }
+            PipelineError::InvalidExtractor(layer) => {
+                write!(f, "Layer {} has a color key with a non-positive tolerance", layer)
+            }
+            PipelineError::InvalidColumnsPerBreakpoint(layer) => {
+                write!(f, "Layer {} must reduce at least one column into each breakpoint", layer)
+            }
+            PipelineError::InvalidAmplitude(layer) => {
+                write!(f, "Layer {} must take a percentile between 0 and 1", layer)
+            }
+            PipelineError::InvalidTransfer(layer) => {
+                write!(f, "Layer {} has a transfer curve or gate out of range", layer)
+            }
+            PipelineError::MissingPitchMap(layer) => {
+                write!(f, "Layer {} has no pitch map and no default was given", layer)
+            }
+            PipelineError::InvalidPitchMap(layer) => {
+                write!(f, "Layer {} must map sections to positive frequencies", layer)
+            }
+            PipelineError::Image(ref err) => write!(f, "Could not load image: {}", err),
+            PipelineError::Wavetable(layer, ref err) => {
+                write!(f, "Could not load wavetable for layer {}: {}", layer, err)
+            }
+            PipelineError::EmptyMorph(layer) => {
+                write!(f, "Layer {} must morph between at least one waveform", layer)
+            }
+            PipelineError::InvalidWaveform(layer) => {
+                write!(f, "Layer {} has an invalid waveform", layer)
+            }
+            PipelineError::NoVoices(layer) => {
+                write!(f, "Layer {} needs at least one section or tracked line", layer)
+            }
+            PipelineError::InvalidSectionWaveforms(layer) => {
+                write!(f, "Layer {} must list one section waveform per section", layer)
+            }
+            PipelineError::InvalidTracking(layer) => {
+                write!(
+                    f,
+                    "Layer {} must track lines between positive frequencies, \
+                     without an envelope, modulation, timbre, section waveforms or amplitude",
+                    layer
+                )
+            }
+            PipelineError::InvalidEnvelope(layer) => {
+                write!(f, "Layer {} has an invalid envelope or onset threshold", layer)
+            }
+            PipelineError::InvalidModulation(layer) => {
+                write!(f, "Layer {} has an invalid vibrato or tremolo", layer)
+            }
+            PipelineError::InvalidInstrument(layer) => {
+                write!(
+                    f,
+                    "Layer {} has an invalid instrument or onset threshold, or an envelope, \
+                     modulation, timbre or section waveforms its instrument ignores",
+                    layer
+                )
+            }
+            PipelineError::Recording(layer, ref err) => {
+                write!(f, "Could not load recording for layer {}: {}", layer, err)
+            }
+            PipelineError::SoundFont(layer, ref err) => {
+                write!(f, "Could not load SoundFont for layer {}: {}", layer, err)
+            }
+            PipelineError::MissingPreset(layer) => {
+                write!(f, "Layer {} plays a preset missing from its SoundFont", layer)
+            }
+            PipelineError::InvalidFilter(layer) => {
+                write!(f, "Layer {} has an invalid filter", layer)
+            }
+            PipelineError::InvalidBusFilter => write!(f, "The output filter is invalid"),
+            PipelineError::InvalidSynthesis(layer) => write!(
+                f,
+                "Layer {} can only voice noise bands without tracking, an instrument \
+                 or section waveforms",
+                layer
+            ),
+        }
+    }
+}
+
+impl Error for PipelineError {}
+
+impl From<image::ImageError> for PipelineError {
+    fn from(err: image::ImageError) -> PipelineError {
+        PipelineError::Image(err)
+    }
+}
+
+/// Ids of the data-only layers read by the sections of one sound layer
+#[derive(Debug, Copy, Clone)]
+struct DataLayerIds {
+    timbre: Option<ImgLayerId>,
+    vibrato_rate: Option<ImgLayerId>,
+    vibrato_depth: Option<ImgLayerId>,
+    tremolo_rate: Option<ImgLayerId>,
+    tremolo_depth: Option<ImgLayerId>,
+    filter_cutoff: Option<ImgLayerId>,
+    filter_q: Option<ImgLayerId>,
+    section_filter_cutoff: Option<ImgLayerId>,
+    section_filter_q: Option<ImgLayerId>,
+}
+
+fn derive_layer_handlers(
+    layers: &[LayerSpec],
+    data_layer_ids: &HashMap<ImgLayerId, DataLayerIds>,
+    layers_metadata: Vec<ImgLayerMetadata>,
+    sample_rate: u32,
+) -> HashMap<ImgLayerId, Vec<Box<dyn LayerInterpreter>>> {
+    let mut layer_handlers = HashMap::new();
+    for layer_metadata in layers_metadata {
+        let layer_id = layer_metadata.img_layer_id;
+        // Data-only layers are read by the sections of the layer they belong to
+        let layer_spec = match layers.get(layer_id as usize) {
+            Some(layer_spec) => layer_spec,
+            None => continue,
+        };
+        let ids = data_layer_ids[&layer_id];
+        let handlers = match layer_spec.tracking {
+            Some(tracking) => vec![generate_pitch_tracking_interpreter(
+                layer_metadata,
+                layer_spec,
+                tracking,
+                sample_rate,
+            )],
+            None => match layer_spec.instrument {
+                Some(ref instrument) => generate_triggered_interpreters(
+                    layer_metadata,
+                    layer_spec,
+                    instrument,
+                    ids,
+                    sample_rate,
+                ),
+                None => generate_naive_section_interpreters(
+                    layer_metadata,
+                    layer_spec,
+                    ids,
+                    sample_rate,
+                ),
+            },
+        };
+        let handlers = match layer_spec.filter {
+            Some(filter_spec) => {
+                let filter =
+                    generate_filter(filter_spec, ids.filter_cutoff, ids.filter_q, sample_rate);
+                let interpreter = FilteredInterpreter::new(
+                    handlers,
+                    filter,
+                    layer_metadata.y_start,
+                    layer_metadata.y_end,
+                );
+                vec![Box::new(interpreter) as Box<dyn LayerInterpreter>]
+            }
+            None => handlers,
+        };
+        layer_handlers.insert(layer_id, handlers);
+    }
+    layer_handlers
+}
+
+fn generate_naive_section_interpreters(
+    layer_metadata: ImgLayerMetadata,
+    layer_spec: &LayerSpec,
+    data_layer_ids: DataLayerIds,
+    sample_rate: u32,
+) -> Vec<Box<dyn LayerInterpreter>> {
+    let mut section_interpreters = Vec::<Box<dyn LayerInterpreter>>::new();
+
+    let frequencies = section_frequencies(layer_spec);
+    let bandwidths = section_bandwidths(&frequencies);
+    let sections = section_ranges(layer_metadata, layer_spec.sections);
+    // Sections sounding the same waveform read the same tables
+    let mut waveform_tables = Vec::<(&Waveform, WaveformTables)>::new();
+    let voices = frequencies.iter().zip(bandwidths).zip(sections);
+    for (section, ((&frequency, bandwidth), (y_start, y_end))) in voices.enumerate() {
+        let oscillator = match layer_spec.synthesis {
+            Synthesis::Oscillator => {
+                let waveform = layer_spec.section_waveform(section);
+                let index = match waveform_tables.iter().position(|&(w, _)| w == waveform) {
+                    Some(index) => index,
+                    None => {
+                        let tables = WaveformTables::new(waveform, sample_rate);
+                        waveform_tables.push((waveform, tables));
+                        waveform_tables.len() - 1
+                    }
+                };
+                Oscillator::from_tables(&waveform_tables[index].1, frequency)
+            }
+            Synthesis::NoiseBand => Oscillator::noise_band(frequency, bandwidth, sample_rate),
+        };
+        let mut section_interpreter = SectionInterpreter::new(oscillator, y_start, y_end)
+            .with_columns_per_breakpoint(layer_spec.columns_per_breakpoint)
+            .with_amplitude_strategy(layer_spec.amplitude.strategy())
+            .with_transfer(layer_spec.transfer);
+        if let Some(timbre_layer_id) = data_layer_ids.timbre {
+            section_interpreter = section_interpreter.with_timbre_layer(timbre_layer_id);
+        }
+        if let Some(vibrato) = layer_spec.vibrato {
+            section_interpreter = section_interpreter.with_vibrato(generate_modulation(
+                vibrato,
+                data_layer_ids.vibrato_rate,
+                data_layer_ids.vibrato_depth,
+                sample_rate,
+            ));
+        }
+        if let Some(tremolo) = layer_spec.tremolo {
+            section_interpreter = section_interpreter.with_tremolo(generate_modulation(
+                tremolo,
+                data_layer_ids.tremolo_rate,
+                data_layer_ids.tremolo_depth,
+                sample_rate,
+            ));
+        }
+        if let Some(adsr) = layer_spec.envelope {
+            section_interpreter = section_interpreter
+                .with_envelope(Envelope::new(adsr, sample_rate), layer_spec.onset_threshold);
+        }
+        if let Some(filter_spec) = layer_spec.section_filter {
+            section_interpreter = section_interpreter.with_filter(generate_filter(
+                filter_spec,
+                data_layer_ids.section_filter_cutoff,
+                data_layer_ids.section_filter_q,
+                sample_rate,
+            ));
+        }
+        section_interpreters.push(Box::new(section_interpreter));
+    }
+
+    section_interpreters
+}
+
+fn generate_triggered_interpreters(
+    layer_metadata: ImgLayerMetadata,
+    layer_spec: &LayerSpec,
+    instrument_spec: &InstrumentSpec,
+    data_layer_ids: DataLayerIds,
+    sample_rate: u32,
+) -> Vec<Box<dyn LayerInterpreter>> {
+    let frequencies = section_frequencies(layer_spec);
+    let sections = section_ranges(layer_metadata, layer_spec.sections);
+    frequencies
+        .iter()
+        .zip(sections)
+        .map(|(&frequency, (y_start, y_end))| {
+            let instrument = instrument_spec.instrument(frequency, sample_rate);
+            let mut interpreter =
+                TriggeredInterpreter::new(instrument, y_start, y_end, layer_spec.onset_threshold)
+                    .with_columns_per_breakpoint(layer_spec.columns_per_breakpoint)
+                    .with_amplitude_strategy(layer_spec.amplitude.strategy())
+                    .with_transfer(layer_spec.transfer);
+            if let Some(filter_spec) = layer_spec.section_filter {
+                interpreter = interpreter.with_filter(generate_filter(
+                    filter_spec,
+                    data_layer_ids.section_filter_cutoff,
+                    data_layer_ids.section_filter_q,
+                    sample_rate,
+                ));
+            }
+            Box::new(interpreter) as Box<dyn LayerInterpreter>
+        })
+        .collect()
+}
+
+fn section_frequencies(layer_spec: &LayerSpec) -> Vec<f32> {
+    layer_spec
+        .pitch
+        .expect("pitch maps are resolved in PipelineBuilder::build")
+        .frequencies(layer_spec.sections)
+}
+
+/// The range of frequencies spanned by each section, in Hz
+///
+/// Neighboring sections meet at the geometric mean of their frequencies.
+/// The outermost sections reach as far beyond their frequency as within it,
+/// and a lone section spans an octave. Every span is at least 1 Hz wide.
+fn section_bandwidths(frequencies: &[f32]) -> Vec<f32> {
+    (0..frequencies.len())
+        .map(|i| {
+            let frequency = frequencies[i];
+            let boundary = |j: usize| (frequency * frequencies[j]).sqrt();
+            let mirrored = |boundary: f32| frequency * frequency / boundary;
+            let (first, second) = match (i.checked_sub(1), frequencies.get(i + 1)) {
+                (Some(previous), Some(_)) => (boundary(previous), boundary(i + 1)),
+                (Some(previous), None) => (boundary(previous), mirrored(boundary(previous))),
+                (None, Some(_)) => (boundary(i + 1), mirrored(boundary(i + 1))),
+                (None, None) => (frequency * 2f32.sqrt(), frequency / 2f32.sqrt()),
+            };
+            (first - second).abs().max(1.)
+        })
+        .collect()
+}
+
+/// The `(y_start, y_end)` rows of each of `section_count` sections of a layer,
+/// whose heights differ by at most one row
+fn section_ranges(layer_metadata: ImgLayerMetadata, section_count: usize) -> Vec<(usize, usize)> {
+    let height = layer_metadata.y_end - layer_metadata.y_start;
+    let boundary = |i: usize| {
+        clamp(
+            layer_metadata.y_start + (height * i / section_count),
+            layer_metadata.y_start,
+            layer_metadata.y_end,
+        )
+    };
+    (0..section_count).map(|i| (boundary(i), boundary(i + 1))).collect()
+}
+
+fn generate_modulation(
+    lfo_spec: LfoSpec,
+    rate_layer_id: Option<ImgLayerId>,
+    depth_layer_id: Option<ImgLayerId>,
+    sample_rate: u32,
+) -> Modulation {
+    let mut modulation = Modulation::new(Lfo::new(sample_rate), lfo_spec.rate, lfo_spec.depth);
+    if let Some(rate_layer_id) = rate_layer_id {
+        modulation = modulation.with_rate_layer(rate_layer_id);
+    }
+    if let Some(depth_layer_id) = depth_layer_id {
+        modulation = modulation.with_depth_layer(depth_layer_id);
+    }
+    modulation
+}
+
+fn generate_filter(
+    filter_spec: FilterSpec,
+    cutoff_layer_id: Option<ImgLayerId>,
+    q_layer_id: Option<ImgLayerId>,
+    sample_rate: u32,
+) -> AutomatedFilter {
+    let mut filter =
+        AutomatedFilter::new(filter_spec.kind, filter_spec.cutoff, filter_spec.q, sample_rate);
+    if let Some(cutoff_layer_id) = cutoff_layer_id {
+        filter = filter.with_cutoff_layer(cutoff_layer_id, filter_spec.dark_cutoff);
+    }
+    if let Some(q_layer_id) = q_layer_id {
+        filter = filter.with_q_layer(q_layer_id);
+    }
+    filter
+}
+
+/// Filter each mixed chunk until `mixed` closes, reading the filter's layers,
+/// if any, from the packets of the bus channel
+fn filter_bus(
+    mixed: Receiver<Chunk>,
+    img_packets: Option<Receiver<ImgPacket>>,
+    mut filter: AutomatedFilter,
+    img_height: usize,
+    filtered_sender: Sender<Chunk>,
+) {
+    let no_layers = ImgPacket::new();
+    for mut chunk in mixed {
+        match img_packets {
+            Some(ref img_packets) => match img_packets.recv() {
+                Ok(img_packet) => filter.apply(&mut chunk, 0, img_height, &img_packet),
+                Err(_) => return,
+            },
+            None => filter.apply(&mut chunk, 0, img_height, &no_layers),
+        }
+        if filtered_sender.send(chunk).is_err() {
+            return;
+        }
+    }
+}
+
+fn generate_pitch_tracking_interpreter(
+    layer_metadata: ImgLayerMetadata,
+    layer_spec: &LayerSpec,
+    tracking: Tracking,
+    sample_rate: u32,
+) -> Box<dyn LayerInterpreter> {
+    // Oscillators are retuned before they are first heard
+    let tables = WaveformTables::new(&layer_spec.waveform, sample_rate);
+    let oscillators = (0..tracking.lines)
+        .map(|_| Oscillator::from_tables(&tables, tracking.low))
+        .collect();
+    let interpreter = PitchTrackingInterpreter::new(
+        oscillators,
+        layer_metadata.y_start,
+        layer_metadata.y_end,
+        tracking.low,
+        tracking.high,
+    );
+    Box::new(
+        interpreter
+            .with_columns_per_breakpoint(layer_spec.columns_per_breakpoint)
+            .with_transfer(layer_spec.transfer),
+    )
+}
+
+fn clamp<T: Ord>(val: T, min: T, max: T) -> T {
+    match val.cmp(&min) {
+        Less => min,
+        _ => match val.cmp(&max) {
+            Greater => max,
+            _ => val,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use amplitude::{Transfer, TransferCurve};
+    use envelope::{Adsr, EnvelopeCurve};
+    use fft;
+    use filter::FilterKind;
+    use sampler::Recording;
+    use soundfont::SoundFont;
+    use test_utils::*;
+    use wav_streamer::WavStreamer;
+    use wavetable::{Wavetable, TABLE_LEN};
+
+    #[test]
+    fn test_clamp_below_min() {
+        assert_eq!(clamp(-1, 0, 10), 0);
+    }
+
+    #[test]
+    fn test_clamp_above_max() {
+        assert_eq!(clamp(11, 0, 10), 10);
+    }
+
+    #[test]
+    fn test_clamp_within_bounds() {
+        assert_eq!(clamp(5, 0, 10), 5);
+    }
+
+    #[test]
+    fn build_without_image_fails() {
+        match Pipeline::builder().layer(LayerSpec::new(1)).build() {
+            Err(PipelineError::MissingImage) => {}
+            _ => panic!("expected MissingImage"),
+        }
+    }
+
+    #[test]
+    fn build_without_layers_fails() {
+        match Pipeline::builder().image("resources/horizontal_line.png").build() {
+            Err(PipelineError::NoLayers) => {}
+            _ => panic!("expected NoLayers"),
+        }
+    }
+
+    #[test]
+    fn build_with_zero_timing_fails() {
+        let builder = || {
+            Pipeline::builder()
+                .image("resources/horizontal_line.png")
+                .pitch_map(PitchMap::HarmonicSeries { fundamental: 100. })
+                .layer(LayerSpec::new(1))
+        };
+        match builder().sample_rate(0).build() {
+            Err(PipelineError::InvalidSampleRate) => {}
+            _ => panic!("expected InvalidSampleRate"),
+        }
+        match builder().samples_per_pixel(0).build() {
+            Err(PipelineError::InvalidSamplesPerPixel) => {}
+            _ => panic!("expected InvalidSamplesPerPixel"),
+        }
+        match builder().chunk_width(0).build() {
+            Err(PipelineError::InvalidChunkWidth) => {}
+            _ => panic!("expected InvalidChunkWidth"),
+        }
+    }
+
+    #[test]
+    fn build_with_invalid_band_fails() {
+        let mut band = BandSpec::new(0.5, 0.25);
+        band.layers.push(LayerSpec::new(1));
+        let result = Pipeline::builder()
+            .image("resources/horizontal_line.png")
+            .pitch_map(PitchMap::HarmonicSeries { fundamental: 100. })
+            .band(band)
+            .build();
+        match result {
+            Err(PipelineError::InvalidBand(0)) => {}
+            _ => panic!("expected InvalidBand"),
+        }
+    }
+
+    #[test]
+    fn build_with_zero_columns_per_breakpoint_fails() {
+        let mut layer = LayerSpec::tracking(1, 110., 880.);
+        layer.columns_per_breakpoint = 0;
+        let result = Pipeline::builder()
+            .image("resources/horizontal_line.png")
+            .layer(layer)
+            .build();
+        match result {
+            Err(PipelineError::InvalidColumnsPerBreakpoint(0)) => {}
+            _ => panic!("expected InvalidColumnsPerBreakpoint"),
+        }
+    }
+
+    #[test]
+    fn build_with_invalid_percentile_fails() {
+        let mut layer = LayerSpec::new(1);
+        layer.amplitude = Amplitude::Percentile(1.5);
+        let result = Pipeline::builder()
+            .image("resources/horizontal_line.png")
+            .pitch_map(PitchMap::HarmonicSeries { fundamental: 100. })
+            .layer(layer)
+            .build();
+        match result {
+            Err(PipelineError::InvalidAmplitude(0)) => {}
+            _ => panic!("expected InvalidAmplitude"),
+        }
+    }
+
+    #[test]
+    fn build_with_invalid_transfer_fails() {
+        let invalid_transfers = vec![
+            Transfer { curve: TransferCurve::Gamma(0.), ..Transfer::default() },
+            Transfer { curve: TransferCurve::Gamma(-1.), ..Transfer::default() },
+            Transfer {
+                curve: TransferCurve::Decibels { floor: -60., ceiling: 6. },
+                ..Transfer::default()
+            },
+            Transfer {
+                curve: TransferCurve::Decibels { floor: -20., ceiling: -20. },
+                ..Transfer::default()
+            },
+            Transfer { gate: 1.5, ..Transfer::default() },
+            Transfer { gate: -0.1, ..Transfer::default() },
+        ];
+        for transfer in invalid_transfers {
+            let mut layer = LayerSpec::new(1);
+            layer.transfer = transfer;
+            let result = Pipeline::builder()
+                .image("resources/horizontal_line.png")
+                .pitch_map(PitchMap::HarmonicSeries { fundamental: 100. })
+                .layer(layer)
+                .build();
+            match result {
+                Err(PipelineError::InvalidTransfer(0)) => {}
+                _ => panic!("expected InvalidTransfer for {:?}", transfer),
+            }
+        }
+    }
+
+    #[test]
+    fn build_with_invalid_color_key_fails() {
+        let color_key = Extractor::ColorKey {
+            color: [255, 0, 0],
+            tolerance: 0.,
+        };
+        let mut layer = LayerSpec::new(1);
+        layer.timbre = Some(color_key);
+        let result = Pipeline::builder()
+            .image("resources/horizontal_line.png")
+            .pitch_map(PitchMap::HarmonicSeries { fundamental: 100. })
+            .layer(LayerSpec::new(1))
+            .layer(layer)
+            .build();
+        match result {
+            Err(PipelineError::InvalidExtractor(1)) => {}
+            _ => panic!("expected InvalidExtractor"),
+        }
+        let result = Pipeline::builder()
+            .image("resources/horizontal_line.png")
+            .pitch_map(PitchMap::HarmonicSeries { fundamental: 100. })
+            .layer(LayerSpec::new(1))
+            .filter(FilterSpec {
+                cutoff_layer: Some(color_key),
+                ..filter_spec(FilterKind::LowPass, 1000.)
+            })
+            .build();
+        match result {
+            Err(PipelineError::InvalidBusFilter) => {}
+            _ => panic!("expected InvalidBusFilter"),
+        }
+    }
+
+    #[test]
+    fn build_with_too_many_sections_fails() {
+        let img_height = image::open("resources/horizontal_line.png").unwrap().to_rgba().height();
+        let mut band = BandSpec::new(0., 0.5);
+        band.layers.push(LayerSpec::new(img_height as usize));
+        let result = Pipeline::builder()
+            .image("resources/horizontal_line.png")
+            .pitch_map(PitchMap::HarmonicSeries { fundamental: 100. })
+            .layer(LayerSpec::new(img_height as usize))
+            .band(band)
+            .build();
+        match result {
+            Err(PipelineError::TooManySections(1)) => {}
+            _ => panic!("expected TooManySections"),
+        }
+    }
+
+    #[test]
+    fn section_ranges_spread_leftover_rows() {
+        let layer_metadata = ImgLayerMetadata {
+            img_layer_id: 0,
+            y_start: 10,
+            y_end: 20,
+            total_img_height: 20,
+        };
+        assert_eq!(
+            section_ranges(layer_metadata, 4),
+            vec![(10, 12), (12, 15), (15, 17), (17, 20)]
+        );
+        assert_eq!(section_ranges(layer_metadata, 10)[9], (19, 20));
+    }
+
+    #[test]
+    fn build_without_pitch_map_fails() {
+        let result = Pipeline::builder()
+            .image("resources/horizontal_line.png")
+            .layer(LayerSpec::new(1))
+            .build();
+        match result {
+            Err(PipelineError::MissingPitchMap(0)) => {}
+            _ => panic!("expected MissingPitchMap"),
+        }
+    }
+
+    #[test]
+    fn build_with_invalid_pitch_map_fails() {
+        let result = Pipeline::builder()
+            .image("resources/horizontal_line.png")
+            .pitch_map(PitchMap::Geometric { low: 0., high: 400. })
+            .layer(LayerSpec::new(1))
+            .build();
+        match result {
+            Err(PipelineError::InvalidPitchMap(0)) => {}
+            _ => panic!("expected InvalidPitchMap"),
+        }
+    }
+
+    #[test]
+    fn build_with_invalid_waveform_fails() {
+        let mut layer = LayerSpec::new(1);
+        layer.waveform = Waveform::Morph(vec![Waveform::Sine, Waveform::Pulse { duty: 1.5 }]);
+        let result = Pipeline::builder()
+            .image("resources/horizontal_line.png")
+            .pitch_map(PitchMap::HarmonicSeries { fundamental: 100. })
+            .layer(layer)
+            .build();
+        match result {
+            Err(PipelineError::InvalidWaveform(0)) => {}
+            _ => panic!("expected InvalidWaveform"),
+        }
+    }
+
+    #[test]
+    fn build_with_missing_wavetable_fails() {
+        let mut layer = LayerSpec::new(1);
+        layer.waveform = Waveform::Wavetable(Wavetable::from(PathBuf::from("missing.wav")));
+        let result = Pipeline::builder()
+            .image("resources/horizontal_line.png")
+            .pitch_map(PitchMap::HarmonicSeries { fundamental: 100. })
+            .layer(LayerSpec::new(1))
+            .layer(layer)
+            .build();
+        match result {
+            Err(PipelineError::Wavetable(1, _)) => {}
+            _ => panic!("expected Wavetable error"),
+        }
+    }
+
+    #[test]
+    fn build_loads_wavetables() {
+        let mut layer = LayerSpec::new(1);
+        let path = PathBuf::from("resources/wavetables/soft_saw.wav");
+        layer.waveform = Waveform::Wavetable(Wavetable::from(path));
+        let pipeline = Pipeline::builder()
+            .image("resources/horizontal_line.png")
+            .pitch_map(PitchMap::HarmonicSeries { fundamental: 100. })
+            .layer(layer)
+            .build()
+            .unwrap();
+        match pipeline.bands[0].layers[0].waveform {
+            Waveform::Wavetable(ref wavetable) => assert_eq!(wavetable.cycle().len(), TABLE_LEN),
+            _ => panic!("expected a wavetable"),
+        }
+    }
+
+    #[test]
+    fn render_produces_samples_for_every_pixel_column() {
+        let samples_per_pixel = 10;
+        let render = Pipeline::builder()
+            .image("resources/horizontal_line.png")
+            .layer(LayerSpec::new(4))
+            .pitch_map(PitchMap::HarmonicSeries { fundamental: 100. })
+            .samples_per_pixel(samples_per_pixel)
+            .build()
+            .unwrap()
+            .render();
+        let img_width = image::open("resources/horizontal_line.png").unwrap().to_rgba().width();
+        let samples = render.collect_samples();
+        assert_eq!(samples.len(), img_width as usize * samples_per_pixel);
+    }
+
+    #[test]
+    fn stream_writes_at_render_sample_rate() {
+        let path = env::temp_dir().join("spectrophoner_pipeline_stream.wav");
+        let render = Pipeline::builder()
+            .image("resources/horizontal_line.png")
+            .layer(LayerSpec::new(4))
+            .pitch_map(PitchMap::HarmonicSeries { fundamental: 100. })
+            .sample_rate(22050)
+            .samples_per_pixel(10)
+            .build()
+            .unwrap()
+            .render();
+        render.stream(&WavStreamer::<f32>::new(path.to_str().unwrap().to_string()));
+        let reader = hound::WavReader::open(&path).unwrap();
+        assert_eq!(reader.spec().sample_rate, 22050);
+    }
+
+    #[test]
+    fn build_with_wrong_number_of_section_waveforms_fails() {
+        let mut layer = LayerSpec::new(3);
+        layer.section_waveforms = Some(vec![Waveform::Sine, Waveform::Saw]);
+        let result = Pipeline::builder()
+            .image("resources/horizontal_line.png")
+            .pitch_map(PitchMap::HarmonicSeries { fundamental: 100. })
+            .layer(layer)
+            .build();
+        match result {
+            Err(PipelineError::InvalidSectionWaveforms(0)) => {}
+            _ => panic!("expected InvalidSectionWaveforms"),
+        }
+    }
+
+    #[test]
+    fn build_loads_section_wavetables() {
+        let mut layer = LayerSpec::new(2);
+        let wavetable = Wavetable::from(PathBuf::from("resources/wavetables/soft_saw.wav"));
+        layer.section_waveforms = Some(vec![Waveform::Sine, Waveform::Wavetable(wavetable)]);
+        let pipeline = Pipeline::builder()
+            .image("resources/horizontal_line.png")
+            .pitch_map(PitchMap::HarmonicSeries { fundamental: 100. })
+            .layer(layer)
+            .build()
+            .unwrap();
+        match *pipeline.bands[0].layers[0].section_waveform(1) {
+            Waveform::Wavetable(ref wavetable) => assert_eq!(wavetable.cycle().len(), TABLE_LEN),
+            _ => panic!("expected a wavetable"),
+        }
+    }
+
+    #[test]
+    fn build_with_empty_morph_fails() {
+        let mut layer = LayerSpec::new(1);
+        layer.waveform = Waveform::Morph(vec![]);
+        let result = Pipeline::builder()
+            .image("resources/horizontal_line.png")
+            .pitch_map(PitchMap::HarmonicSeries { fundamental: 100. })
+            .layer(layer)
+            .build();
+        match result {
+            Err(PipelineError::EmptyMorph(0)) => {}
+            _ => panic!("expected EmptyMorph"),
+        }
+    }
+
+    #[test]
+    fn build_with_deep_tremolo_fails() {
+        let mut layer = LayerSpec::new(1);
+        layer.tremolo = Some(LfoSpec {
+            rate: 3.,
+            depth: 1.5,
+            rate_layer: None,
+            depth_layer: None,
+        });
+        let result = Pipeline::builder()
+            .image("resources/horizontal_line.png")
+            .pitch_map(PitchMap::HarmonicSeries { fundamental: 100. })
+            .layer(layer)
+            .build();
+        match result {
+            Err(PipelineError::InvalidModulation(0)) => {}
+            _ => panic!("expected InvalidModulation"),
+        }
+    }
+
+    fn filter_spec(kind: FilterKind, cutoff: f32) -> FilterSpec {
+        FilterSpec {
+            kind,
+            cutoff,
+            q: 0.707,
+            cutoff_layer: None,
+            dark_cutoff: 20.,
+            q_layer: None,
+        }
+    }
+
+    #[test]
+    fn render_with_filters() {
+        let samples_per_pixel = 100;
+        let render_with = |bus_filter: Option<FilterSpec>| {
+            let mut layer = LayerSpec::new(4);
+            layer.filter = Some(FilterSpec {
+                cutoff_layer: Some(Extractor::Blue),
+                ..filter_spec(FilterKind::LowPass, 5000.)
+            });
+            layer.section_filter = Some(FilterSpec {
+                q_layer: Some(Extractor::Red),
+                ..filter_spec(FilterKind::BandPass, 300.)
+            });
+            let mut builder = Pipeline::builder()
+                .image("resources/horizontal_line.png")
+                .layer(layer)
+                .pitch_map(PitchMap::HarmonicSeries { fundamental: 100. })
+                .samples_per_pixel(samples_per_pixel);
+            if let Some(bus_filter) = bus_filter {
+                builder = builder.filter(bus_filter);
+            }
+            builder.build().unwrap().render().collect_samples()
+        };
+        let power = |samples: &[f32]| samples.iter().map(|s| s * s).sum::<f32>();
+        let img_width = image::open("resources/horizontal_line.png").unwrap().to_rgba().width();
+
+        let unfiltered = render_with(None);
+        assert_eq!(unfiltered.len(), img_width as usize * samples_per_pixel);
+        assert!(power(&unfiltered) > 0.);
+        // Every section lies well below a bus high-pass whose cutoff follows the image
+        let filtered = render_with(Some(FilterSpec {
+            cutoff_layer: Some(Extractor::Grayscale),
+            dark_cutoff: 10000.,
+            ..filter_spec(FilterKind::HighPass, 10000.)
+        }));
+        assert_eq!(filtered.len(), unfiltered.len());
+        assert!(power(&filtered) < power(&unfiltered) / 100.);
+    }
+
+    #[test]
+    fn build_with_invalid_filter_fails() {
+        let mut layer = LayerSpec::new(1);
+        layer.section_filter = Some(filter_spec(FilterKind::Notch, 0.));
+        let result = Pipeline::builder()
+            .image("resources/horizontal_line.png")
+            .pitch_map(PitchMap::HarmonicSeries { fundamental: 100. })
+            .layer(layer)
+            .build();
+        match result {
+            Err(PipelineError::InvalidFilter(0)) => {}
+            _ => panic!("expected InvalidFilter"),
+        }
+    }
+
+    #[test]
+    fn build_with_section_filter_on_tracking_fails() {
+        let mut layer = LayerSpec::new(0);
+        layer.tracking = Some(Tracking {
+            lines: 1,
+            low: 100.,
+            high: 400.,
+        });
+        layer.section_filter = Some(filter_spec(FilterKind::LowPass, 1000.));
+        let result = Pipeline::builder()
+            .image("resources/horizontal_line.png")
+            .layer(layer)
+            .build();
+        match result {
+            Err(PipelineError::InvalidFilter(0)) => {}
+            _ => panic!("expected InvalidFilter"),
+        }
+    }
+
+    #[test]
+    fn build_with_invalid_bus_filter_fails() {
+        let result = Pipeline::builder()
+            .image("resources/horizontal_line.png")
+            .pitch_map(PitchMap::HarmonicSeries { fundamental: 100. })
+            .layer(LayerSpec::new(1))
+            .filter(FilterSpec {
+                q: -1.,
+                ..filter_spec(FilterKind::LowPass, 1000.)
+            })
+            .build();
+        match result {
+            Err(PipelineError::InvalidBusFilter) => {}
+            _ => panic!("expected InvalidBusFilter"),
+        }
+    }
+
+    #[test]
+    fn section_bandwidths_meet_between_sections() {
+        // Each section of a geometric map spans the same fraction of an octave
+        let frequencies = PitchMap::Geometric { low: 100., high: 400. }.frequencies(3);
+        let expected: Vec<f32> = frequencies
+            .iter()
+            .map(|frequency| frequency * (2f32.sqrt() - 1. / 2f32.sqrt()))
+            .collect();
+        assert_almost_eq_by_element(section_bandwidths(&frequencies), expected);
+        // Harmonic sections span from halfway to each neighbor in pitch
+        assert_almost_eq_by_element(
+            section_bandwidths(&[300., 200., 100.]),
+            vec![
+                (300. * 300. / (300f32 * 200.).sqrt()) - (300f32 * 200.).sqrt(),
+                (300f32 * 200.).sqrt() - (200f32 * 100.).sqrt(),
+                100. * (2f32.sqrt() - 1. / 2f32.sqrt()),
+            ],
+        );
+        assert_almost_eq_by_element(
+            section_bandwidths(&[440.]),
+            vec![440. * (2f32.sqrt() - 1. / 2f32.sqrt())],
+        );
+        assert_almost_eq_by_element(section_bandwidths(&[100., 100.]), vec![1., 1.]);
+    }
+
+    #[test]
+    fn build_with_noise_band_instrument_fails() {
+        let mut layer = LayerSpec::new(1);
+        layer.synthesis = Synthesis::NoiseBand;
+        layer.instrument = Some(InstrumentSpec::PluckedString {
+            damping: 0.01,
+            stretch: 0.5,
+        });
+        let result = Pipeline::builder()
+            .image("resources/horizontal_line.png")
+            .pitch_map(PitchMap::HarmonicSeries { fundamental: 100. })
+            .layer(layer)
+            .build();
+        match result {
+            Err(PipelineError::InvalidSynthesis(0)) => {}
+            _ => panic!("expected InvalidSynthesis"),
+        }
+    }
+
+    #[test]
+    fn build_with_missing_preset_fails() {
+        let mut layer = LayerSpec::new(1);
+        layer.instrument = Some(InstrumentSpec::SoundFont {
+            file: SoundFont::from(PathBuf::from("resources/soundfonts/saw_and_sine.sf2")),
+            bank: 0,
+            preset: 7,
+        });
+        let result = Pipeline::builder()
+            .image("resources/horizontal_line.png")
+            .pitch_map(PitchMap::HarmonicSeries { fundamental: 100. })
+            .layer(layer)
+            .build();
+        match result {
+            Err(PipelineError::MissingPreset(0)) => {}
+            _ => panic!("expected MissingPreset error"),
+        }
+    }
+
+    #[test]
+    fn build_with_invalid_soundfont_fails() {
+        let mut layer = LayerSpec::new(1);
+        layer.instrument = Some(InstrumentSpec::SoundFont {
+            file: SoundFont::from(PathBuf::from("resources/wavetables/soft_saw.wav")),
+            bank: 0,
+            preset: 0,
+        });
+        let result = Pipeline::builder()
+            .image("resources/horizontal_line.png")
+            .pitch_map(PitchMap::HarmonicSeries { fundamental: 100. })
+            .layer(layer)
+            .build();
+        match result {
+            Err(PipelineError::SoundFont(0, SoundFontError::Format(_))) => {}
+            _ => panic!("expected SoundFont error"),
+        }
+    }
+
+    #[test]
+    fn build_with_missing_recording_fails() {
+        let mut layer = LayerSpec::new(1);
+        layer.instrument = Some(InstrumentSpec::Sample {
+            recording: Recording::from(PathBuf::from("resources/missing.wav")),
+            root: 440.,
+            looping: false,
+        });
+        let result = Pipeline::builder()
+            .image("resources/horizontal_line.png")
+            .pitch_map(PitchMap::HarmonicSeries { fundamental: 100. })
+            .layer(layer)
+            .build();
+        match result {
+            Err(PipelineError::Recording(0, _)) => {}
+            _ => panic!("expected Recording error"),
+        }
+    }
+
+    #[test]
+    fn build_with_invalid_instrument_fails() {
+        let mut layer = LayerSpec::new(1);
+        layer.instrument = Some(InstrumentSpec::PluckedString {
+            damping: 2.,
+            stretch: 0.5,
+        });
+        let result = Pipeline::builder()
+            .image("resources/horizontal_line.png")
+            .pitch_map(PitchMap::HarmonicSeries { fundamental: 100. })
+            .layer(layer)
+            .build();
+        match result {
+            Err(PipelineError::InvalidInstrument(0)) => {}
+            _ => panic!("expected InvalidInstrument"),
+        }
+    }
+
+    #[test]
+    fn build_instrument_layer_with_section_voicing_fails() {
+        let lfo = LfoSpec {
+            rate: 5.,
+            depth: 0.5,
+            rate_layer: None,
+            depth_layer: None,
+        };
+        let plucked_string = || {
+            let mut layer = LayerSpec::new(1);
+            layer.instrument = Some(InstrumentSpec::PluckedString {
+                damping: 0.01,
+                stretch: 0.5,
+            });
+            layer
+        };
+        let layers = vec![
+            LayerSpec {
+                envelope: Some(Adsr {
+                    attack: 0.,
+                    decay: 0.1,
+                    sustain: 0.5,
+                    release: 0.1,
+                    curve: EnvelopeCurve::Linear,
+                }),
+                ..plucked_string()
+            },
+            LayerSpec {
+                vibrato: Some(lfo),
+                ..plucked_string()
+            },
+            LayerSpec {
+                tremolo: Some(lfo),
+                ..plucked_string()
+            },
+            LayerSpec {
+                timbre: Some(Extractor::Red),
+                ..plucked_string()
+            },
+        ];
+        for layer in layers {
+            let result = Pipeline::builder()
+                .image("resources/horizontal_line.png")
+                .pitch_map(PitchMap::HarmonicSeries { fundamental: 100. })
+                .layer(layer)
+                .build();
+            match result {
+                Err(PipelineError::InvalidInstrument(0)) => {}
+                _ => panic!("expected InvalidInstrument"),
+            }
+        }
+    }
+
+    #[test]
+    fn build_tracking_layer_without_pitch_map() {
+        let result = Pipeline::builder()
+            .image("resources/horizontal_line.png")
+            .layer(LayerSpec::tracking(1, 110., 880.))
+            .build();
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn build_without_voices_fails() {
+        let result = Pipeline::builder()
+            .image("resources/horizontal_line.png")
+            .pitch_map(PitchMap::HarmonicSeries { fundamental: 100. })
+            .layer(LayerSpec::new(0))
+            .build();
+        match result {
+            Err(PipelineError::NoVoices(0)) => {}
+            _ => panic!("expected NoVoices"),
+        }
+    }
+
+    #[test]
+    fn build_with_invalid_tracking_fails() {
+        let result = Pipeline::builder()
+            .image("resources/horizontal_line.png")
+            .layer(LayerSpec::tracking(1, 0., 880.))
+            .build();
+        match result {
+            Err(PipelineError::InvalidTracking(0)) => {}
+            _ => panic!("expected InvalidTracking"),
+        }
+    }
+
+    #[test]
+    fn build_tracking_layer_with_section_voicing_fails() {
+        let lfo = LfoSpec {
+            rate: 5.,
+            depth: 0.5,
+            rate_layer: None,
+            depth_layer: None,
+        };
+        let envelope = Adsr {
+            attack: 0.,
+            decay: 0.1,
+            sustain: 0.5,
+            release: 0.1,
+            curve: EnvelopeCurve::Linear,
+        };
+        let layers = vec![
+            LayerSpec {
+                envelope: Some(envelope),
+                ..LayerSpec::tracking(1, 110., 880.)
+            },
+            LayerSpec {
+                vibrato: Some(lfo),
+                ..LayerSpec::tracking(1, 110., 880.)
+            },
+            LayerSpec {
+                tremolo: Some(lfo),
+                ..LayerSpec::tracking(1, 110., 880.)
+            },
+            LayerSpec {
+                timbre: Some(Extractor::Red),
+                ..LayerSpec::tracking(1, 110., 880.)
+            },
+            LayerSpec {
+                amplitude: Amplitude::Max,
+                ..LayerSpec::tracking(1, 110., 880.)
+            },
+        ];
+        for layer in layers {
+            let result = Pipeline::builder()
+                .image("resources/horizontal_line.png")
+                .layer(layer)
+                .build();
+            match result {
+                Err(PipelineError::InvalidTracking(0)) => {}
+                _ => panic!("expected InvalidTracking"),
+            }
+        }
+    }
+
+    #[test]
+    fn build_with_invalid_envelope_fails() {
+        let layer = LayerSpec {
+            envelope: Some(Adsr {
+                attack: 0.,
+                decay: 0.1,
+                sustain: 1.5,
+                release: 0.1,
+                curve: EnvelopeCurve::Linear,
+            }),
+            ..LayerSpec::new(1)
+        };
+        let result = Pipeline::builder()
+            .image("resources/horizontal_line.png")
+            .pitch_map(PitchMap::HarmonicSeries { fundamental: 100. })
+            .layer(layer)
+            .build();
+        match result {
+            Err(PipelineError::InvalidEnvelope(0)) => {}
+            _ => panic!("expected InvalidEnvelope"),
+        }
+    }
+
+    #[test]
+    fn render_mixes_bands() {
+        let samples_per_pixel = 10;
+        let mut top_band = BandSpec::new(0., 0.5);
+        top_band.layers.push(LayerSpec::new(2));
+        let mut bottom_band = BandSpec::new(0.5, 1.);
+        bottom_band.layers.push(LayerSpec::new(3));
+        let render = Pipeline::builder()
+            .image("resources/horizontal_line.png")
+            .band(top_band)
+            .band(bottom_band)
+            .pitch_map(PitchMap::HarmonicSeries { fundamental: 100. })
+            .samples_per_pixel(samples_per_pixel)
+            .build()
+            .unwrap()
+            .render();
+        let img_width = image::open("resources/horizontal_line.png").unwrap().to_rgba().width();
+        let samples = render.collect_samples();
+        assert_eq!(samples.len(), img_width as usize * samples_per_pixel);
+    }
+
+    /// Render `layer` alone over a thin band lying within horizontal_line.png's line,
+    /// with the band's first section at `fundamental`
+    fn render_line(layer: LayerSpec, fundamental: f32) -> Vec<f32> {
+        let mut band = BandSpec::new(0.47, 0.5);
+        band.layers.push(layer);
+        Pipeline::builder()
+            .image("resources/horizontal_line.png")
+            .band(band)
+            .pitch_map(PitchMap::HarmonicSeries { fundamental })
+            .samples_per_pixel(100)
+            .build()
+            .unwrap()
+            .render()
+            .collect_samples()
+    }
+
+    /// Fraction of the power of the longest power-of-two prefix of `samples`
+    /// which lies between `low` and `high` Hz
+    fn power_fraction_between(samples: &[f32], low: f32, high: f32) -> f32 {
+        let len = (samples.len() + 1).next_power_of_two() / 2;
+        let mut re: Vec<f64> = samples[..len].iter().map(|s| *s as f64).collect();
+        let mut im = vec![0.; len];
+        fft::fft(&mut re, &mut im, false);
+        let bin_width = 44100. / len as f32;
+        let (mut in_band, mut total) = (0., 0.);
+        for bin in 1..len / 2 {
+            let power = (re[bin] * re[bin]) + (im[bin] * im[bin]);
+            let frequency = bin as f32 * bin_width;
+            if frequency >= low && frequency <= high {
+                in_band += power;
+            }
+            total += power;
+        }
+        (in_band / total) as f32
+    }
+
+    /// Estimate the frequency of `samples` by counting rising zero crossings
+    fn measure_frequency(samples: &[f32]) -> f32 {
+        let rising_crossings = samples
+            .windows(2)
+            .filter(|pair| pair[0] < 0. && pair[1] >= 0.)
+            .count();
+        rising_crossings as f32 * 44100. / samples.len() as f32
+    }
+
+    fn rms(samples: &[f32]) -> f32 {
+        (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt()
+    }
+
+    #[test]
+    fn timbre_layer_morphs_spectrum() {
+        let morph = |timbre| {
+            let mut layer = LayerSpec::new(1);
+            layer.waveform = Waveform::Morph(vec![Waveform::Sine, Waveform::Saw]);
+            layer.timbre = timbre;
+            render_line(layer, 200.)
+        };
+        // Without a timbre layer the morph rests on its sine
+        let overtones = |samples: &[f32]| power_fraction_between(samples, 300., 22050.);
+        assert!(overtones(&morph(None)) < 0.01);
+        // The bright line moves it most of the way to the saw
+        assert!(overtones(&morph(Some(Extractor::Red))) > 0.2);
+    }
+
+    #[test]
+    fn modulation_layers_vary_pitch_and_loudness() {
+        let lfo = |rate, depth, rate_layer| LfoSpec {
+            rate,
+            depth,
+            rate_layer,
+            depth_layer: Some(Extractor::Value),
+        };
+        let render_with = |vibrato, tremolo| {
+            let mut layer = LayerSpec::new(1);
+            layer.vibrato = vibrato;
+            layer.tremolo = tremolo;
+            render_line(layer, 2000.)
+        };
+        // Spread of a measure over every 20ms window, from its lowest to its highest
+        let spread = |samples: &[f32], measure: &dyn Fn(&[f32]) -> f32| {
+            let measures: Vec<f32> = samples.chunks(882).map(measure).collect();
+            let highest = measures.iter().cloned().fold(0., f32::max);
+            let lowest = measures.iter().cloned().fold(f32::INFINITY, f32::min);
+            highest / lowest
+        };
+        let steady = render_with(None, None);
+        let vibrato = render_with(Some(lfo(5., 2., None)), None);
+        let tremolo = render_with(None, Some(lfo(3., 0.5, Some(Extractor::Green))));
+        assert!(spread(&steady, &measure_frequency) < 1.05);
+        assert!(spread(&steady, &rms) < 1.1);
+        // Two semitones either side of 2000 Hz, scaled by the line's brightness
+        assert!(spread(&vibrato, &measure_frequency) > 1.15);
+        assert!(spread(&vibrato, &rms) < 1.1);
+        assert!(spread(&tremolo, &measure_frequency) < 1.05);
+        assert!(spread(&tremolo, &rms) > 1.5);
+    }
+
+    #[test]
+    fn noise_band_energy_lies_in_its_band() {
+        let mut layer = LayerSpec::new(1);
+        layer.synthesis = Synthesis::NoiseBand;
+        let samples = render_line(layer, 1000.);
+        // The section's band spans half an octave either side of its frequency,
+        // and the band-pass skirts fall away beyond it
+        assert!(power_fraction_between(&samples, 707., 1414.) > 0.4);
+        assert!(power_fraction_between(&samples, 500., 2000.) > 0.6);
+    }
+
+    #[test]
+    fn section_waveforms_replace_layer_waveform() {
+        let mut layer = LayerSpec::new(1);
+        layer.section_waveforms = Some(vec![Waveform::WhiteNoise]);
+        let samples = render_line(layer, 1000.);
+        // The layer's sine would put nearly all its power around 1000 Hz,
+        // white noise spreads it up to Nyquist
+        assert!(power_fraction_between(&samples, 500., 2000.) < 0.2);
+        assert!(power_fraction_between(&samples, 5000., 20000.) > 0.5);
+    }
+
+    #[test]
+    fn tracking_layer_rises_with_ascending_line() {
+        let samples = Pipeline::builder()
+            .image("resources/ascending_line.png")
+            .layer(LayerSpec::tracking(1, 110., 880.))
+            .samples_per_pixel(100)
+            .build()
+            .unwrap()
+            .render()
+            .collect_samples();
+        // The line climbs from the bottom left corner to the top right
+        let eighth = samples.len() / 8;
+        assert!(measure_frequency(&samples[..eighth]) < 160.);
+        assert!(measure_frequency(&samples[samples.len() - eighth..]) > 650.);
+    }
+
+    #[test]
+    fn instruments_start_where_section_brightens() {
+        let instruments = vec![
+            InstrumentSpec::PluckedString {
+                damping: 0.01,
+                stretch: 0.5,
+            },
+            InstrumentSpec::Sample {
+                recording: Recording::from(PathBuf::from("resources/wavetables/soft_saw.wav")),
+                root: 73.5,
+                looping: true,
+            },
+            InstrumentSpec::SoundFont {
+                file: SoundFont::from(PathBuf::from("resources/soundfonts/saw_and_sine.sf2")),
+                bank: 0,
+                preset: 0,
+            },
+        ];
+        for instrument in instruments {
+            let mut layer = LayerSpec::new(1);
+            layer.instrument = Some(instrument);
+            layer.amplitude = Amplitude::Max;
+            // The line enters the top quarter of ascending_line.png towards its right edge
+            let mut band = BandSpec::new(0., 0.25);
+            band.layers.push(layer);
+            let samples = Pipeline::builder()
+                .image("resources/ascending_line.png")
+                .band(band)
+                .pitch_map(PitchMap::HarmonicSeries { fundamental: 200. })
+                .samples_per_pixel(100)
+                .build()
+                .unwrap()
+                .render()
+                .collect_samples();
+            let onset_column = samples.iter().position(|s| *s != 0.).unwrap() / 100;
+            assert!(onset_column > 440 && onset_column < 490, "onset at {}", onset_column);
+        }
+    }
+}
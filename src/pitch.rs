@@ -2,6 +2,16 @@ pub fn harmonic_series(fundamental: f32, partials: usize) -> Vec<f32> {
     (1..partials + 1).map(|p| p as f32 * fundamental).collect()
 }
 
+/// Generate `count` frequencies from `low` to `high` (inclusive) with
+/// a constant ratio between neighbors, i.e. evenly spaced in pitch.
+pub fn geometric_series(low: f32, high: f32, count: usize) -> Vec<f32> {
+    if count < 2 {
+        return vec![low; count];
+    }
+    let ratio = (high / low).powf(1. / (count - 1) as f32);
+    (0..count).map(|i| low * ratio.powi(i as i32)).collect()
+}
+
 #[cfg(test)]
 mod test_harmonic_series {
     use super::*;
@@ -31,3 +41,26 @@ mod test_harmonic_series {
         assert_almost_eq_by_element(harmonic_series(440., 4), expected);
     }
 }
+
+#[cfg(test)]
+mod test_geometric_series {
+    use super::*;
+    use test_utils::*;
+
+    #[test]
+    fn zero_frequencies() {
+        let expected = Vec::<f32>::new();
+        assert_almost_eq_by_element(geometric_series(100., 200., 0), expected);
+    }
+
+    #[test]
+    fn one_frequency() {
+        assert_almost_eq_by_element(geometric_series(100., 200., 1), vec![100.]);
+    }
+
+    #[test]
+    fn octaves() {
+        let expected = vec![110., 220., 440., 880.];
+        assert_almost_eq_by_element(geometric_series(110., 880., 4), expected);
+    }
+}
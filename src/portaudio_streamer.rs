@@ -1,41 +1,46 @@
 use std::cell::RefCell;
-use std::sync::mpsc::{channel, Receiver};
-use std::sync::{Arc, Mutex};
+use std::marker::PhantomData;
+use std::sync::mpsc::Receiver;
+use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
 
 use portaudio;
-use stopwatch::Stopwatch;
 
 use audio_streamer::AudioStreamer;
 use sample_buffer::SampleBuffer;
 
 const CHANNELS: i32 = 1;
-const SAMPLE_RATE: f64 = 44_100.0;
 const FRAMES_PER_BUFFER: u32 = 1024;
 const THREAD_SLEEP_DUR: Duration = Duration::from_millis(500);
 
 pub struct PortAudioStreamer<T> {
     portaudio: portaudio::PortAudio,
-    portaudio_settings: portaudio::OutputStreamSettings<T>,
+    phantom: PhantomData<T>,
 }
 
 impl<T> PortAudioStreamer<T> {
     pub fn new() -> PortAudioStreamer<T> {
-        let pa = portaudio::PortAudio::new().unwrap();
-        let settings = pa
-            .default_output_stream_settings(CHANNELS, SAMPLE_RATE, FRAMES_PER_BUFFER)
-            .unwrap();
         PortAudioStreamer {
-            portaudio: pa,
-            portaudio_settings: settings,
+            portaudio: portaudio::PortAudio::new().unwrap(),
+            phantom: PhantomData,
         }
     }
 }
 
+impl<T> Default for PortAudioStreamer<T> {
+    fn default() -> PortAudioStreamer<T> {
+        PortAudioStreamer::new()
+    }
+}
+
 impl<T: 'static> AudioStreamer<T> for PortAudioStreamer<T>
 where T: portaudio::Sample {
-    fn stream(&self, chunk_receiver: Receiver<Vec<T>>) {
+    fn stream(&self, chunk_receiver: Receiver<Vec<T>>, sample_rate: u32) {
+        let settings = self
+            .portaudio
+            .default_output_stream_settings(CHANNELS, sample_rate as f64, FRAMES_PER_BUFFER)
+            .unwrap();
         let initial_queue_buffer = SampleBuffer::new(Vec::<T>::new());
         let queued_received_samples = Arc::new(RefCell::new(initial_queue_buffer));
 
@@ -45,7 +50,7 @@ where T: portaudio::Sample {
 
         let mut stream = self
             .portaudio
-            .open_non_blocking_stream(self.portaudio_settings, callback)
+            .open_non_blocking_stream(settings, callback)
             .unwrap();
         stream.start().unwrap();
 
@@ -78,7 +83,7 @@ fn fill_pa_buffer<T>(
 
         match chunk_receiver.recv() {
             Ok(samples)    => queued_samples.overwrite(samples),
-            Err(RecvError) => return portaudio::stream::CallbackResult::Complete
+            Err(_)         => return portaudio::stream::CallbackResult::Complete
         }
     }
 }
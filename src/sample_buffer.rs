@@ -39,7 +39,7 @@ impl <T> SampleBuffer<T> {
     pub fn consume_into(&mut self, target: &[T]) {
         let element_count = target.len();
         debug_assert!(element_count <= self.elements_remaining());
-        let src_ptr = (&self.underlier[self.index..]).as_ptr() as *mut libc::c_void;
+        let src_ptr = self.underlier[self.index..].as_ptr() as *mut libc::c_void;
         let write_ptr = target.as_ptr() as *mut libc::c_void;
         let bytes = self.elem_size * element_count;
         unsafe {
@@ -0,0 +1,383 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use hound;
+
+use instrument::Instrument;
+use wavetable;
+
+/// How long a looping note takes to fade out once released, in seconds
+const LOOP_RELEASE_SECONDS: f32 = 0.01;
+
+/// A recorded sound, read from a WAV file
+///
+/// Deserializes from the file's path without reading it;
+/// `PipelineBuilder::build` loads the recordings of every layer.
+/// Only the first channel of the file is used.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(from = "PathBuf")]
+pub struct Recording {
+    pub path: PathBuf,
+    // Shared between every sampler playing it
+    samples: Option<Arc<Vec<f32>>>,
+    sample_rate: u32,
+}
+
+impl From<PathBuf> for Recording {
+    fn from(path: PathBuf) -> Recording {
+        Recording {
+            path,
+            samples: None,
+            sample_rate: 0,
+        }
+    }
+}
+
+impl Recording {
+    /// Read a recording from the WAV file at `path`
+    pub fn open<P: Into<PathBuf>>(path: P) -> Result<Recording, hound::Error> {
+        let mut recording = Recording::from(path.into());
+        recording.load()?;
+        Ok(recording)
+    }
+
+    /// Read the WAV file at `path` if it hasn't been already
+    pub fn load(&mut self) -> Result<(), hound::Error> {
+        if self.samples.is_some() {
+            return Ok(());
+        }
+        let (samples, sample_rate) = wavetable::read_first_channel(&self.path)?;
+        if samples.is_empty() {
+            return Err(hound::Error::FormatError("recording contains no samples"));
+        }
+        self.samples = Some(Arc::new(samples));
+        self.sample_rate = sample_rate;
+        Ok(())
+    }
+
+    /// Panics if the recording has not been loaded
+    fn loaded_samples(&self) -> Arc<Vec<f32>> {
+        match self.samples {
+            Some(ref samples) => samples.clone(),
+            None => panic!("Recording {} has not been loaded", self.path.display()),
+        }
+    }
+}
+
+/// Plays a `Recording`, repitched from its `root_frequency` by resampling
+///
+/// One-shot notes play to the end of the recording regardless of `note_off`.
+/// Looping notes repeat their loop until released, then fade out.
+/// Pitching up reads the recording faster without band-limiting it,
+/// so recordings with strong high harmonics may alias when pitched far up.
+pub struct Sampler {
+    samples: Arc<Vec<f32>>,
+    // The part of `samples` played
+    start: usize,
+    end: usize,
+    // The part of `samples` repeated, when looping
+    loop_points: Option<(usize, usize)>,
+    // How far through the recording each output sample advances
+    rate: f64,
+    // Position in `samples`, or `None` when silent
+    position: Option<f64>,
+    velocity: f32,
+    gain: f32,
+    // Gain of a released looping note, fading to 0
+    release_gain: Option<f32>,
+    release_step: f32,
+}
+
+impl Sampler {
+    /// Play `recording` at `frequency`, where it sounds `root_frequency` as recorded.
+    /// Looping notes repeat the whole recording.
+    /// Panics if the recording has not been loaded.
+    pub fn new(
+        recording: &Recording,
+        root_frequency: f32,
+        frequency: f32,
+        sample_rate: u32,
+        looping: bool,
+    ) -> Sampler {
+        let samples = recording.loaded_samples();
+        let len = samples.len();
+        let sampler = Sampler::from_samples(
+            samples,
+            recording.sample_rate,
+            root_frequency,
+            frequency,
+            sample_rate,
+        );
+        if looping {
+            sampler.with_loop(0, len)
+        } else {
+            sampler
+        }
+    }
+
+    /// Play `samples`, recorded at `samples_rate`, as a one-shot note
+    /// at `frequency`, where they sound `root_frequency` as recorded
+    pub fn from_samples(
+        samples: Arc<Vec<f32>>,
+        samples_rate: u32,
+        root_frequency: f32,
+        frequency: f32,
+        sample_rate: u32,
+    ) -> Sampler {
+        assert!(!samples.is_empty(), "No samples to play");
+        assert!(root_frequency > 0., "Invalid root frequency: {}", root_frequency);
+        assert!(frequency > 0., "Invalid frequency: {}", frequency);
+        let rate = (frequency as f64 / root_frequency as f64)
+            * (samples_rate as f64 / sample_rate as f64);
+        Sampler {
+            start: 0,
+            end: samples.len(),
+            samples,
+            loop_points: None,
+            rate,
+            position: None,
+            velocity: 0.,
+            gain: 1.,
+            release_gain: None,
+            release_step: 1. / (LOOP_RELEASE_SECONDS * sample_rate as f32).max(1.),
+        }
+    }
+
+    /// Play only `samples[start..end]`, dropping any loop
+    pub fn with_range(mut self, start: usize, end: usize) -> Self {
+        assert!(
+            start < end && end <= self.samples.len(),
+            "Invalid sample range: {}..{}",
+            start,
+            end
+        );
+        self.start = start;
+        self.end = end;
+        self.loop_points = None;
+        self
+    }
+
+    /// Repeat `samples[loop_start..loop_end]`, within the range played,
+    /// until the note is released
+    pub fn with_loop(mut self, loop_start: usize, loop_end: usize) -> Self {
+        assert!(
+            self.start <= loop_start && loop_start < loop_end && loop_end <= self.end,
+            "Invalid loop: {}..{}",
+            loop_start,
+            loop_end
+        );
+        self.loop_points = Some((loop_start, loop_end));
+        self
+    }
+
+    /// Scale every note by `gain`
+    pub fn with_gain(mut self, gain: f32) -> Self {
+        self.gain = gain;
+        self
+    }
+
+    #[inline]
+    fn next_sample(&mut self) -> f32 {
+        let position = match self.position {
+            Some(position) => position,
+            None => return 0.,
+        };
+        let index = position as usize;
+        let fraction = (position - index as f64) as f32;
+        let current = self.samples[index];
+        let next = match self.loop_points {
+            Some((loop_start, loop_end)) if index + 1 == loop_end => self.samples[loop_start],
+            _ if index + 1 < self.end => self.samples[index + 1],
+            _ => 0.,
+        };
+        let mut sample = (current + ((next - current) * fraction)) * self.velocity * self.gain;
+
+        if let Some(gain) = self.release_gain {
+            sample *= gain;
+            let gain = gain - self.release_step;
+            if gain <= 0. {
+                self.position = None;
+                return sample;
+            }
+            self.release_gain = Some(gain);
+        }
+        let mut next_position = position + self.rate;
+        match self.loop_points {
+            Some((loop_start, loop_end)) => {
+                if next_position >= loop_end as f64 {
+                    let loop_len = (loop_end - loop_start) as f64;
+                    next_position =
+                        loop_start as f64 + ((next_position - loop_start as f64) % loop_len);
+                }
+            }
+            None => {
+                if next_position >= self.end as f64 {
+                    self.position = None;
+                    return sample;
+                }
+            }
+        }
+        self.position = Some(next_position);
+        sample
+    }
+}
+
+impl Instrument for Sampler {
+    fn note_on(&mut self, velocity: f32) {
+        self.position = Some(self.start as f64);
+        self.velocity = velocity;
+        self.release_gain = None;
+    }
+
+    fn note_off(&mut self) {
+        if self.loop_points.is_some() && self.position.is_some() {
+            self.release_gain = Some(1.);
+        }
+    }
+
+    fn get_samples(&mut self, num: usize) -> Vec<f32> {
+        (0..num).map(|_| self.next_sample()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use test_utils::*;
+
+    fn recording(samples: Vec<f32>, sample_rate: u32) -> Recording {
+        Recording {
+            path: PathBuf::from("test.wav"),
+            samples: Some(Arc::new(samples)),
+            sample_rate,
+        }
+    }
+
+    #[test]
+    fn silent_until_note_on() {
+        let mut sampler = Sampler::new(&recording(vec![1.; 4], 10), 100., 100., 10, false);
+        assert_almost_eq_by_element(sampler.get_samples(3), vec![0.; 3]);
+    }
+
+    #[test]
+    fn one_shot_plays_once_at_root() {
+        let mut sampler =
+            Sampler::new(&recording(vec![0.5, 1., -1., 0.25], 10), 100., 100., 10, false);
+        sampler.note_on(0.5);
+        assert_almost_eq_by_element(
+            sampler.get_samples(6),
+            vec![0.25, 0.5, -0.5, 0.125, 0., 0.],
+        );
+    }
+
+    #[test]
+    fn one_shot_ignores_note_off() {
+        let mut sampler = Sampler::new(&recording(vec![1.; 4], 10), 100., 100., 10, false);
+        sampler.note_on(1.);
+        sampler.note_off();
+        assert_almost_eq_by_element(sampler.get_samples(5), vec![1., 1., 1., 1., 0.]);
+    }
+
+    #[test]
+    fn repitches_by_frequency_and_sample_rate() {
+        let samples = vec![0., 1., 2., 3., 4., 5., 6., 7.];
+        // An octave up
+        let mut octave = Sampler::new(&recording(samples.clone(), 10), 100., 200., 10, false);
+        octave.note_on(1.);
+        assert_almost_eq_by_element(octave.get_samples(5), vec![0., 2., 4., 6., 0.]);
+        // Recorded at twice the output rate, a fifth down
+        let mut fifth = Sampler::new(&recording(samples, 20), 300., 200., 10, false);
+        fifth.note_on(1.);
+        assert_almost_eq_by_element(fifth.get_samples(3), vec![0., 1.3333334, 2.6666667]);
+    }
+
+    #[test]
+    fn loops_until_released() {
+        let mut sampler = Sampler::new(&recording(vec![1., 0.], 10), 100., 100., 10, true);
+        sampler.note_on(1.);
+        assert_almost_eq_by_element(sampler.get_samples(5), vec![1., 0., 1., 0., 1.]);
+        // Half interpolates across the loop point
+        let mut half = Sampler::new(&recording(vec![1., 0.], 10), 100., 50., 10, true);
+        half.note_on(1.);
+        assert_almost_eq_by_element(half.get_samples(5), vec![1., 0.5, 0., 0.5, 1.]);
+    }
+
+    #[test]
+    fn released_loop_fades_out() {
+        // A release one sample long at 100 Hz
+        let mut sampler = Sampler::new(&recording(vec![1.], 100), 100., 100., 100, true);
+        sampler.note_on(1.);
+        sampler.get_samples(10);
+        sampler.note_off();
+        assert_almost_eq_by_element(sampler.get_samples(3), vec![1., 0., 0.]);
+    }
+
+    #[test]
+    fn retrigger_restarts() {
+        let mut sampler =
+            Sampler::new(&recording(vec![0.5, 1., -1.], 10), 100., 100., 10, false);
+        sampler.note_on(1.);
+        sampler.get_samples(2);
+        sampler.note_on(1.);
+        assert_almost_eq_by_element(sampler.get_samples(2), vec![0.5, 1.]);
+    }
+
+    #[test]
+    fn loops_within_range() {
+        let samples = Arc::new(vec![0., 1., 2., 3., 4., 5.]);
+        let mut sampler = Sampler::from_samples(samples, 10, 100., 100., 10)
+            .with_range(1, 5)
+            .with_loop(2, 4)
+            .with_gain(0.5);
+        sampler.note_on(1.);
+        assert_almost_eq_by_element(sampler.get_samples(6), vec![0.5, 1., 1.5, 1., 1.5, 1.]);
+    }
+
+    #[test]
+    fn plays_range_once() {
+        let samples = Arc::new(vec![0., 1., 2., 3., 4., 5.]);
+        let mut sampler = Sampler::from_samples(samples, 10, 100., 100., 10).with_range(2, 4);
+        sampler.note_on(1.);
+        assert_almost_eq_by_element(sampler.get_samples(3), vec![2., 3., 0.]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn loop_outside_range_panics() {
+        Sampler::from_samples(Arc::new(vec![0.; 6]), 10, 100., 100., 10)
+            .with_range(1, 5)
+            .with_loop(0, 4);
+    }
+
+    #[test]
+    fn load_wav() {
+        let path = env::temp_dir().join("spectrophoner_sampler_load_wav.wav");
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: 22050,
+            bits_per_sample: 32,
+            sample_format: hound::SampleFormat::Float,
+        };
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        for sample in &[0.5f32, -0.25, 1.] {
+            writer.write_sample(*sample).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let recording = Recording::open(path).unwrap();
+        assert_eq!(recording.sample_rate, 22050);
+        assert_almost_eq_by_element((*recording.loaded_samples()).clone(), vec![0.5, -0.25, 1.]);
+    }
+
+    #[test]
+    fn load_missing_file_fails() {
+        assert!(Recording::open("resources/missing.wav").is_err());
+    }
+
+    #[test]
+    #[should_panic]
+    fn unloaded_recording_panics() {
+        Sampler::new(&Recording::from(PathBuf::from("missing.wav")), 100., 100., 10, false);
+    }
+}
@@ -0,0 +1,1289 @@
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use toml;
+
+use image::Rgb;
+
+use amplitude;
+use amplitude::{AmplitudeStrategy, Transfer};
+use envelope::Adsr;
+use filter::FilterKind;
+use img_dispatcher;
+use img_dispatcher::LayerExtractorFn;
+use instrument::Instrument;
+use karplus_strong::PluckedString;
+use pipeline::{Pipeline, PipelineBuilder};
+use pitch;
+use sampler::{Recording, Sampler};
+use soundfont::SoundFont;
+use synth::Waveform;
+
+/// A declarative description of a piece, normally loaded from a TOML file
+///
+/// A minimal score looks like:
+///
+/// ```toml
+/// image = "ascending_line.png"
+/// samples_per_pixel = 4410
+/// chunk_width = 100
+///
+/// [[layers]]
+/// sections = 60
+/// waveform = "sine"
+/// pitch = { harmonic_series = { fundamental = 2.0 } }
+/// ```
+#[derive(Debug, Deserialize)]
+pub struct Score {
+    /// Path to the source image. When loaded with `Score::from_file`,
+    /// relative paths are resolved against the score file's directory.
+    pub image: PathBuf,
+    /// How many audio samples each column of pixels lasts for
+    pub samples_per_pixel: usize,
+    /// How many pixel columns are dispatched to interpreters at a time
+    pub chunk_width: u32,
+    /// The pitch map used by layers which do not specify their own
+    pub pitch: Option<PitchMap>,
+    /// Layers spanning the full height of the image
+    #[serde(default)]
+    pub layers: Vec<LayerSpec>,
+    /// Horizontal bands of the image, each with its own layers
+    #[serde(default)]
+    pub bands: Vec<BandSpec>,
+    /// A filter on the mixed output, whose layers are read over the whole image
+    pub filter: Option<FilterSpec>,
+}
+
+/// A horizontal band of the image, dispatched and interpreted
+/// independently of the other bands
+#[derive(Debug, Deserialize)]
+pub struct BandSpec {
+    /// Top edge of the band as a fraction of the image height
+    pub top: f32,
+    /// Bottom edge of the band as a fraction of the image height
+    pub bottom: f32,
+    pub layers: Vec<LayerSpec>,
+}
+
+/// Describes how a single image layer is voiced
+#[derive(Debug, Deserialize)]
+pub struct LayerSpec {
+    /// The number of equal-height horizontal sections the layer is
+    /// divided into, each of which drives its own oscillator.
+    /// Not needed when `tracking`.
+    #[serde(default)]
+    pub sections: usize,
+    #[serde(default = "default_extractor")]
+    pub extractor: Extractor,
+    /// Scale the extracted layer by the image's opacity so that
+    /// transparent regions are silent
+    #[serde(default)]
+    pub alpha_mask: bool,
+    /// Wavetable paths, like `waveform = { wavetable = "waves/soft_saw.wav" }`,
+    /// are resolved like `Score::image`
+    #[serde(default = "default_waveform")]
+    pub waveform: Waveform,
+    /// The waveform of each section from the top of the layer down, in place of
+    /// `waveform`, like `section_waveforms = ["sine", "saw", "pink_noise"]`.
+    /// Lists one waveform per section. Not available when `tracking`,
+    /// with an `instrument` or with `synthesis = "noise_band"`.
+    pub section_waveforms: Option<Vec<Waveform>>,
+    /// How sections sound when not played by an `instrument`, like
+    /// `synthesis = "noise_band"`. Not available when `tracking`.
+    #[serde(default)]
+    pub synthesis: Synthesis,
+    /// Falls back to the score's pitch map when not given
+    pub pitch: Option<PitchMap>,
+    /// How many pixel columns are reduced into each amplitude breakpoint.
+    /// Defaults to 1, so that every column of the image is heard.
+    #[serde(default = "default_columns_per_breakpoint")]
+    pub columns_per_breakpoint: usize,
+    /// Not available when `tracking`, which follows each line's brightest pixel
+    #[serde(default = "default_amplitude")]
+    pub amplitude: Amplitude,
+    /// Mapping from reduced brightness to gain
+    #[serde(default)]
+    pub transfer: Transfer,
+    /// A second layer of the same image region whose mean value sets the
+    /// timbre of morphing and FM waveforms, like `timbre = "hue"`.
+    /// Not available when `tracking`.
+    pub timbre: Option<Extractor>,
+    /// Follow drawn lines with gliding oscillators instead of voicing fixed sections
+    pub tracking: Option<Tracking>,
+    /// Play each section as notes shaped by an envelope, like
+    /// `envelope = { attack = 0.0, decay = 0.2, sustain = 0.0, release = 0.1 }`,
+    /// instead of ramping between amplitude breakpoints. Not available when `tracking`.
+    pub envelope: Option<Adsr>,
+    /// Play notes on an instrument at each section's frequency instead of
+    /// sounding `waveform`, like `instrument = { plucked_string = {} }`.
+    /// Not available with `envelope`, `vibrato`, `tremolo` or `timbre`.
+    pub instrument: Option<InstrumentSpec>,
+    /// Amplitude at which a section's note starts when `envelope` or `instrument` is given
+    #[serde(default = "default_onset_threshold")]
+    pub onset_threshold: f32,
+    /// Pitch modulation of each section, with depth in semitones,
+    /// like `vibrato = { rate = 5.0, depth = 0.2 }`. Not available when `tracking`.
+    pub vibrato: Option<LfoSpec>,
+    /// Amplitude modulation of each section, with depth the fraction of
+    /// amplitude removed at its troughs, like `tremolo = { rate = 3.0, depth = 0.5 }`.
+    /// Not available when `tracking`.
+    pub tremolo: Option<LfoSpec>,
+    /// A filter on the mixed output of all the layer's sections,
+    /// whose layers are read over the whole layer
+    pub filter: Option<FilterSpec>,
+    /// A filter on each section, whose layers are read over that section.
+    /// Not available when `tracking`.
+    pub section_filter: Option<FilterSpec>,
+}
+
+/// Ways of voicing each section of a layer
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Synthesis {
+    /// An oscillator sounding `waveform` at the section's frequency
+    Oscillator,
+    /// White noise band-passed around the section's frequency, as wide as
+    /// the range of frequencies the section spans. Textures and photographs
+    /// sound more natural as shaped noise than as a comb of tones.
+    /// See `Oscillator::noise_band`.
+    NoiseBand,
+}
+
+/// Instruments which sections can play notes on, see `TriggeredInterpreter`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InstrumentSpec {
+    /// A Karplus-Strong string plucked as hard as the section is bright.
+    /// See `PluckedString`.
+    PluckedString {
+        #[serde(default = "default_damping")]
+        damping: f32,
+        #[serde(default = "default_stretch")]
+        stretch: f32,
+    },
+    /// A recorded sound, repitched from the `root` frequency it was recorded at
+    /// and played as loud as the section is bright. See `Sampler`.
+    ///
+    /// ```toml
+    /// instrument = { sample = { recording = "samples/cello_c3.wav", root = 130.81 } }
+    /// ```
+    ///
+    /// Recording paths are resolved like `Score::image`.
+    Sample {
+        recording: Recording,
+        root: f32,
+        /// Repeat the recording until the section darkens, instead of playing it once
+        #[serde(default)]
+        looping: bool,
+    },
+    /// A preset of a General MIDI SoundFont, played as loud as the section
+    /// is bright. Presets are numbered from 0 within each bank. See `SoundFont`.
+    ///
+    /// ```toml
+    /// instrument = { soundfont = { file = "soundfonts/gm.sf2", preset = 24 } }
+    /// ```
+    ///
+    /// SoundFont paths are resolved like `Score::image`.
+    #[serde(rename = "soundfont")]
+    SoundFont {
+        file: SoundFont,
+        #[serde(default)]
+        bank: u16,
+        #[serde(default)]
+        preset: u16,
+    },
+}
+
+/// Slow periodic modulation of every section of a layer, each starting at
+/// a random phase. See `Modulation`.
+///
+/// ```toml
+/// vibrato = { rate = 5.0, depth = 0.2, depth_layer = "blue" }
+/// ```
+#[derive(Debug, Copy, Clone, Deserialize)]
+pub struct LfoSpec {
+    /// Cycles per second
+    pub rate: f32,
+    pub depth: f32,
+    /// A layer of the same image region whose mean value scales `rate`
+    pub rate_layer: Option<Extractor>,
+    /// A layer of the same image region whose mean value scales `depth`
+    pub depth_layer: Option<Extractor>,
+}
+
+/// A resonant filter, see `AutomatedFilter`
+///
+/// ```toml
+/// filter = { kind = "low_pass", cutoff = 4000.0, q = 2.0, cutoff_layer = "blue" }
+/// ```
+#[derive(Debug, Copy, Clone, Deserialize)]
+pub struct FilterSpec {
+    pub kind: FilterKind,
+    /// Cutoff, or center frequency of band-pass and notch filters, in Hz
+    pub cutoff: f32,
+    #[serde(default = "default_q")]
+    pub q: f32,
+    /// A layer of the filtered image region which moves the cutoff from
+    /// `dark_cutoff` where it is black to `cutoff` where it is white
+    pub cutoff_layer: Option<Extractor>,
+    #[serde(default = "default_dark_cutoff")]
+    pub dark_cutoff: f32,
+    /// A layer of the filtered image region whose mean value scales `q`
+    pub q_layer: Option<Extractor>,
+}
+
+/// Pitch tracking of bright lines, see `PitchTrackingInterpreter`
+///
+/// ```toml
+/// tracking = { lines = 2, low = 110.0, high = 880.0 }
+/// ```
+#[derive(Debug, Copy, Clone, Deserialize)]
+pub struct Tracking {
+    /// How many lines to follow. A single line follows the brightness-weighted
+    /// centroid of each column; more follow its brightest runs of lit pixels.
+    #[serde(default = "default_lines")]
+    pub lines: usize,
+    /// Frequency at the bottom of the layer
+    pub low: f32,
+    /// Frequency at the top of the layer
+    pub high: f32,
+}
+
+/// Ways of deriving a layer's image data from the source image
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Extractor {
+    /// Pixel brightness
+    Grayscale,
+    Red,
+    Green,
+    Blue,
+    /// Hue, from red at 0 through green and blue. Grays have a hue of 0.
+    Hue,
+    Saturation,
+    /// HSV value, the brightest of each pixel's RGB channels
+    Value,
+    /// Pixel opacity
+    Alpha,
+    /// Closeness of each pixel to `color`, see `color_key_layer_extractor`
+    ColorKey {
+        color: [u8; 3],
+        #[serde(default = "default_color_key_tolerance")]
+        tolerance: f32,
+    },
+}
+
+/// Ways of reducing a section of image data to an amplitude.
+/// See the `amplitude` module.
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Amplitude {
+    Mean,
+    Max,
+    Rms,
+    Median,
+    /// Fraction between 0 and 1
+    Percentile(f32),
+    /// The fraction of pixels at least as bright as `threshold`
+    Coverage { threshold: u8 },
+}
+
+/// Strategies for assigning frequencies to a layer's sections
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PitchMap {
+    /// Integer multiples of `fundamental`
+    HarmonicSeries { fundamental: f32 },
+    /// Frequencies evenly spaced in pitch between `low` and `high`
+    Geometric { low: f32, high: f32 },
+}
+
+#[derive(Debug)]
+pub enum ScoreError {
+    Io(io::Error),
+    Parse(toml::de::Error),
+    /// `samples_per_pixel` is 0
+    InvalidSamplesPerPixel,
+    /// `chunk_width` is 0
+    InvalidChunkWidth,
+}
+
+fn default_extractor() -> Extractor {
+    Extractor::Grayscale
+}
+
+fn default_color_key_tolerance() -> f32 {
+    1.
+}
+
+fn default_columns_per_breakpoint() -> usize {
+    1
+}
+
+fn default_amplitude() -> Amplitude {
+    Amplitude::Mean
+}
+
+fn default_lines() -> usize {
+    1
+}
+
+fn default_onset_threshold() -> f32 {
+    0.5
+}
+
+fn default_damping() -> f32 {
+    0.005
+}
+
+fn default_stretch() -> f32 {
+    0.5
+}
+
+fn default_q() -> f32 {
+    0.707
+}
+
+fn default_dark_cutoff() -> f32 {
+    20.
+}
+
+fn default_waveform() -> Waveform {
+    Waveform::Sine
+}
+
+impl Score {
+    /// Read and parse a score file
+    pub fn from_file(path: &Path) -> Result<Score, ScoreError> {
+        let mut contents = String::new();
+        File::open(path)?.read_to_string(&mut contents)?;
+        let mut score = Score::from_str(&contents)?;
+        if let Some(score_dir) = path.parent() {
+            resolve_relative_to(score_dir, &mut score.image);
+            let band_layers = score.bands.iter_mut().flat_map(|band| band.layers.iter_mut());
+            for layer in score.layers.iter_mut().chain(band_layers) {
+                let wavetables = layer.waveforms_mut().into_iter().flat_map(|w| w.wavetables_mut());
+                for wavetable in wavetables {
+                    resolve_relative_to(score_dir, &mut wavetable.path);
+                }
+                if let Some(recording) = layer.instrument.as_mut().and_then(|i| i.recording_mut()) {
+                    resolve_relative_to(score_dir, &mut recording.path);
+                }
+                if let Some(soundfont) = layer.instrument.as_mut().and_then(|i| i.soundfont_mut()) {
+                    resolve_relative_to(score_dir, &mut soundfont.path);
+                }
+            }
+        }
+        Ok(score)
+    }
+
+    /// Create a `PipelineBuilder` configured to render this score
+    pub fn pipeline_builder(self) -> PipelineBuilder {
+        let mut builder = Pipeline::builder()
+            .image(self.image)
+            .samples_per_pixel(self.samples_per_pixel)
+            .chunk_width(self.chunk_width);
+        if let Some(pitch_map) = self.pitch {
+            builder = builder.pitch_map(pitch_map);
+        }
+        for layer in self.layers {
+            builder = builder.layer(layer);
+        }
+        for band in self.bands {
+            builder = builder.band(band);
+        }
+        if let Some(filter) = self.filter {
+            builder = builder.filter(filter);
+        }
+        builder
+    }
+}
+
+impl FromStr for Score {
+    type Err = ScoreError;
+
+    fn from_str(contents: &str) -> Result<Score, ScoreError> {
+        let score: Score = toml::from_str(contents)?;
+        if score.samples_per_pixel == 0 {
+            return Err(ScoreError::InvalidSamplesPerPixel);
+        }
+        if score.chunk_width == 0 {
+            return Err(ScoreError::InvalidChunkWidth);
+        }
+        Ok(score)
+    }
+}
+
+fn resolve_relative_to(dir: &Path, path: &mut PathBuf) {
+    if path.is_relative() {
+        *path = dir.join(&path);
+    }
+}
+
+impl BandSpec {
+    /// A band with no layers between the fractional heights `top` and `bottom`
+    pub fn new(top: f32, bottom: f32) -> BandSpec {
+        BandSpec {
+            top,
+            bottom,
+            layers: vec![],
+        }
+    }
+
+    pub fn full_image(layers: Vec<LayerSpec>) -> BandSpec {
+        BandSpec {
+            top: 0.,
+            bottom: 1.,
+            layers,
+        }
+    }
+
+    /// The band's `(y_start, y_end)` rows in an image `img_height` pixels tall
+    pub fn pixel_range(&self, img_height: usize) -> (usize, usize) {
+        let to_row = |fraction: f32| (fraction * img_height as f32).round() as usize;
+        (to_row(self.top), to_row(self.bottom))
+    }
+}
+
+impl LayerSpec {
+    /// A sine layer with `sections` sections and no pitch map of its own
+    pub fn new(sections: usize) -> LayerSpec {
+        LayerSpec {
+            sections,
+            extractor: default_extractor(),
+            alpha_mask: false,
+            waveform: default_waveform(),
+            section_waveforms: None,
+            synthesis: Synthesis::default(),
+            pitch: None,
+            columns_per_breakpoint: default_columns_per_breakpoint(),
+            amplitude: default_amplitude(),
+            transfer: Transfer::default(),
+            timbre: None,
+            tracking: None,
+            envelope: None,
+            onset_threshold: default_onset_threshold(),
+            vibrato: None,
+            tremolo: None,
+            instrument: None,
+            filter: None,
+            section_filter: None,
+        }
+    }
+
+    /// A layer following `lines` drawn lines between `low` and `high` frequencies
+    pub fn tracking(lines: usize, low: f32, high: f32) -> LayerSpec {
+        LayerSpec {
+            tracking: Some(Tracking { lines, low, high }),
+            ..LayerSpec::new(0)
+        }
+    }
+
+    /// How many oscillators the layer is voiced with
+    pub fn voice_count(&self) -> usize {
+        match self.tracking {
+            Some(tracking) => tracking.lines,
+            None => self.sections,
+        }
+    }
+
+    /// Whether the layer sets `envelope`, `vibrato`, `tremolo`, `timbre`
+    /// or `section_waveforms`, which only oscillators voicing fixed sections read
+    pub fn uses_section_voicing(&self) -> bool {
+        self.envelope.is_some()
+            || self.vibrato.is_some()
+            || self.tremolo.is_some()
+            || self.timbre.is_some()
+            || self.section_waveforms.is_some()
+    }
+
+    /// The waveform sounded by the section at index `section`
+    pub fn section_waveform(&self, section: usize) -> &Waveform {
+        match self.section_waveforms {
+            Some(ref waveforms) => &waveforms[section],
+            None => &self.waveform,
+        }
+    }
+
+    /// `waveform` followed by every waveform in `section_waveforms`
+    pub fn waveforms_mut(&mut self) -> Vec<&mut Waveform> {
+        let mut waveforms = vec![&mut self.waveform];
+        if let Some(ref mut section_waveforms) = self.section_waveforms {
+            waveforms.extend(section_waveforms.iter_mut());
+        }
+        waveforms
+    }
+
+    /// Every extractor the layer reads, including those of its data-only layers
+    pub fn extractors(&self) -> Vec<Extractor> {
+        let mut extractors = vec![self.extractor];
+        extractors.extend(self.timbre);
+        for lfo in self.vibrato.iter().chain(self.tremolo.iter()) {
+            extractors.extend(lfo.rate_layer);
+            extractors.extend(lfo.depth_layer);
+        }
+        for filter in self.filter.iter().chain(self.section_filter.iter()) {
+            extractors.append(&mut filter.extractors());
+        }
+        extractors
+    }
+
+    /// The extractor for this layer, including any alpha masking
+    pub fn layer_extractor(&self) -> LayerExtractorFn {
+        let extractor = self.extractor.layer_extractor();
+        if self.alpha_mask {
+            img_dispatcher::alpha_masked_layer_extractor(extractor)
+        } else {
+            extractor
+        }
+    }
+}
+
+impl Extractor {
+    /// Whether the extractor's parameters are within their ranges
+    pub fn is_valid(&self) -> bool {
+        match *self {
+            Extractor::ColorKey { tolerance, .. } => tolerance > 0.,
+            _ => true,
+        }
+    }
+
+    pub fn layer_extractor(&self) -> LayerExtractorFn {
+        match *self {
+            Extractor::Grayscale => Box::new(img_dispatcher::naive_layer_extractor),
+            Extractor::Red => Box::new(img_dispatcher::red_layer_extractor),
+            Extractor::Green => Box::new(img_dispatcher::green_layer_extractor),
+            Extractor::Blue => Box::new(img_dispatcher::blue_layer_extractor),
+            Extractor::Hue => Box::new(img_dispatcher::hue_layer_extractor),
+            Extractor::Saturation => Box::new(img_dispatcher::saturation_layer_extractor),
+            Extractor::Value => Box::new(img_dispatcher::value_layer_extractor),
+            Extractor::Alpha => Box::new(img_dispatcher::alpha_layer_extractor),
+            Extractor::ColorKey { color, tolerance } => {
+                img_dispatcher::color_key_layer_extractor(Rgb { data: color }, tolerance)
+            }
+        }
+    }
+}
+
+impl Amplitude {
+    /// Whether the strategy's parameters are within their ranges
+    pub fn is_valid(&self) -> bool {
+        match *self {
+            Amplitude::Percentile(fraction) => fraction >= 0. && fraction <= 1.,
+            _ => true,
+        }
+    }
+
+    pub fn strategy(&self) -> Box<dyn AmplitudeStrategy> {
+        match *self {
+            Amplitude::Mean => Box::new(amplitude::Mean),
+            Amplitude::Max => Box::new(amplitude::Max),
+            Amplitude::Rms => Box::new(amplitude::Rms),
+            Amplitude::Median => Box::new(amplitude::Median),
+            Amplitude::Percentile(fraction) => Box::new(amplitude::Percentile(fraction)),
+            Amplitude::Coverage { threshold } => Box::new(amplitude::Coverage { threshold }),
+        }
+    }
+}
+
+impl Default for Synthesis {
+    fn default() -> Synthesis {
+        Synthesis::Oscillator
+    }
+}
+
+impl FilterSpec {
+    /// Whether the cutoffs and Q are positive
+    pub fn is_valid(&self) -> bool {
+        self.cutoff > 0. && self.dark_cutoff > 0. && self.q > 0.
+    }
+
+    /// The extractors of the filter's cutoff and Q layers, if any
+    pub fn extractors(&self) -> Vec<Extractor> {
+        self.cutoff_layer.into_iter().chain(self.q_layer).collect()
+    }
+}
+
+impl InstrumentSpec {
+    /// Whether the instrument's parameters are within their ranges
+    pub fn is_valid(&self) -> bool {
+        match *self {
+            InstrumentSpec::PluckedString { damping, stretch } => {
+                damping >= 0. && damping <= 1. && stretch >= 0. && stretch <= 1.
+            }
+            InstrumentSpec::Sample { root, .. } => root > 0.,
+            InstrumentSpec::SoundFont { .. } => true,
+        }
+    }
+
+    /// The recording played by the instrument, if any
+    pub fn recording_mut(&mut self) -> Option<&mut Recording> {
+        match *self {
+            InstrumentSpec::Sample { ref mut recording, .. } => Some(recording),
+            _ => None,
+        }
+    }
+
+    /// The SoundFont played by the instrument, if any
+    pub fn soundfont_mut(&mut self) -> Option<&mut SoundFont> {
+        match *self {
+            InstrumentSpec::SoundFont { ref mut file, .. } => Some(file),
+            _ => None,
+        }
+    }
+
+    /// Whether the instrument plays a preset its SoundFont lacks.
+    /// Panics if the SoundFont has not been loaded.
+    pub fn has_missing_preset(&self) -> bool {
+        match *self {
+            InstrumentSpec::SoundFont {
+                ref file,
+                bank,
+                preset,
+            } => !file.has_preset(bank, preset),
+            _ => false,
+        }
+    }
+
+    pub fn instrument(&self, frequency: f32, sample_rate: u32) -> Box<dyn Instrument> {
+        match *self {
+            InstrumentSpec::PluckedString { damping, stretch } => {
+                Box::new(PluckedString::new(frequency, sample_rate, damping, stretch))
+            }
+            InstrumentSpec::Sample {
+                ref recording,
+                root,
+                looping,
+            } => Box::new(Sampler::new(recording, root, frequency, sample_rate, looping)),
+            InstrumentSpec::SoundFont {
+                ref file,
+                bank,
+                preset,
+            } => Box::new(file.sampler(bank, preset, frequency, sample_rate)),
+        }
+    }
+}
+
+impl PitchMap {
+    /// Whether every frequency the map generates is positive
+    pub fn is_valid(&self) -> bool {
+        match *self {
+            PitchMap::HarmonicSeries { fundamental } => fundamental > 0.,
+            PitchMap::Geometric { low, high } => low > 0. && high > 0.,
+        }
+    }
+
+    /// Generate `count` frequencies ordered from the top of the image to the bottom,
+    /// so that higher sections sound higher pitches.
+    pub fn frequencies(&self, count: usize) -> Vec<f32> {
+        let mut frequencies = match *self {
+            PitchMap::HarmonicSeries { fundamental } => pitch::harmonic_series(fundamental, count),
+            PitchMap::Geometric { low, high } => pitch::geometric_series(low, high, count),
+        };
+        frequencies.reverse();
+        frequencies
+    }
+}
+
+impl fmt::Display for ScoreError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ScoreError::Io(ref err) => write!(f, "Could not read score: {}", err),
+            ScoreError::Parse(ref err) => write!(f, "Could not parse score: {}", err),
+            ScoreError::InvalidSamplesPerPixel => write!(f, "samples_per_pixel must be positive"),
+            ScoreError::InvalidChunkWidth => write!(f, "chunk_width must be positive"),
+        }
+    }
+}
+
+impl Error for ScoreError {}
+
+impl From<io::Error> for ScoreError {
+    fn from(err: io::Error) -> ScoreError {
+        ScoreError::Io(err)
+    }
+}
+
+impl From<toml::de::Error> for ScoreError {
+    fn from(err: toml::de::Error) -> ScoreError {
+        ScoreError::Parse(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use amplitude::TransferCurve;
+    use envelope::EnvelopeCurve;
+    use test_utils::*;
+
+    const MINIMAL_SCORE: &str = r#"
+        image = "ascending_line.png"
+        samples_per_pixel = 4410
+        chunk_width = 100
+
+        [[layers]]
+        sections = 3
+        pitch = { harmonic_series = { fundamental = 2.0 } }
+
+        [[layers]]
+        sections = 4
+        waveform = { pulse = { duty = 0.25 } }
+        extractor = "saturation"
+        alpha_mask = true
+        columns_per_breakpoint = 10
+        amplitude = { percentile = 0.9 }
+        transfer = { curve = { decibels = { floor = -60.0, ceiling = 0.0 } }, gate = 0.1 }
+
+        [[layers]]
+        sections = 2
+        extractor = { color_key = { color = [255, 0, 0], tolerance = 0.25 } }
+        amplitude = "max"
+        waveform = "square"
+        pitch = { geometric = { low = 100.0, high = 400.0 } }
+    "#;
+
+    #[test]
+    fn parse_minimal_score() {
+        let score = Score::from_str(MINIMAL_SCORE).unwrap();
+        assert_eq!(score.image, PathBuf::from("ascending_line.png"));
+        assert_eq!(score.samples_per_pixel, 4410);
+        assert_eq!(score.chunk_width, 100);
+        assert_eq!(score.layers.len(), 3);
+        assert_eq!(score.layers[0].sections, 3);
+        assert_eq!(score.layers[1].sections, 4);
+        assert_eq!(score.layers[2].sections, 2);
+        match score.layers[0].waveform {
+            Waveform::Sine => {}
+            _ => panic!("expected default waveform to be sine"),
+        }
+        match score.layers[1].waveform {
+            Waveform::Pulse { duty } => assert_almost_eq(duty, 0.25),
+            _ => panic!("expected pulse waveform"),
+        }
+        match score.layers[2].waveform {
+            Waveform::Square => {}
+            _ => panic!("expected square waveform"),
+        }
+        match score.layers[0].extractor {
+            Extractor::Grayscale => {}
+            _ => panic!("expected default extractor to be grayscale"),
+        }
+        assert_eq!(score.layers[0].columns_per_breakpoint, 1);
+        assert_eq!(score.layers[1].columns_per_breakpoint, 10);
+        assert!(!score.layers[0].alpha_mask);
+        assert!(score.layers[1].alpha_mask);
+        match score.layers[0].amplitude {
+            Amplitude::Mean => {}
+            _ => panic!("expected default amplitude to be mean"),
+        }
+        match score.layers[1].amplitude {
+            Amplitude::Percentile(fraction) => assert_almost_eq(fraction, 0.9),
+            _ => panic!("expected percentile amplitude"),
+        }
+        match score.layers[2].amplitude {
+            Amplitude::Max => {}
+            _ => panic!("expected max amplitude"),
+        }
+        match score.layers[1].transfer.curve {
+            TransferCurve::Decibels { floor, ceiling } => {
+                assert_almost_eq(floor, -60.);
+                assert_almost_eq(ceiling, 0.);
+            }
+            _ => panic!("expected decibel transfer curve"),
+        }
+        assert!(!score.layers[1].transfer.inverted);
+        assert_almost_eq(score.layers[1].transfer.gate, 0.1);
+        match score.layers[1].extractor {
+            Extractor::Saturation => {}
+            _ => panic!("expected saturation extractor"),
+        }
+        match score.layers[2].extractor {
+            Extractor::ColorKey { color, tolerance } => {
+                assert_eq!(color, [255, 0, 0]);
+                assert_almost_eq(tolerance, 0.25);
+            }
+            _ => panic!("expected color key extractor"),
+        }
+    }
+
+    #[test]
+    fn parse_score_level_pitch_map() {
+        let score = Score::from_str(
+            r#"
+            image = "ascending_line.png"
+            samples_per_pixel = 4410
+            chunk_width = 100
+            pitch = { harmonic_series = { fundamental = 2.0 } }
+
+            [[layers]]
+            sections = 3
+            "#,
+        ).unwrap();
+        assert!(score.pitch.is_some());
+        assert!(score.layers[0].pitch.is_none());
+    }
+
+    #[test]
+    fn parse_additive_waveform() {
+        let score = Score::from_str(
+            r#"
+            image = "ascending_line.png"
+            samples_per_pixel = 4410
+            chunk_width = 100
+            pitch = { harmonic_series = { fundamental = 2.0 } }
+
+            [[layers]]
+            sections = 3
+            waveform = { additive = [[1, 1.0, 0.0], [3, 0.33, 0.25]] }
+            "#,
+        ).unwrap();
+        match score.layers[0].waveform {
+            Waveform::Additive(ref partials) => {
+                assert_eq!(partials.len(), 2);
+                assert_eq!(partials[1].0, 3);
+                assert_almost_eq(partials[1].1, 0.33);
+                assert_almost_eq(partials[1].2, 0.25);
+            }
+            _ => panic!("expected additive waveform"),
+        }
+    }
+
+    #[test]
+    fn parse_morph_waveform() {
+        let score = Score::from_str(
+            r#"
+            image = "ascending_line.png"
+            samples_per_pixel = 4410
+            chunk_width = 100
+            pitch = { harmonic_series = { fundamental = 2.0 } }
+
+            [[layers]]
+            sections = 3
+            waveform = { morph = ["sine", "saw", { wavetable = "soft_saw.wav" }] }
+            timbre = "green"
+            "#,
+        ).unwrap();
+        match score.layers[0].waveform {
+            Waveform::Morph(ref waveforms) => assert_eq!(waveforms.len(), 3),
+            _ => panic!("expected morph waveform"),
+        }
+        match score.layers[0].timbre {
+            Some(Extractor::Green) => {}
+            _ => panic!("expected green timbre"),
+        }
+    }
+
+    #[test]
+    fn parse_fm_waveform() {
+        let score = Score::from_str(
+            r#"
+            image = "ascending_line.png"
+            samples_per_pixel = 4410
+            chunk_width = 100
+            pitch = { harmonic_series = { fundamental = 2.0 } }
+
+            [[layers]]
+            sections = 3
+            waveform = { fm = { ratio = 1.4, index = 3.0 } }
+            timbre = "value"
+            "#,
+        ).unwrap();
+        match score.layers[0].waveform {
+            Waveform::Fm { ratio, index } => {
+                assert_almost_eq(ratio, 1.4);
+                assert_almost_eq(index, 3.);
+            }
+            _ => panic!("expected fm waveform"),
+        }
+    }
+
+    #[test]
+    fn parse_tracking() {
+        let score = Score::from_str(
+            r#"
+            image = "ascending_line.png"
+            samples_per_pixel = 4410
+            chunk_width = 100
+
+            [[layers]]
+            tracking = { low = 110.0, high = 880.0 }
+
+            [[layers]]
+            tracking = { lines = 3, low = 220.0, high = 440.0 }
+            "#,
+        ).unwrap();
+        let tracking = score.layers[0].tracking.unwrap();
+        assert_eq!(tracking.lines, 1);
+        assert_almost_eq(tracking.low, 110.);
+        assert_almost_eq(tracking.high, 880.);
+        assert_eq!(score.layers[1].voice_count(), 3);
+    }
+
+    #[test]
+    fn parse_envelope() {
+        let score = Score::from_str(
+            r#"
+            image = "ascending_line.png"
+            samples_per_pixel = 4410
+            chunk_width = 100
+            pitch = { harmonic_series = { fundamental = 2.0 } }
+
+            [[layers]]
+            sections = 3
+            envelope = { attack = 0.0, decay = 0.2, sustain = 0.5, release = 0.1 }
+
+            [[layers]]
+            sections = 3
+            onset_threshold = 0.25
+
+            [layers.envelope]
+            attack = 0.01
+            decay = 0.0
+            sustain = 1.0
+            release = 0.5
+            curve = "exponential"
+            "#,
+        ).unwrap();
+        let envelope = score.layers[0].envelope.unwrap();
+        assert_almost_eq(envelope.decay, 0.2);
+        assert_almost_eq(envelope.sustain, 0.5);
+        assert_almost_eq(score.layers[0].onset_threshold, 0.5);
+        match score.layers[1].envelope.unwrap().curve {
+            EnvelopeCurve::Exponential => {}
+            _ => panic!("expected exponential curve"),
+        }
+        assert_almost_eq(score.layers[1].onset_threshold, 0.25);
+    }
+
+    #[test]
+    fn parse_modulation() {
+        let score = Score::from_str(
+            r#"
+            image = "ascending_line.png"
+            samples_per_pixel = 4410
+            chunk_width = 100
+            pitch = { harmonic_series = { fundamental = 2.0 } }
+
+            [[layers]]
+            sections = 3
+            vibrato = { rate = 5.0, depth = 0.2 }
+            tremolo = { rate = 3.0, depth = 0.5, rate_layer = "red", depth_layer = "blue" }
+            "#,
+        ).unwrap();
+        let vibrato = score.layers[0].vibrato.unwrap();
+        assert_almost_eq(vibrato.rate, 5.);
+        assert_almost_eq(vibrato.depth, 0.2);
+        assert!(vibrato.rate_layer.is_none());
+        let tremolo = score.layers[0].tremolo.unwrap();
+        match (tremolo.rate_layer, tremolo.depth_layer) {
+            (Some(Extractor::Red), Some(Extractor::Blue)) => {}
+            _ => panic!("expected red rate and blue depth layers"),
+        }
+    }
+
+    #[test]
+    fn parse_synthesis() {
+        let score = Score::from_str(
+            r#"
+            image = "ascending_line.png"
+            samples_per_pixel = 4410
+            chunk_width = 100
+            pitch = { harmonic_series = { fundamental = 2.0 } }
+
+            [[layers]]
+            sections = 3
+
+            [[layers]]
+            sections = 3
+            synthesis = "noise_band"
+            "#,
+        ).unwrap();
+        assert_eq!(score.layers[0].synthesis, Synthesis::Oscillator);
+        assert_eq!(score.layers[1].synthesis, Synthesis::NoiseBand);
+    }
+
+    #[test]
+    fn parse_filters() {
+        let score = Score::from_str(
+            r#"
+            image = "ascending_line.png"
+            samples_per_pixel = 4410
+            chunk_width = 100
+            pitch = { harmonic_series = { fundamental = 2.0 } }
+            filter = { kind = "notch", cutoff = 60.0, q = 10.0 }
+
+            [[layers]]
+            sections = 3
+            filter = { kind = "low_pass", cutoff = 4000.0, cutoff_layer = "blue" }
+            section_filter = { kind = "band_pass", cutoff = 800.0, q_layer = "red" }
+            "#,
+        ).unwrap();
+        let bus = score.filter.unwrap();
+        assert_eq!(bus.kind, FilterKind::Notch);
+        assert_almost_eq(bus.q, 10.);
+        let layer = score.layers[0].filter.unwrap();
+        assert_eq!(layer.kind, FilterKind::LowPass);
+        assert_almost_eq(layer.cutoff, 4000.);
+        assert_almost_eq(layer.q, 0.707);
+        assert_almost_eq(layer.dark_cutoff, 20.);
+        match layer.cutoff_layer {
+            Some(Extractor::Blue) => {}
+            _ => panic!("expected blue cutoff layer"),
+        }
+        let section = score.layers[0].section_filter.unwrap();
+        assert_eq!(section.kind, FilterKind::BandPass);
+        assert!(section.cutoff_layer.is_none());
+        match section.q_layer {
+            Some(Extractor::Red) => {}
+            _ => panic!("expected red Q layer"),
+        }
+    }
+
+    #[test]
+    fn parse_instrument() {
+        let score = Score::from_str(
+            r#"
+            image = "ascending_line.png"
+            samples_per_pixel = 4410
+            chunk_width = 100
+            pitch = { harmonic_series = { fundamental = 2.0 } }
+
+            [[layers]]
+            sections = 3
+            instrument = { plucked_string = {} }
+
+            [[layers]]
+            sections = 3
+            instrument = { plucked_string = { damping = 0.02, stretch = 0.3 } }
+            "#,
+        ).unwrap();
+        match score.layers[0].instrument {
+            Some(InstrumentSpec::PluckedString { damping, stretch }) => {
+                assert_almost_eq(damping, 0.005);
+                assert_almost_eq(stretch, 0.5);
+            }
+            _ => panic!("expected plucked string"),
+        }
+        match score.layers[1].instrument {
+            Some(InstrumentSpec::PluckedString { damping, stretch }) => {
+                assert_almost_eq(damping, 0.02);
+                assert_almost_eq(stretch, 0.3);
+            }
+            _ => panic!("expected plucked string"),
+        }
+    }
+
+    #[test]
+    fn parse_sample_instrument() {
+        let score = Score::from_str(
+            r#"
+            image = "ascending_line.png"
+            samples_per_pixel = 4410
+            chunk_width = 100
+            pitch = { harmonic_series = { fundamental = 2.0 } }
+
+            [[layers]]
+            sections = 3
+            instrument = { sample = { recording = "samples/cello.wav", root = 130.81 } }
+
+            [[layers]]
+            sections = 3
+            instrument = { sample = { recording = "organ.wav", root = 440.0, looping = true } }
+            "#,
+        ).unwrap();
+        match score.layers[0].instrument {
+            Some(InstrumentSpec::Sample {
+                ref recording,
+                root,
+                looping,
+            }) => {
+                assert_eq!(recording.path, PathBuf::from("samples/cello.wav"));
+                assert_almost_eq(root, 130.81);
+                assert!(!looping);
+            }
+            _ => panic!("expected sample"),
+        }
+        match score.layers[1].instrument {
+            Some(InstrumentSpec::Sample { looping, .. }) => assert!(looping),
+            _ => panic!("expected sample"),
+        }
+    }
+
+    #[test]
+    fn parse_bands() {
+        let score = Score::from_str(
+            r#"
+            image = "ascending_line.png"
+            samples_per_pixel = 4410
+            chunk_width = 100
+            pitch = { harmonic_series = { fundamental = 2.0 } }
+
+            [[bands]]
+            top = 0.0
+            bottom = 0.25
+
+            [[bands.layers]]
+            sections = 10
+
+            [[bands]]
+            top = 0.25
+            bottom = 1.0
+
+            [[bands.layers]]
+            sections = 20
+            waveform = "square"
+
+            [[bands.layers]]
+            sections = 5
+            extractor = "blue"
+            "#,
+        ).unwrap();
+        assert!(score.layers.is_empty());
+        assert_eq!(score.bands.len(), 2);
+        assert_eq!(score.bands[0].layers.len(), 1);
+        assert_eq!(score.bands[1].layers.len(), 2);
+        assert_eq!(score.bands[0].pixel_range(200), (0, 50));
+        assert_eq!(score.bands[1].pixel_range(200), (50, 200));
+    }
+
+    #[test]
+    fn parse_missing_field_fails() {
+        match Score::from_str("image = \"foo.png\"") {
+            Err(ScoreError::Parse(_)) => {}
+            other => panic!("expected parse error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parse_zero_timing_fails() {
+        let score = |samples_per_pixel, chunk_width| {
+            Score::from_str(&format!(
+                "image = \"foo.png\"\nsamples_per_pixel = {}\nchunk_width = {}",
+                samples_per_pixel, chunk_width
+            ))
+        };
+        assert!(score(4410, 100).is_ok());
+        match score(0, 100) {
+            Err(ScoreError::InvalidSamplesPerPixel) => {}
+            other => panic!("expected InvalidSamplesPerPixel, got {:?}", other),
+        }
+        match score(4410, 0) {
+            Err(ScoreError::InvalidChunkWidth) => {}
+            other => panic!("expected InvalidChunkWidth, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn from_file_resolves_image_relative_to_score() {
+        let score = Score::from_file(Path::new("resources/ascending_line.toml")).unwrap();
+        assert_eq!(score.image, PathBuf::from("resources/ascending_line.png"));
+    }
+
+    #[test]
+    fn from_file_resolves_wavetables_relative_to_score() {
+        let score = Score::from_file(Path::new("resources/ascending_line_wavetable.toml")).unwrap();
+        match score.layers[0].waveform {
+            Waveform::Wavetable(ref wavetable) => {
+                assert_eq!(wavetable.path, PathBuf::from("resources/wavetables/soft_saw.wav"))
+            }
+            ref other => panic!("expected a wavetable, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn from_file_resolves_morph_wavetables_relative_to_score() {
+        let score = Score::from_file(Path::new("resources/ascending_line_morph.toml")).unwrap();
+        let mut layer = score.layers.into_iter().next().unwrap();
+        let wavetables = layer.waveform.wavetables_mut();
+        assert_eq!(wavetables.len(), 1);
+        assert_eq!(wavetables[0].path, PathBuf::from("resources/wavetables/soft_saw.wav"));
+    }
+
+    #[test]
+    fn from_file_reads_section_waveforms() {
+        let score = Score::from_file(Path::new("resources/ascending_line_waveforms.toml")).unwrap();
+        let layer = &score.layers[0];
+        assert_eq!(layer.section_waveforms.as_ref().map(Vec::len), Some(6));
+        assert_eq!(*layer.section_waveform(0), Waveform::Sine);
+        assert_eq!(*layer.section_waveform(3), Waveform::Pulse { duty: 0.25 });
+        match *layer.section_waveform(4) {
+            Waveform::Wavetable(ref wavetable) => {
+                assert_eq!(wavetable.path, PathBuf::from("resources/wavetables/soft_saw.wav"))
+            }
+            ref other => panic!("expected a wavetable, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parse_soundfont_instrument() {
+        let score = Score::from_str(
+            r#"
+            image = "ascending_line.png"
+            samples_per_pixel = 4410
+            chunk_width = 100
+            pitch = { harmonic_series = { fundamental = 2.0 } }
+
+            [[layers]]
+            sections = 3
+            instrument = { soundfont = { file = "gm.sf2", bank = 128, preset = 24 } }
+
+            [[layers]]
+            sections = 3
+            instrument = { soundfont = { file = "gm.sf2" } }
+            "#,
+        ).unwrap();
+        match score.layers[0].instrument {
+            Some(InstrumentSpec::SoundFont {
+                ref file,
+                bank,
+                preset,
+            }) => {
+                assert_eq!(file.path, PathBuf::from("gm.sf2"));
+                assert_eq!(bank, 128);
+                assert_eq!(preset, 24);
+            }
+            _ => panic!("expected soundfont"),
+        }
+        match score.layers[1].instrument {
+            Some(InstrumentSpec::SoundFont { bank, preset, .. }) => {
+                assert_eq!((bank, preset), (0, 0));
+            }
+            _ => panic!("expected soundfont"),
+        }
+    }
+
+    #[test]
+    fn from_file_resolves_soundfont_paths() {
+        let score =
+            Score::from_file(Path::new("resources/ascending_line_soundfont.toml")).unwrap();
+        let mut layer = score.layers.into_iter().next().unwrap();
+        let soundfont = layer.instrument.as_mut().unwrap().soundfont_mut().unwrap();
+        assert_eq!(soundfont.path, PathBuf::from("resources/soundfonts/saw_and_sine.sf2"));
+    }
+
+    #[test]
+    fn from_file_resolves_recording_paths() {
+        let score = Score::from_file(Path::new("resources/ascending_line_sample.toml")).unwrap();
+        let mut layer = score.layers.into_iter().next().unwrap();
+        let recording = layer.instrument.as_mut().unwrap().recording_mut().unwrap();
+        assert_eq!(recording.path, PathBuf::from("resources/wavetables/soft_saw.wav"));
+    }
+
+    #[test]
+    fn harmonic_series_pitch_map_descends() {
+        let pitch_map = PitchMap::HarmonicSeries { fundamental: 2. };
+        assert_almost_eq_by_element(pitch_map.frequencies(3), vec![6., 4., 2.]);
+    }
+
+    #[test]
+    fn geometric_pitch_map_descends() {
+        let pitch_map = PitchMap::Geometric { low: 100., high: 400. };
+        assert_almost_eq_by_element(pitch_map.frequencies(3), vec![400., 200., 100.]);
+    }
+}
@@ -0,0 +1,585 @@
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use sampler::Sampler;
+
+// Generator operators, from section 8.1.2 of the SoundFont 2.04 specification
+const START_ADDRS_OFFSET: u16 = 0;
+const END_ADDRS_OFFSET: u16 = 1;
+const STARTLOOP_ADDRS_OFFSET: u16 = 2;
+const ENDLOOP_ADDRS_OFFSET: u16 = 3;
+const START_ADDRS_COARSE_OFFSET: u16 = 4;
+const END_ADDRS_COARSE_OFFSET: u16 = 12;
+const INSTRUMENT: u16 = 41;
+const KEY_RANGE: u16 = 43;
+const STARTLOOP_ADDRS_COARSE_OFFSET: u16 = 45;
+const INITIAL_ATTENUATION: u16 = 48;
+const ENDLOOP_ADDRS_COARSE_OFFSET: u16 = 50;
+const COARSE_TUNE: u16 = 51;
+const FINE_TUNE: u16 = 52;
+const SAMPLE_ID: u16 = 53;
+const SAMPLE_MODES: u16 = 54;
+const OVERRIDING_ROOT_KEY: u16 = 58;
+
+/// Size in bytes of each record in the preset data chunks
+const PHDR_SIZE: usize = 38;
+const BAG_SIZE: usize = 4;
+const GEN_SIZE: usize = 4;
+const INST_SIZE: usize = 22;
+const SHDR_SIZE: usize = 46;
+
+/// A General MIDI SoundFont, read from an SF2 file
+///
+/// Deserializes from the file's path without reading it;
+/// `PipelineBuilder::build` loads the SoundFonts of every layer.
+///
+/// Each note plays the sample mapped to its key by the chosen preset,
+/// repitched by a `Sampler` and looped as the SoundFont says.
+/// Only keys, tuning, sample offsets, loops and attenuation are honored:
+/// velocity ranges, envelopes, filters and modulators are ignored,
+/// and of a stereo pair only the first sample mapped to a key is played.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(from = "PathBuf")]
+pub struct SoundFont {
+    pub path: PathBuf,
+    // Shared between every sampler playing it
+    data: Option<Arc<SoundFontData>>,
+}
+
+#[derive(Debug)]
+pub enum SoundFontError {
+    Io(io::Error),
+    /// The file is not a well-formed SF2 file
+    Format(&'static str),
+}
+
+#[derive(Debug)]
+struct SoundFontData {
+    presets: Vec<Preset>,
+    samples: Arc<Vec<f32>>,
+}
+
+#[derive(Debug)]
+struct Preset {
+    bank: u16,
+    number: u16,
+    zones: Vec<KeyZone>,
+}
+
+/// A sample mapped to a range of keys, with the generators
+/// of its preset and instrument zones applied
+#[derive(Debug, Clone)]
+struct KeyZone {
+    low_key: u8,
+    high_key: u8,
+    start: usize,
+    end: usize,
+    loop_points: Option<(usize, usize)>,
+    sample_rate: u32,
+    root_key: u8,
+    tuning_cents: f32,
+    gain: f32,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Generator {
+    operator: u16,
+    amount: [u8; 2],
+}
+
+#[derive(Debug, Clone, Copy)]
+struct SampleHeader {
+    start: u32,
+    end: u32,
+    loop_start: u32,
+    loop_end: u32,
+    sample_rate: u32,
+    original_pitch: u8,
+    pitch_correction: i8,
+}
+
+/// The id and data of a chunk of a RIFF file
+type RiffChunk<'a> = (&'a [u8], &'a [u8]);
+
+/// A zone's generators, and those of the global zone it falls back to
+struct ZoneGenerators<'a> {
+    zone: &'a [Generator],
+    global: &'a [Generator],
+}
+
+impl From<PathBuf> for SoundFont {
+    fn from(path: PathBuf) -> SoundFont {
+        SoundFont { path, data: None }
+    }
+}
+
+impl SoundFont {
+    /// Read a SoundFont from the SF2 file at `path`
+    pub fn open<P: Into<PathBuf>>(path: P) -> Result<SoundFont, SoundFontError> {
+        let mut soundfont = SoundFont::from(path.into());
+        soundfont.load()?;
+        Ok(soundfont)
+    }
+
+    /// Read the SF2 file at `path` if it hasn't been already
+    pub fn load(&mut self) -> Result<(), SoundFontError> {
+        if self.data.is_some() {
+            return Ok(());
+        }
+        let mut bytes = Vec::new();
+        File::open(&self.path)?.read_to_end(&mut bytes)?;
+        self.data = Some(Arc::new(parse(&bytes)?));
+        Ok(())
+    }
+
+    /// Whether the SoundFont has a preset numbered `preset` in `bank`
+    /// which plays at least one sample. Panics if it has not been loaded.
+    pub fn has_preset(&self, bank: u16, preset: u16) -> bool {
+        self.find_preset(bank, preset).map_or(false, |preset| !preset.zones.is_empty())
+    }
+
+    /// A sampler playing `preset` of `bank` at `frequency`, using the
+    /// sample mapped to the nearest key. Panics if the SoundFont has not
+    /// been loaded or the preset plays no samples.
+    pub fn sampler(&self, bank: u16, preset: u16, frequency: f32, sample_rate: u32) -> Sampler {
+        let zones = match self.find_preset(bank, preset) {
+            Some(preset) if !preset.zones.is_empty() => &preset.zones,
+            _ => panic!("SoundFont {} has no preset {}:{}", self.path.display(), bank, preset),
+        };
+        let key = 69. + (12. * (frequency / 440.).log2());
+        let zone = zones
+            .iter()
+            .min_by(|a, b| a.key_distance(key).partial_cmp(&b.key_distance(key)).unwrap())
+            .unwrap();
+        let root_frequency = 440.
+            * ((zone.root_key as f32 - 69. - (zone.tuning_cents / 100.)) / 12.).exp2();
+        let sampler = Sampler::from_samples(
+            self.loaded_data().samples.clone(),
+            zone.sample_rate,
+            root_frequency,
+            frequency,
+            sample_rate,
+        )
+        .with_range(zone.start, zone.end)
+        .with_gain(zone.gain);
+        match zone.loop_points {
+            Some((loop_start, loop_end)) => sampler.with_loop(loop_start, loop_end),
+            None => sampler,
+        }
+    }
+
+    fn find_preset(&self, bank: u16, preset: u16) -> Option<&Preset> {
+        self.loaded_data().presets.iter().find(|p| p.bank == bank && p.number == preset)
+    }
+
+    /// Panics if the SoundFont has not been loaded
+    fn loaded_data(&self) -> &SoundFontData {
+        match self.data {
+            Some(ref data) => data,
+            None => panic!("SoundFont {} has not been loaded", self.path.display()),
+        }
+    }
+}
+
+impl KeyZone {
+    /// How many semitones `key` lies outside the zone's range
+    fn key_distance(&self, key: f32) -> f32 {
+        (self.low_key as f32 - key).max(key - self.high_key as f32).max(0.)
+    }
+}
+
+impl Generator {
+    fn value(&self) -> i16 {
+        (self.amount[0] as u16 | (self.amount[1] as u16) << 8) as i16
+    }
+
+    fn range(&self) -> (u8, u8) {
+        (self.amount[0], self.amount[1])
+    }
+}
+
+impl<'a> ZoneGenerators<'a> {
+    fn get(&self, operator: u16) -> Option<Generator> {
+        let find = |generators: &[Generator]| {
+            generators.iter().rev().find(|g| g.operator == operator).cloned()
+        };
+        find(self.zone).or_else(|| find(self.global))
+    }
+
+    fn value(&self, operator: u16) -> i32 {
+        self.get(operator).map_or(0, |g| g.value() as i32)
+    }
+
+    fn range(&self, operator: u16) -> (u8, u8) {
+        self.get(operator).map_or((0, 127), |g| g.range())
+    }
+
+    /// A sample address offset, in samples
+    fn offset(&self, fine: u16, coarse: u16) -> i64 {
+        self.value(fine) as i64 + (self.value(coarse) as i64 * 32768)
+    }
+}
+
+fn parse(bytes: &[u8]) -> Result<SoundFontData, SoundFontError> {
+    let riff = chunks(bytes)?;
+    let body = match riff.first() {
+        Some(&(id, body)) if id == b"RIFF" && body.starts_with(b"sfbk") => &body[4..],
+        _ => return Err(SoundFontError::Format("not a SoundFont file")),
+    };
+    let lists = chunks(body)?;
+    let sdta = list(&lists, b"sdta")?;
+    let pdta = list(&lists, b"pdta")?;
+
+    let samples = sub_chunk(&sdta, b"smpl")?
+        .chunks(2)
+        .filter(|pair| pair.len() == 2)
+        .map(|pair| u16_at(pair, 0) as i16 as f32 / 32768.)
+        .collect::<Vec<f32>>();
+    let presets = records(sub_chunk(&pdta, b"phdr")?, PHDR_SIZE, |r| {
+        ((u16_at(r, 20), u16_at(r, 22)), u16_at(r, 24) as usize)
+    })?;
+    let preset_bags = records(sub_chunk(&pdta, b"pbag")?, BAG_SIZE, |r| u16_at(r, 0) as usize)?;
+    let preset_generators = records(sub_chunk(&pdta, b"pgen")?, GEN_SIZE, generator)?;
+    let instruments =
+        records(sub_chunk(&pdta, b"inst")?, INST_SIZE, |r| ((), u16_at(r, 20) as usize))?;
+    let instrument_bags =
+        records(sub_chunk(&pdta, b"ibag")?, BAG_SIZE, |r| u16_at(r, 0) as usize)?;
+    let instrument_generators = records(sub_chunk(&pdta, b"igen")?, GEN_SIZE, generator)?;
+    let sample_headers = records(sub_chunk(&pdta, b"shdr")?, SHDR_SIZE, |r| SampleHeader {
+        start: u32_at(r, 20),
+        end: u32_at(r, 24),
+        loop_start: u32_at(r, 28),
+        loop_end: u32_at(r, 32),
+        sample_rate: u32_at(r, 36),
+        original_pitch: r[40],
+        pitch_correction: r[41] as i8,
+    })?;
+
+    // Every list ends with a terminal record marking where the last entry's zones end
+    let preset_zones = |index| zones(&presets, &preset_bags, &preset_generators, index, INSTRUMENT);
+    let instrument_zones =
+        |index| zones(&instruments, &instrument_bags, &instrument_generators, index, SAMPLE_ID);
+    let mut resolved = Vec::new();
+    for (index, &((number, bank), _)) in presets.iter().enumerate().take(presets.len() - 1) {
+        let mut key_zones = Vec::new();
+        for preset_zone in preset_zones(index)? {
+            let instrument = preset_zone.value(INSTRUMENT) as u16 as usize;
+            if instrument + 1 >= instruments.len() {
+                return Err(SoundFontError::Format("preset zone names a missing instrument"));
+            }
+            for instrument_zone in instrument_zones(instrument)? {
+                let header = instrument_zone.value(SAMPLE_ID) as u16 as usize;
+                if header + 1 >= sample_headers.len() {
+                    return Err(SoundFontError::Format("zone names a missing sample"));
+                }
+                let header = &sample_headers[header];
+                if let Some(zone) = key_zone(&preset_zone, &instrument_zone, header, samples.len())
+                {
+                    key_zones.push(zone);
+                }
+            }
+        }
+        resolved.push(Preset {
+            bank,
+            number,
+            zones: key_zones,
+        });
+    }
+    Ok(SoundFontData {
+        presets: resolved,
+        samples: Arc::new(samples),
+    })
+}
+
+/// Combine a preset zone and instrument zone into the sample they play,
+/// or `None` if their key ranges don't overlap or the sample is empty
+fn key_zone(
+    preset_zone: &ZoneGenerators,
+    instrument_zone: &ZoneGenerators,
+    header: &SampleHeader,
+    samples_len: usize,
+) -> Option<KeyZone> {
+    let (preset_low, preset_high) = preset_zone.range(KEY_RANGE);
+    let (instrument_low, instrument_high) = instrument_zone.range(KEY_RANGE);
+    let low_key = preset_low.max(instrument_low);
+    let high_key = preset_high.min(instrument_high);
+
+    let address = |base: u32, fine, coarse| -> usize {
+        let address = base as i64 + instrument_zone.offset(fine, coarse);
+        address.max(0).min(samples_len as i64) as usize
+    };
+    let start = address(header.start, START_ADDRS_OFFSET, START_ADDRS_COARSE_OFFSET);
+    let end = address(header.end, END_ADDRS_OFFSET, END_ADDRS_COARSE_OFFSET);
+    if low_key > high_key || start >= end {
+        return None;
+    }
+    let loop_start =
+        address(header.loop_start, STARTLOOP_ADDRS_OFFSET, STARTLOOP_ADDRS_COARSE_OFFSET);
+    let loop_end = address(header.loop_end, ENDLOOP_ADDRS_OFFSET, ENDLOOP_ADDRS_COARSE_OFFSET);
+    // Modes 1 and 3 loop, the latter playing on past the loop once released
+    let loops = instrument_zone.value(SAMPLE_MODES) & 1 == 1;
+    let loop_points = if loops && start <= loop_start && loop_start < loop_end && loop_end <= end
+    {
+        Some((loop_start, loop_end))
+    } else {
+        None
+    };
+
+    let root_key = match instrument_zone.get(OVERRIDING_ROOT_KEY) {
+        Some(generator) if generator.value() >= 0 && generator.value() <= 127 => {
+            generator.value() as u8
+        }
+        _ => header.original_pitch.min(127),
+    };
+    // Preset zone generators are offsets added to the instrument's
+    let tuning_cents = header.pitch_correction as i32
+        + ((instrument_zone.value(COARSE_TUNE) + preset_zone.value(COARSE_TUNE)) * 100)
+        + instrument_zone.value(FINE_TUNE)
+        + preset_zone.value(FINE_TUNE);
+    let attenuation_centibels =
+        (instrument_zone.value(INITIAL_ATTENUATION) + preset_zone.value(INITIAL_ATTENUATION))
+            .max(0);
+    Some(KeyZone {
+        low_key,
+        high_key,
+        start,
+        end,
+        loop_points,
+        sample_rate: header.sample_rate.max(1),
+        root_key,
+        tuning_cents: tuning_cents as f32,
+        gain: 10f32.powf(-(attenuation_centibels as f32) / 200.),
+    })
+}
+
+/// The zones of the preset or instrument at `index` which end in a `terminal`
+/// generator. A first zone without one is global and supplies defaults.
+fn zones<'a, T>(
+    headers: &[(T, usize)],
+    bags: &[usize],
+    generators: &'a [Generator],
+    index: usize,
+    terminal: u16,
+) -> Result<Vec<ZoneGenerators<'a>>, SoundFontError> {
+    let first_bag = headers[index].1;
+    let last_bag = headers[index + 1].1;
+    if first_bag > last_bag || last_bag >= bags.len() {
+        return Err(SoundFontError::Format("zone indices out of order"));
+    }
+    let mut zone_generators = Vec::new();
+    for bag in first_bag..last_bag {
+        let (first, last) = (bags[bag], bags[bag + 1]);
+        if first > last || last > generators.len() {
+            return Err(SoundFontError::Format("generator indices out of order"));
+        }
+        zone_generators.push(&generators[first..last]);
+    }
+    let mut global: &[Generator] = &[];
+    let mut zones = Vec::new();
+    for (i, zone) in zone_generators.into_iter().enumerate() {
+        // The terminal generator must come last in its zone
+        match zone.last() {
+            Some(generator) if generator.operator == terminal => {
+                zones.push(ZoneGenerators { zone, global: &[] });
+            }
+            _ if i == 0 => global = zone,
+            _ => {}
+        }
+    }
+    for zone in zones.iter_mut() {
+        zone.global = global;
+    }
+    Ok(zones)
+}
+
+/// The `(id, data)` chunks of a RIFF body
+fn chunks(mut bytes: &[u8]) -> Result<Vec<RiffChunk<'_>>, SoundFontError> {
+    let mut chunks = Vec::new();
+    while bytes.len() >= 8 {
+        let id = &bytes[0..4];
+        let size = u32_at(bytes, 4) as usize;
+        if bytes.len() - 8 < size {
+            return Err(SoundFontError::Format("chunk extends past the end of the file"));
+        }
+        chunks.push((id, &bytes[8..8 + size]));
+        // Chunks are padded to an even length
+        let padded = (size + 1) & !1;
+        bytes = &bytes[(8 + padded).min(bytes.len())..];
+    }
+    Ok(chunks)
+}
+
+/// The chunks of the LIST chunk of type `list_type`
+fn list<'a>(
+    chunks: &[RiffChunk<'a>],
+    list_type: &[u8],
+) -> Result<Vec<RiffChunk<'a>>, SoundFontError> {
+    let body = chunks
+        .iter()
+        .find(|&&(id, body)| id == b"LIST" && body.starts_with(list_type))
+        .map(|&(_, body)| &body[4..])
+        .ok_or(SoundFontError::Format("missing LIST chunk"))?;
+    self::chunks(body)
+}
+
+fn sub_chunk<'a>(
+    chunks: &[RiffChunk<'a>],
+    id: &[u8],
+) -> Result<&'a [u8], SoundFontError> {
+    chunks
+        .iter()
+        .find(|&&(chunk_id, _)| chunk_id == id)
+        .map(|&(_, body)| body)
+        .ok_or(SoundFontError::Format("missing sub-chunk"))
+}
+
+/// Parse fixed-size records, requiring at least the terminal record
+fn records<T, F>(bytes: &[u8], size: usize, parse: F) -> Result<Vec<T>, SoundFontError>
+where
+    F: Fn(&[u8]) -> T,
+{
+    if bytes.len() % size != 0 || bytes.len() < size {
+        return Err(SoundFontError::Format("malformed preset data"));
+    }
+    Ok(bytes.chunks(size).map(parse).collect())
+}
+
+fn generator(record: &[u8]) -> Generator {
+    Generator {
+        operator: u16_at(record, 0),
+        amount: [record[2], record[3]],
+    }
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+    bytes[offset] as u16 | (bytes[offset + 1] as u16) << 8
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u16_at(bytes, offset) as u32 | (u16_at(bytes, offset + 2) as u32) << 16
+}
+
+impl fmt::Display for SoundFontError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SoundFontError::Io(ref err) => write!(f, "Could not read SoundFont: {}", err),
+            SoundFontError::Format(reason) => write!(f, "Invalid SoundFont: {}", reason),
+        }
+    }
+}
+
+impl Error for SoundFontError {}
+
+impl From<io::Error> for SoundFontError {
+    fn from(err: io::Error) -> SoundFontError {
+        SoundFontError::Io(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use instrument::Instrument;
+    use test_utils::*;
+
+    // Preset 0:0 loops a 600 sample saw cycle below middle C, rooted at key 38
+    // 2 cents sharp, and a 100 sample sine cycle attenuated by 6 dB above it,
+    // rooted at key 69 4 cents sharp. Preset 0:1 is the same a semitone up.
+    const SAW_AND_SINE: &str = "resources/soundfonts/saw_and_sine.sf2";
+
+    fn zones(soundfont: &SoundFont, preset: u16) -> &[KeyZone] {
+        &soundfont.find_preset(0, preset).unwrap().zones
+    }
+
+    #[test]
+    fn resolves_zones() {
+        let soundfont = SoundFont::open(SAW_AND_SINE).unwrap();
+        let zones = zones(&soundfont, 0);
+        assert_eq!(zones.len(), 2);
+        let (saw, sine) = (&zones[0], &zones[1]);
+        assert_eq!((saw.low_key, saw.high_key), (0, 59));
+        assert_eq!((saw.start, saw.end, saw.loop_points), (0, 600, Some((0, 600))));
+        assert_eq!((saw.root_key, saw.sample_rate), (38, 44100));
+        assert_almost_eq(saw.tuning_cents, -2.);
+        assert_almost_eq(saw.gain, 1.);
+        // Each sample is followed by 46 samples of silence
+        assert_eq!((sine.low_key, sine.high_key), (60, 127));
+        assert_eq!((sine.start, sine.end, sine.loop_points), (646, 746, Some((646, 746))));
+        assert_eq!(sine.root_key, 69);
+        assert_almost_eq(sine.tuning_cents, -4.);
+        assert_almost_eq(sine.gain, 0.50118726);
+    }
+
+    #[test]
+    fn preset_tuning_adds_to_instrument() {
+        let soundfont = SoundFont::open(SAW_AND_SINE).unwrap();
+        let tunings: Vec<f32> = zones(&soundfont, 1).iter().map(|z| z.tuning_cents).collect();
+        assert_almost_eq_by_element(tunings, vec![98., 96.]);
+    }
+
+    #[test]
+    fn has_preset() {
+        let soundfont = SoundFont::open(SAW_AND_SINE).unwrap();
+        assert!(soundfont.has_preset(0, 0));
+        assert!(soundfont.has_preset(0, 1));
+        assert!(!soundfont.has_preset(0, 2));
+        assert!(!soundfont.has_preset(1, 0));
+    }
+
+    #[test]
+    fn plays_sample_at_root_and_loops() {
+        let soundfont = SoundFont::open(SAW_AND_SINE).unwrap();
+        // Key 38 corrected by 2 cents
+        let root_frequency = 440. * ((38. - 69. + 0.02) / 12f32).exp2();
+        let mut sampler = soundfont.sampler(0, 0, root_frequency, 44100);
+        sampler.note_on(1.);
+        let samples = sampler.get_samples(1200);
+        let saw = soundfont.loaded_data().samples[0..600].to_vec();
+        for (played, expected) in samples.chunks(600).zip([saw.clone(), saw].iter()) {
+            for (a, b) in played.iter().zip(expected.iter()) {
+                assert!((a - b).abs() < 1e-3, "{} != {}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn high_notes_play_attenuated_sine() {
+        let soundfont = SoundFont::open(SAW_AND_SINE).unwrap();
+        let mut sampler = soundfont.sampler(0, 0, 880., 44100);
+        sampler.note_on(1.);
+        let samples = sampler.get_samples(1000);
+        let peak = samples.iter().fold(0., |peak: f32, s| peak.max(s.abs()));
+        assert!((peak - (0.8 * 0.50118726)).abs() < 0.01, "peak of {}", peak);
+    }
+
+    #[test]
+    fn open_invalid_file_fails() {
+        match SoundFont::open("resources/wavetables/soft_saw.wav") {
+            Err(SoundFontError::Format(_)) => {}
+            _ => panic!("expected Format error"),
+        }
+        match SoundFont::open("resources/missing.sf2") {
+            Err(SoundFontError::Io(_)) => {}
+            _ => panic!("expected Io error"),
+        }
+    }
+
+    #[test]
+    fn truncated_file_fails() {
+        let mut bytes = Vec::new();
+        File::open(SAW_AND_SINE).unwrap().read_to_end(&mut bytes).unwrap();
+        bytes.truncate(bytes.len() - 10);
+        assert!(parse(&bytes).is_err());
+    }
+
+    #[test]
+    #[should_panic]
+    fn unloaded_soundfont_panics() {
+        SoundFont::from(PathBuf::from("missing.sf2")).sampler(0, 0, 440., 44100);
+    }
+}
@@ -1,24 +1,51 @@
 use std::f32::consts;
-
-use ndarray::prelude::*;
+use std::sync::Arc;
 
 use arrays;
+use filter::{Biquad, FilterKind};
+use noise::{NoiseColor, NoiseGenerator};
+use wavetable::{self, MipMappedTable, Wavetable, TABLE_LEN};
 
 const TWO_PI: f32 = consts::PI * 2.;
 const SINGLE_SIGNAL_MIN: f32 = -1.;
 const SINGLE_SIGNAL_MAX: f32 = 1.;
-
-fn period_length(frequency: f32, sample_rate: u32) -> u32 {
-    return ((sample_rate as f32) / frequency) as u32;
-}
+/// RMS level of a noise band, a little below a full-scale sine's
+/// to leave room for noise's higher peaks
+const NOISE_BAND_RMS: f32 = 0.5;
+/// RMS level of uniform white noise between -1 and 1
+const WHITE_NOISE_RMS: f32 = 0.57735026;
 
 trait PeriodGenerator {
-    fn generate_period(&self, frequency: f32, sample_rate: u32) -> Vec<f32>;
+    /// Generate a single cycle of the waveform `len` samples long
+    fn generate_cycle(&self, len: usize) -> Vec<f32>;
 }
 
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum Waveform {
     Sine,
     Square,
+    /// Rising ramp, starting from 0 at the beginning of the cycle
+    Saw,
+    Triangle,
+    /// Square wave which is high for `duty` (between 0 and 1) of each cycle
+    Pulse { duty: f32 },
+    WhiteNoise,
+    PinkNoise,
+    BrownNoise,
+    /// A single cycle read from a WAV file
+    Wavetable(Wavetable),
+    /// A sum of harmonics given as `(partial, amplitude, phase)`, where `partial`
+    /// is the harmonic number and `phase` is a fraction of that harmonic's cycle.
+    /// Amplitudes are relative; the sum is normalized to a peak of 1.
+    Additive(Vec<(u32, f32, f32)>),
+    /// Crossfades between the cycles of these waveforms by timbre, from the
+    /// first at 0 to the last at 1. See `Oscillator::get_samples_with_timbre_envelope`.
+    Morph(Vec<Waveform>),
+    /// Two-operator FM: a sine carrier phase modulated by a sine at `ratio` times
+    /// its frequency. `index` is the peak phase deviation in radians, reached at
+    /// a timbre of 1, or below it wherever that would push sidebands past Nyquist.
+    Fm { ratio: f32, index: f32 },
 }
 
 #[inline]
@@ -42,51 +69,525 @@ fn populate_square_period(period: &mut Vec<f32>) {
     }
 }
 
+#[inline]
+fn populate_saw_period(period: &mut Vec<f32>) {
+    let len = period.capacity() as f32;
+    for i in 0..period.capacity() {
+        // Offset by half a cycle so the ramp crosses zero at the start
+        let ramp_position = ((i as f32 / len) + 0.5) % 1.;
+        period.push(SINGLE_SIGNAL_MIN + (ramp_position * (SINGLE_SIGNAL_MAX - SINGLE_SIGNAL_MIN)));
+    }
+}
+
+#[inline]
+fn populate_triangle_period(period: &mut Vec<f32>) {
+    let len = period.capacity() as f32;
+    for i in 0..period.capacity() {
+        let position = i as f32 / len;
+        let value = if position < 0.25 {
+            position * 4.
+        } else if position < 0.75 {
+            2. - (position * 4.)
+        } else {
+            (position * 4.) - 4.
+        };
+        period.push(value * SINGLE_SIGNAL_MAX);
+    }
+}
+
+#[inline]
+fn populate_pulse_period(period: &mut Vec<f32>, duty: f32) {
+    assert!(duty >= 0. && duty <= 1., "Invalid pulse duty: {}", duty);
+    let high_len = ((period.capacity() as f32) * duty).round() as usize;
+    let low_len = period.capacity() - high_len;
+    for _ in 0..high_len {
+        period.push(SINGLE_SIGNAL_MAX);
+    }
+    for _ in 0..low_len {
+        period.push(SINGLE_SIGNAL_MIN);
+    }
+}
+
+/// A cycle of noise is just `capacity` samples of it
+#[inline]
+fn populate_noise_period(period: &mut Vec<f32>, color: NoiseColor) {
+    let len = period.capacity();
+    period.append(&mut NoiseGenerator::new(color).get_samples(len));
+}
+
+/// The wavetable's cycle, resampled to `capacity` samples
+#[inline]
+fn populate_wavetable_period(period: &mut Vec<f32>, wavetable: &Wavetable) {
+    let len = period.capacity();
+    period.append(&mut wavetable::resample_cycle(wavetable.cycle(), len));
+}
+
+#[inline]
+fn populate_additive_period(period: &mut Vec<f32>, partials: &[(u32, f32, f32)]) {
+    let sum_at = |position: f32| -> f32 {
+        partials
+            .iter()
+            .map(|&(partial, amplitude, phase)| {
+                amplitude * (TWO_PI * ((partial as f32 * position) + phase)).sin()
+            })
+            .sum()
+    };
+    // Measure the peak finely enough that it doesn't depend on the period length
+    let peak = (0..TABLE_LEN)
+        .map(|i| sum_at(i as f32 / TABLE_LEN as f32).abs())
+        .fold(0., f32::max);
+    let scale = if peak > 0. { SINGLE_SIGNAL_MAX / peak } else { 0. };
+    let len = period.capacity() as f32;
+    for i in 0..period.capacity() {
+        period.push(sum_at(i as f32 / len) * scale);
+    }
+}
+
+/// A morph's cycle is that of its first waveform
+#[inline]
+fn populate_morph_period(period: &mut Vec<f32>, waveforms: &[Waveform]) {
+    let len = period.capacity();
+    match waveforms.first() {
+        Some(waveform) => period.append(&mut waveform.generate_cycle(len)),
+        None => period.resize(len, 0.),
+    }
+}
+
+#[inline]
+fn populate_fm_period(period: &mut Vec<f32>, ratio: f32, index: f32) {
+    let x_scale = TWO_PI / (period.capacity() as f32);
+    for i in 0..period.capacity() {
+        let x = i as f32 * x_scale;
+        period.push((x + (index * (ratio * x).sin())).sin() * SINGLE_SIGNAL_MAX);
+    }
+}
+
+impl Waveform {
+    /// Every wavetable this waveform reads from, which must be loaded
+    /// before an `Oscillator` can be made from it
+    pub fn wavetables_mut(&mut self) -> Vec<&mut Wavetable> {
+        match *self {
+            Waveform::Wavetable(ref mut wavetable) => vec![wavetable],
+            Waveform::Morph(ref mut waveforms) => waveforms
+                .iter_mut()
+                .flat_map(|waveform| waveform.wavetables_mut())
+                .collect(),
+            _ => vec![],
+        }
+    }
+
+    /// Whether the waveform's parameters are within their ranges
+    pub fn is_valid(&self) -> bool {
+        match *self {
+            Waveform::Pulse { duty } => duty >= 0. && duty <= 1.,
+            Waveform::Fm { ratio, index } => ratio >= 0. && index >= 0.,
+            Waveform::Morph(ref waveforms) => waveforms.iter().all(Waveform::is_valid),
+            _ => true,
+        }
+    }
+}
+
 impl PeriodGenerator for Waveform {
-    fn generate_period(&self, frequency: f32, sample_rate: u32) -> Vec<f32> {
-        assert!(frequency > 0., "Invalid frequency: {}", frequency);
-        let samples_needed = period_length(frequency, sample_rate);
-        let mut period = Vec::<f32>::with_capacity(samples_needed as usize);
+    fn generate_cycle(&self, len: usize) -> Vec<f32> {
+        let mut period = Vec::<f32>::with_capacity(len);
 
-        match self {
-            &Waveform::Sine => populate_sine_period(&mut period),
-            &Waveform::Square => populate_square_period(&mut period),
+        match *self {
+            Waveform::Sine => populate_sine_period(&mut period),
+            Waveform::Square => populate_square_period(&mut period),
+            Waveform::Saw => populate_saw_period(&mut period),
+            Waveform::Triangle => populate_triangle_period(&mut period),
+            Waveform::Pulse { duty } => populate_pulse_period(&mut period, duty),
+            Waveform::WhiteNoise => populate_noise_period(&mut period, NoiseColor::White),
+            Waveform::PinkNoise => populate_noise_period(&mut period, NoiseColor::Pink),
+            Waveform::BrownNoise => populate_noise_period(&mut period, NoiseColor::Brown),
+            Waveform::Wavetable(ref wavetable) => {
+                populate_wavetable_period(&mut period, wavetable)
+            }
+            Waveform::Additive(ref partials) => populate_additive_period(&mut period, partials),
+            Waveform::Morph(ref waveforms) => populate_morph_period(&mut period, waveforms),
+            Waveform::Fm { ratio, index } => populate_fm_period(&mut period, ratio, index),
         };
-        return period;
+        period
     }
 }
 
+/// The tables oscillators read a waveform from
+///
+/// Band-limiting a waveform is costly, so oscillators sounding the same
+/// waveform, like the sections of a layer, are made from one set of tables
+/// with `Oscillator::from_tables`, which they share.
+#[derive(Clone)]
+pub struct WaveformTables {
+    tables: Tables,
+    sample_rate: u32,
+}
+
+#[derive(Clone)]
+enum Tables {
+    /// A single cycle of a sine, which has no harmonics to alias
+    Sine(Arc<Vec<f32>>),
+    MipMapped(Arc<MipMappedTable>),
+    Morph(Arc<Vec<MipMappedTable>>),
+    Fm {
+        sine: Arc<Vec<f32>>,
+        ratio: f32,
+        index: f32,
+    },
+    /// Noise has no tables, each oscillator generates its own
+    Noise(NoiseColor),
+}
+
+/// Where an oscillator's samples come from
+enum Source {
+    /// A single cycle of a periodic waveform with no harmonics to alias
+    Table(Arc<Vec<f32>>),
+    /// Band-limited cycles of a periodic waveform, chosen by frequency
+    MipMapped(Arc<MipMappedTable>),
+    /// Band-limited cycles of several waveforms, crossfaded by timbre
+    Morph(Arc<Vec<MipMappedTable>>),
+    /// A sine carrier, read at `phase`, modulated by a sine with its own phase
+    Fm {
+        sine: Arc<Vec<f32>>,
+        modulator_phase: f64,
+        ratio: f64,
+        index: f32,
+    },
+    /// Aperiodic noise, for which frequency is meaningless
+    Noise(NoiseGenerator),
+    /// White noise band-passed around the oscillator's frequency
+    /// with a constant `q`, the ratio of frequency to bandwidth
+    NoiseBand {
+        generator: NoiseGenerator,
+        filter: Biquad,
+        q: f32,
+    },
+}
+
+/// A phase-accumulating wavetable oscillator
+///
+/// Samples are read from a single cycle of the waveform with linear
+/// interpolation, so any frequency is rendered exactly in tune.
+/// Waveforms with harmonics are read from band-limited tables so they
+/// don't alias at high frequencies.
+/// Noise waveforms are generated continuously and ignore frequency.
+/// Noise bands are centered on it, see `Oscillator::noise_band`.
 pub struct Oscillator {
-    period_cache: Vec<f32>,
-    phase: usize,
+    source: Source,
+    // Position within the cycle, between 0 and 1
+    phase: f64,
+    // How far `phase` advances each sample
+    phase_increment: f64,
+    sample_rate: u32,
+    // Between 0 and 1, see `get_samples_with_timbre_envelope`
+    timbre: f32,
+}
+
+impl WaveformTables {
+    /// Build the tables for `waveform`, band-limited for playback at `sample_rate`
+    pub fn new(waveform: &Waveform, sample_rate: u32) -> WaveformTables {
+        let band_limited = |waveform: &Waveform| {
+            MipMappedTable::from_cycle(&waveform.generate_cycle(TABLE_LEN), sample_rate)
+        };
+        let tables = match *waveform {
+            Waveform::WhiteNoise => Tables::Noise(NoiseColor::White),
+            Waveform::PinkNoise => Tables::Noise(NoiseColor::Pink),
+            Waveform::BrownNoise => Tables::Noise(NoiseColor::Brown),
+            Waveform::Sine => Tables::Sine(Arc::new(waveform.generate_cycle(TABLE_LEN))),
+            Waveform::Fm { ratio, index } => Tables::Fm {
+                sine: Arc::new(Waveform::Sine.generate_cycle(TABLE_LEN)),
+                ratio,
+                index,
+            },
+            Waveform::Morph(ref waveforms) => {
+                assert!(!waveforms.is_empty(), "Morph requires at least one waveform");
+                Tables::Morph(Arc::new(waveforms.iter().map(band_limited).collect()))
+            }
+            _ => Tables::MipMapped(Arc::new(band_limited(waveform))),
+        };
+        WaveformTables {
+            tables,
+            sample_rate,
+        }
+    }
 }
 
 impl Oscillator {
     pub fn new(waveform: Waveform, frequency: f32, sample_rate: u32) -> Oscillator {
+        Oscillator::from_tables(&WaveformTables::new(&waveform, sample_rate), frequency)
+    }
+
+    /// An oscillator at `frequency` reading from `tables`, at their sample rate
+    pub fn from_tables(tables: &WaveformTables, frequency: f32) -> Oscillator {
+        assert!(frequency > 0., "Invalid frequency: {}", frequency);
+        let source = match tables.tables {
+            Tables::Sine(ref sine) => Source::Table(sine.clone()),
+            Tables::MipMapped(ref mip_mapped) => Source::MipMapped(mip_mapped.clone()),
+            Tables::Morph(ref morph) => Source::Morph(morph.clone()),
+            Tables::Fm {
+                ref sine,
+                ratio,
+                index,
+            } => Source::Fm {
+                sine: sine.clone(),
+                modulator_phase: 0.,
+                ratio: ratio as f64,
+                index,
+            },
+            Tables::Noise(color) => Source::Noise(NoiseGenerator::new(color)),
+        };
+        // FM modulates at its full index until told otherwise
+        let timbre = if let Source::Fm { .. } = source { 1. } else { 0. };
         Oscillator {
-            period_cache: waveform.generate_period(frequency, sample_rate),
-            phase: 0,
+            source,
+            phase: 0.,
+            phase_increment: frequency as f64 / tables.sample_rate as f64,
+            sample_rate: tables.sample_rate,
+            timbre,
         }
     }
 
-    pub fn get_samples(&mut self, num: usize, amplitude: f32) -> Vec<f32> {
-        let period_at_amplitude = &self.period_cache.iter().map(|s| (*s * amplitude)).collect();
-        let samples = arrays::roll_vec(&period_at_amplitude, self.phase, num);
-        self.phase = (&self.phase + num) % &self.period_cache.len();
+    /// White noise band-passed around `frequency`, about `bandwidth` Hz wide,
+    /// at a level independent of its width
+    ///
+    /// Frequency changes, as by vibrato, move the band while keeping its
+    /// width in proportion to its frequency. Timbre is ignored.
+    pub fn noise_band(frequency: f32, bandwidth: f32, sample_rate: u32) -> Oscillator {
+        assert!(frequency > 0., "Invalid frequency: {}", frequency);
+        assert!(bandwidth > 0., "Invalid bandwidth: {}", bandwidth);
+        let q = frequency / bandwidth;
+        Oscillator {
+            source: Source::NoiseBand {
+                generator: NoiseGenerator::new(NoiseColor::White),
+                filter: Biquad::new(FilterKind::BandPass, frequency, q, sample_rate),
+                q,
+            },
+            phase: 0.,
+            phase_increment: frequency as f64 / sample_rate as f64,
+            sample_rate,
+            timbre: 0.,
+        }
+    }
+
+    pub fn frequency(&self) -> f32 {
+        (self.phase_increment * self.sample_rate as f64) as f32
+    }
+
+    /// Retune immediately. Phase is kept, so the waveform stays continuous.
+    pub fn set_frequency(&mut self, frequency: f32) {
+        assert!(frequency > 0., "Invalid frequency: {}", frequency);
+        self.phase_increment = self.increment_for(frequency);
+    }
+
+    fn increment_for(&self, frequency: f32) -> f64 {
+        frequency as f64 / self.sample_rate as f64
+    }
+
+    fn render(&mut self, num: usize) -> Vec<f32> {
+        let timbre = vec![self.timbre; num];
+        let increment = self.phase_increment;
+        self.render_with(&timbre, increment, None)
+    }
+
+    /// Render a sample for each of `timbre`, which only morphing and FM waveforms read,
+    /// while the phase increment ramps linearly to `end_increment`.
+    /// Each sample's increment is scaled by the matching element of
+    /// `frequency_ratios`, if given, without changing the ramp.
+    fn render_with(
+        &mut self,
+        timbre: &[f32],
+        end_increment: f64,
+        frequency_ratios: Option<&[f32]>,
+    ) -> Vec<f32> {
+        let num = timbre.len();
+        let start_increment = self.phase_increment;
+        let increment_step = (end_increment - start_increment) / num.max(1) as f64;
+        // The increment after sample `i`, reaching `end_increment` after the last
+        let increment = |i: usize| {
+            let ramped = start_increment + (increment_step * (i + 1) as f64);
+            match frequency_ratios {
+                Some(ratios) => ramped * ratios[i] as f64,
+                None => ramped,
+            }
+        };
+        // Band-limit for the highest frequency reached
+        let max_ratio =
+            frequency_ratios.map_or(1., |ratios| ratios.iter().fold(1., |max, r| r.max(max)));
+        let max_increment = start_increment.max(end_increment) * max_ratio as f64;
+        let frequency = (max_increment * self.sample_rate as f64) as f32;
+        let sample_rate = self.sample_rate;
+        self.phase_increment = end_increment;
+
+        let mut samples = Vec::<f32>::with_capacity(num);
+        match self.source {
+            Source::Noise(ref mut generator) => return generator.get_samples(num),
+            Source::NoiseBand {
+                ref mut generator,
+                ref mut filter,
+                q,
+            } => {
+                let mut samples = generator.get_samples(num);
+                let centers: Vec<f32> =
+                    (0..num).map(|i| (increment(i) * sample_rate as f64) as f32).collect();
+                filter.process_swept(&mut samples, &centers, &vec![q; num]);
+                for (sample, center) in samples.iter_mut().zip(centers) {
+                    *sample *= noise_band_gain(center / q, sample_rate);
+                }
+                return samples;
+            }
+            Source::Table(ref table) => for i in 0..num {
+                samples.push(wavetable::read_interpolated(table, self.phase));
+                advance_phase(&mut self.phase, increment(i));
+            },
+            Source::MipMapped(ref tables) => {
+                let table = tables.table_for(frequency);
+                for i in 0..num {
+                    samples.push(wavetable::read_interpolated(table, self.phase));
+                    advance_phase(&mut self.phase, increment(i));
+                }
+            }
+            Source::Morph(ref morph_tables) => {
+                let tables: Vec<&[f32]> =
+                    morph_tables.iter().map(|tables| tables.table_for(frequency)).collect();
+                for (i, &timbre) in timbre.iter().enumerate() {
+                    samples.push(read_morphed(&tables, timbre, self.phase));
+                    advance_phase(&mut self.phase, increment(i));
+                }
+            }
+            Source::Fm {
+                ref sine,
+                ref mut modulator_phase,
+                ratio,
+                index,
+            } => {
+                let max_index = max_fm_index(frequency, ratio as f32, sample_rate);
+                for (i, &timbre) in timbre.iter().enumerate() {
+                    let index = (index * timbre).min(max_index);
+                    let modulator = wavetable::read_interpolated(sine, *modulator_phase);
+                    let carrier_phase = self.phase + ((index * modulator) / TWO_PI) as f64;
+                    let carrier_phase = carrier_phase - carrier_phase.floor();
+                    samples.push(wavetable::read_interpolated(sine, carrier_phase));
+                    advance_phase(&mut self.phase, increment(i));
+                    advance_phase(modulator_phase, increment(i) * ratio);
+                }
+            }
+        }
         samples
     }
 
+    pub fn get_samples(&mut self, num: usize, amplitude: f32) -> Vec<f32> {
+        self.render(num).iter().map(|s| *s * amplitude).collect()
+    }
+
     pub fn get_samples_with_interpolated_amp(
         &mut self,
         num: usize,
         start_amplitude: f32,
         end_amplitude: f32,
     ) -> Vec<f32> {
-        let mut samples = arrays::roll_vec(&self.period_cache, self.phase, num);
+        let mut samples = self.render(num);
         arrays::multiply_over_linspace(samples.as_mut_slice(), start_amplitude, end_amplitude);
-        self.phase = (&self.phase + num) % &self.period_cache.len();
         samples
     }
+
+    /// Get `num` samples while sweeping the timbre along a piecewise linear envelope
+    /// of `(sample_count, timbre)` segments, starting from where the last one ended.
+    ///
+    /// Segment lengths must sum to `num`.
+    /// Timbre is between 0 and 1. Morphing waveforms crossfade between their
+    /// tables by it, reading every table at the same phase so that changes in
+    /// timbre never introduce discontinuities. FM scales its modulation index
+    /// by it, starting from 1. Other waveforms ignore it.
+    pub fn get_samples_with_timbre_envelope(
+        &mut self,
+        num: usize,
+        segments: &[(usize, f32)],
+    ) -> Vec<f32> {
+        self.get_samples_with_modulation(num, Some(segments), None)
+    }
+
+    /// Get `num` samples with an optional timbre envelope, as in
+    /// `get_samples_with_timbre_envelope`, while each sample's frequency is
+    /// multiplied by the matching element of `frequency_ratios`.
+    /// The oscillator's own frequency is left unchanged, as for vibrato.
+    pub fn get_samples_with_modulation(
+        &mut self,
+        num: usize,
+        timbre_segments: Option<&[(usize, f32)]>,
+        frequency_ratios: Option<&[f32]>,
+    ) -> Vec<f32> {
+        let timbre = match timbre_segments {
+            Some(segments) => {
+                let mut timbre = vec![1.; num];
+                arrays::multiply_over_segments(timbre.as_mut_slice(), self.timbre, segments);
+                if let Some(&(_, end_timbre)) = segments.last() {
+                    self.timbre = end_timbre;
+                }
+                timbre
+            }
+            None => vec![self.timbre; num],
+        };
+        let increment = self.phase_increment;
+        self.render_with(&timbre, increment, frequency_ratios)
+    }
+
+    /// Get `num` samples while gliding linearly from `start_frequency` to
+    /// `end_frequency`, which the oscillator is left tuned to.
+    ///
+    /// Phase is continuous across the glide and across calls, so chaining
+    /// glides produces a smooth glissando.
+    pub fn get_samples_with_interpolated_freq(
+        &mut self,
+        num: usize,
+        start_frequency: f32,
+        end_frequency: f32,
+    ) -> Vec<f32> {
+        self.set_frequency(start_frequency);
+        assert!(end_frequency > 0., "Invalid frequency: {}", end_frequency);
+        let timbre = vec![self.timbre; num];
+        let end_increment = self.increment_for(end_frequency);
+        self.render_with(&timbre, end_increment, None)
+    }
+}
+
+/// Gain bringing white noise band-passed to `bandwidth` to `NOISE_BAND_RMS`.
+/// A resonant band-pass passes noise over about pi / 2 times its bandwidth.
+#[inline]
+fn noise_band_gain(bandwidth: f32, sample_rate: u32) -> f32 {
+    let nyquist = sample_rate as f32 / 2.;
+    let passed_fraction = ((consts::PI / 2.) * bandwidth / nyquist).min(1.);
+    NOISE_BAND_RMS / (WHITE_NOISE_RMS * passed_fraction.sqrt())
+}
+
+/// Sidebands kept below Nyquist beyond the `index + 1` of Carson's rule,
+/// past which too little power remains to be heard aliasing
+const FM_GUARD_SIDEBANDS: f32 = 4.;
+
+/// The largest FM index which keeps Carson's bandwidth, and `FM_GUARD_SIDEBANDS`
+/// more above it, below Nyquist
+fn max_fm_index(frequency: f32, ratio: f32, sample_rate: u32) -> f32 {
+    let headroom = (sample_rate as f32 / 2.) - frequency;
+    ((headroom / (ratio * frequency)) - 1. - FM_GUARD_SIDEBANDS).max(0.)
+}
+
+#[inline]
+fn advance_phase(phase: &mut f64, phase_increment: f64) {
+    *phase += phase_increment;
+    if *phase >= 1. {
+        *phase -= phase.floor();
+    }
+}
+
+/// Read `tables` at `phase`, crossfading between the two nearest to `timbre`
+#[inline]
+fn read_morphed(tables: &[&[f32]], timbre: f32, phase: f64) -> f32 {
+    let position = timbre.max(0.).min(1.) * (tables.len() - 1) as f32;
+    let index = position as usize;
+    let current = wavetable::read_interpolated(tables[index], phase);
+    if index + 1 == tables.len() {
+        return current;
+    }
+    let next = wavetable::read_interpolated(tables[index + 1], phase);
+    current + ((next - current) * (position - index as f32))
 }
 
 #[cfg(test)]
@@ -103,8 +604,8 @@ mod tests {
 
             #[test]
             fn compare_against_known_good_output() {
-                let actual = Waveform::Sine.generate_period(2250., 44100);
-                #[rustfmt_skip]
+                let actual = Waveform::Sine.generate_cycle(19);
+                #[rustfmt::skip]
                 let expected = vec![
                     // these values verified as sane by plotting and doing an eyeball check
                     0.0, 0.32469946, 0.6142127, 0.8371665, 0.9694003, 0.9965845,
@@ -117,7 +618,7 @@ mod tests {
 
             #[test]
             fn capacity_used() {
-                let period = Waveform::Sine.generate_period(10., 44100);
+                let period = Waveform::Sine.generate_cycle(4410);
                 assert_eq!(period.len(), period.capacity());
             }
         }
@@ -127,8 +628,8 @@ mod tests {
 
             #[test]
             fn compare_against_known_good_output() {
-                let actual = Waveform::Square.generate_period(4410., 44100);
-                #[rustfmt_skip]
+                let actual = Waveform::Square.generate_cycle(10);
+                #[rustfmt::skip]
                 let expected = vec![
                     // these values verified as sane by plotting and doing an eyeball check
                     1.0, 1.0, 1.0, 1.0, 1.0,
@@ -139,7 +640,140 @@ mod tests {
 
             #[test]
             fn capacity_used() {
-                let period = Waveform::Square.generate_period(10., 44100);
+                let period = Waveform::Square.generate_cycle(4410);
+                assert_eq!(period.len(), period.capacity());
+            }
+        }
+
+        mod saw {
+            use super::*;
+
+            #[test]
+            fn compare_against_known_good_output() {
+                let actual = Waveform::Saw.generate_cycle(8);
+                #[rustfmt::skip]
+                let expected = vec![
+                    0.0, 0.25, 0.5, 0.75, -1.0, -0.75, -0.5, -0.25,
+                ];
+                assert_almost_eq_by_element(actual, expected);
+            }
+        }
+
+        mod triangle {
+            use super::*;
+
+            #[test]
+            fn compare_against_known_good_output() {
+                let actual = Waveform::Triangle.generate_cycle(8);
+                #[rustfmt::skip]
+                let expected = vec![
+                    0.0, 0.5, 1.0, 0.5, 0.0, -0.5, -1.0, -0.5,
+                ];
+                assert_almost_eq_by_element(actual, expected);
+            }
+        }
+
+        mod pulse {
+            use super::*;
+
+            #[test]
+            fn compare_against_known_good_output() {
+                let actual = Waveform::Pulse { duty: 0.25 }.generate_cycle(8);
+                #[rustfmt::skip]
+                let expected = vec![
+                    1.0, 1.0, -1.0, -1.0, -1.0, -1.0, -1.0, -1.0,
+                ];
+                assert_almost_eq_by_element(actual, expected);
+            }
+
+            #[test]
+            fn half_duty_matches_square() {
+                let pulse = Waveform::Pulse { duty: 0.5 }.generate_cycle(10);
+                let square = Waveform::Square.generate_cycle(10);
+                assert_almost_eq_by_element(pulse, square);
+            }
+        }
+
+        mod wavetable {
+            use super::*;
+
+            #[test]
+            fn resampled_to_period() {
+                let wavetable = Wavetable::open("resources/wavetables/soft_saw.wav").unwrap();
+                let waveform = Waveform::Wavetable(wavetable.clone());
+                let period = waveform.generate_cycle(64);
+                assert_eq!(period.len(), 64);
+                // Only differs by the file's quantization noise in the dropped harmonics
+                for (actual, expected) in period.iter().zip(wavetable.cycle().iter().step_by(64)) {
+                    assert!((actual - expected).abs() < 1e-3);
+                }
+            }
+        }
+
+        mod additive {
+            use super::*;
+
+            #[test]
+            fn single_partial_matches_sine() {
+                let additive = Waveform::Additive(vec![(1, 0.5, 0.)]).generate_cycle(19);
+                let sine = Waveform::Sine.generate_cycle(19);
+                assert_almost_eq_by_element(additive, sine);
+            }
+
+            #[test]
+            fn compare_against_known_good_output() {
+                let partials = vec![(1, 1., 0.), (2, 0.5, 0.25)];
+                let actual = Waveform::Additive(partials).generate_cycle(8);
+                // sin(x) + 0.5 * cos(2x), normalized by its peak of -1.5 at 3/4 of the cycle
+                #[rustfmt::skip]
+                let expected = vec![
+                    0.33333333, 0.47140452, 0.33333333, 0.47140452,
+                    0.33333333, -0.47140452, -1.0, -0.47140452,
+                ];
+                assert_almost_eq_by_element(actual, expected);
+            }
+
+            #[test]
+            fn odd_harmonics_are_half_wave_symmetric() {
+                let partials = vec![(1, 1., 0.), (3, 0.5, 0.1), (5, 0.25, 0.3)];
+                let period = Waveform::Additive(partials).generate_cycle(100);
+                for i in 0..50 {
+                    assert_almost_eq(period[i], -period[i + 50]);
+                }
+            }
+
+            #[test]
+            fn capacity_used() {
+                let period = Waveform::Additive(vec![(1, 1., 0.)]).generate_cycle(4410);
+                assert_eq!(period.len(), period.capacity());
+            }
+        }
+
+        mod fm {
+            use super::*;
+
+            #[test]
+            fn no_index_matches_sine() {
+                let fm = Waveform::Fm { ratio: 2., index: 0. }.generate_cycle(19);
+                let sine = Waveform::Sine.generate_cycle(19);
+                assert_almost_eq_by_element(fm, sine);
+            }
+
+            #[test]
+            fn compare_against_known_good_output() {
+                let actual = Waveform::Fm { ratio: 1., index: 1. }.generate_cycle(4);
+                // sin(x + sin(x)) at quarter cycles
+                let expected = vec![0., 0.5403023, 0., -0.5403023];
+                assert_almost_eq_by_element(actual, expected);
+            }
+        }
+
+        mod noise {
+            use super::*;
+
+            #[test]
+            fn capacity_used() {
+                let period = Waveform::PinkNoise.generate_cycle(4410);
                 assert_eq!(period.len(), period.capacity());
             }
         }
@@ -153,7 +787,7 @@ mod tests {
             let mut osc = Oscillator::new(Waveform::Sine, 4410., 44100);
             let mut samples = osc.get_samples(10, 1.);
             samples.append(&mut osc.get_samples(10, 1.));
-            #[rustfmt_skip]
+            #[rustfmt::skip]
             let expected: Vec<f32> = vec![
                 0.0, 0.58778524, 0.95105654, 0.9510565, 0.5877852,
                 -0.00000008742278, -0.58778536, -0.9510565, -0.9510565, -0.58778495,
@@ -166,8 +800,8 @@ mod tests {
         #[test]
         fn get_samples_with_interpolated_amp() {
             let mut osc = Oscillator::new(Waveform::Sine, 4410., 44100);
-            let mut samples = osc.get_samples_with_interpolated_amp(20, 0., 1.);
-            #[rustfmt_skip]
+            let samples = osc.get_samples_with_interpolated_amp(20, 0., 1.);
+            #[rustfmt::skip]
             let expected: Vec<f32> = vec![
                 0.0, 0.030936066, 0.10011122, 0.15016681, 0.12374425,
                 -0.000000023005995, -0.18561642, -0.3503892, -0.40044484,
@@ -178,6 +812,337 @@ mod tests {
             assert_almost_eq_by_element(samples, expected);
         }
 
+        /// Estimate the frequency of `samples` by counting rising zero crossings
+        fn measure_frequency(samples: &[f32], sample_rate: u32) -> f32 {
+            let rising_crossings = samples
+                .windows(2)
+                .filter(|pair| pair[0] < 0. && pair[1] >= 0.)
+                .count();
+            rising_crossings as f32 * sample_rate as f32 / samples.len() as f32
+        }
+
+        #[test]
+        fn high_frequency_sine_in_tune() {
+            // A 2250 Hz period truncated to 19 samples would sound at ~2321 Hz
+            let mut osc = Oscillator::new(Waveform::Sine, 2250., 44100);
+            let samples = osc.get_samples(44100 * 4, 1.);
+            assert!((measure_frequency(&samples, 44100) - 2250.).abs() < 0.5);
+        }
+
+        #[test]
+        fn fractional_frequency_in_tune() {
+            let mut osc = Oscillator::new(Waveform::Square, 261.63, 44100);
+            let samples = osc.get_samples(44100 * 4, 1.);
+            assert!((measure_frequency(&samples, 44100) - 261.63).abs() < 0.5);
+        }
+
+        #[test]
+        fn pitch_stays_in_tune_across_calls() {
+            let mut osc = Oscillator::new(Waveform::Sine, 3520., 44100);
+            let mut samples = Vec::<f32>::new();
+            for _ in 0..400 {
+                samples.append(&mut osc.get_samples(441, 1.));
+            }
+            assert!((measure_frequency(&samples, 44100) - 3520.).abs() < 0.5);
+        }
+
+        #[test]
+        fn saw_in_tune() {
+            let mut osc = Oscillator::new(Waveform::Saw, 1234.5, 44100);
+            let samples = osc.get_samples(44100 * 4, 1.);
+            assert!((measure_frequency(&samples, 44100) - 1234.5).abs() < 0.5);
+        }
+
+        #[test]
+        fn wavetable_in_tune() {
+            let wavetable = Wavetable::open("resources/wavetables/soft_saw.wav").unwrap();
+            let mut osc = Oscillator::new(Waveform::Wavetable(wavetable), 523.25, 44100);
+            let samples = osc.get_samples(44100 * 4, 1.);
+            assert!((measure_frequency(&samples, 44100) - 523.25).abs() < 0.5);
+        }
+
+        #[test]
+        fn noise_ignores_frequency() {
+            let mut osc = Oscillator::new(Waveform::WhiteNoise, 1., 44100);
+            let samples = osc.get_samples(44100, 1.);
+            // A 1 Hz periodic waveform would cross zero once per second
+            assert!(measure_frequency(&samples, 44100) > 1000.);
+        }
+
+        /// Fraction of the power in `samples` which lies outside DC and the harmonics
+        /// of `fundamental` below Nyquist; any such power has been aliased.
+        /// Harmonics must land on exact DFT bins of `samples`.
+        fn aliased_power_ratio(samples: &[f32], fundamental: f64, sample_rate: u32) -> f64 {
+            let len = samples.len() as f64;
+            let total_power = samples.iter().map(|s| (*s as f64).powi(2)).sum::<f64>() / len;
+            let mean = samples.iter().map(|s| *s as f64).sum::<f64>() / len;
+            let mut harmonic_power = mean.powi(2);
+            let mut frequency = fundamental;
+            while frequency < sample_rate as f64 / 2. {
+                let (mut re, mut im) = (0., 0.);
+                for (n, sample) in samples.iter().enumerate() {
+                    let angle =
+                        2. * ::std::f64::consts::PI * frequency * n as f64 / sample_rate as f64;
+                    re += *sample as f64 * angle.cos();
+                    im -= *sample as f64 * angle.sin();
+                }
+                harmonic_power += 2. * (re.powi(2) + im.powi(2)) / len.powi(2);
+                frequency += fundamental;
+            }
+            1. - (harmonic_power / total_power)
+        }
+
+        #[test]
+        fn band_limited_waveforms_do_not_alias() {
+            let waveforms = vec![
+                Waveform::Square,
+                Waveform::Saw,
+                Waveform::Triangle,
+                Waveform::Pulse { duty: 0.25 },
+            ];
+            for waveform in waveforms {
+                let mut osc = Oscillator::new(waveform.clone(), 2900., 44100);
+                let samples = osc.get_samples(44100, 1.);
+                let ratio = aliased_power_ratio(&samples, 2900., 44100);
+                assert!(ratio < 1e-5, "{:?} aliased power ratio: {}", waveform, ratio);
+            }
+        }
+
+        #[test]
+        fn naive_saw_aliases() {
+            // Sanity check that aliased_power_ratio catches the unfiltered cycle
+            let mut osc = Oscillator {
+                source: Source::Table(Arc::new(Waveform::Saw.generate_cycle(TABLE_LEN))),
+                phase: 0.,
+                phase_increment: 2900. / 44100.,
+                sample_rate: 44100,
+                timbre: 0.,
+            };
+            let samples = osc.get_samples(44100, 1.);
+            assert!(aliased_power_ratio(&samples, 2900., 44100) > 1e-2);
+        }
+
+        #[test]
+        fn morph_extremes_match_waveforms() {
+            let morph = Waveform::Morph(vec![Waveform::Sine, Waveform::Saw]);
+            let mut osc = Oscillator::new(morph, 441., 44100);
+            let first = osc.get_samples_with_timbre_envelope(100, &[(100, 0.)]);
+            osc.get_samples_with_timbre_envelope(1, &[(1, 1.)]);
+            let last = osc.get_samples_with_timbre_envelope(100, &[(100, 1.)]);
+
+            let mut sine = Oscillator::new(Waveform::Sine, 441., 44100);
+            assert_almost_eq_by_element(first, sine.get_samples(100, 1.));
+            let mut saw = Oscillator::new(Waveform::Saw, 441., 44100);
+            saw.get_samples(101, 1.);
+            assert_almost_eq_by_element(last, saw.get_samples(100, 1.));
+        }
+
+        #[test]
+        fn morph_crossfades_between_neighbours() {
+            let waveforms = vec![Waveform::Sine, Waveform::Square, Waveform::Triangle];
+            let mut osc = Oscillator::new(Waveform::Morph(waveforms), 441., 44100);
+            osc.get_samples_with_timbre_envelope(1, &[(1, 0.25)]);
+            let samples = osc.get_samples_with_timbre_envelope(100, &[(100, 0.25)]);
+            let mut sine = Oscillator::new(Waveform::Sine, 441., 44100);
+            let mut square = Oscillator::new(Waveform::Square, 441., 44100);
+            sine.get_samples(1, 1.);
+            square.get_samples(1, 1.);
+            let expected: Vec<f32> = sine
+                .get_samples(100, 1.)
+                .iter()
+                .zip(square.get_samples(100, 1.).iter())
+                .map(|(sine, square)| (sine + square) / 2.)
+                .collect();
+            assert_almost_eq_by_element(samples, expected);
+        }
+
+        #[test]
+        fn morph_sweep_is_continuous() {
+            let morph = Waveform::Morph(vec![Waveform::Sine, Waveform::Triangle]);
+            let mut osc = Oscillator::new(morph, 100., 44100);
+            let mut samples = osc.get_samples_with_timbre_envelope(1000, &[(1000, 1.)]);
+            samples.append(&mut osc.get_samples_with_timbre_envelope(1000, &[(1000, 0.)]));
+            // A 100 Hz sine never moves more than 2 * pi * 100 / 44100 per sample
+            let max_step = samples
+                .windows(2)
+                .map(|pair| (pair[1] - pair[0]).abs())
+                .fold(0., f32::max);
+            assert!(max_step < 0.02, "discontinuity of {}", max_step);
+        }
+
+        #[test]
+        fn fm_matches_closed_form() {
+            let mut osc = Oscillator::new(Waveform::Fm { ratio: 2., index: 3. }, 441., 44100);
+            let samples = osc.get_samples(200, 1.);
+            let expected: Vec<f32> = (0..200)
+                .map(|n| {
+                    let x = TWO_PI * 441. * n as f32 / 44100.;
+                    (x + (3. * (2. * x).sin())).sin()
+                })
+                .collect();
+            for (actual, expected) in samples.iter().zip(expected.iter()) {
+                assert!((actual - expected).abs() < 1e-3);
+            }
+        }
+
+        #[test]
+        fn fm_index_scaled_by_timbre() {
+            let mut fm = Oscillator::new(Waveform::Fm { ratio: 3.5, index: 5. }, 441., 44100);
+            fm.get_samples_with_timbre_envelope(1, &[(1, 0.)]);
+            let samples = fm.get_samples_with_timbre_envelope(100, &[(100, 0.)]);
+            let mut sine = Oscillator::new(Waveform::Sine, 441., 44100);
+            sine.get_samples(1, 1.);
+            assert_almost_eq_by_element(samples, sine.get_samples(100, 1.));
+        }
+
+        #[test]
+        fn fm_in_tune() {
+            let mut osc = Oscillator::new(Waveform::Fm { ratio: 1., index: 1. }, 261.63, 44100);
+            let samples = osc.get_samples(44100 * 4, 1.);
+            assert!((measure_frequency(&samples, 44100) - 261.63).abs() < 0.5);
+        }
+
+        #[test]
+        fn fm_does_not_alias() {
+            for &(ratio, index) in &[(1., 1.), (1., 20.), (2., 20.)] {
+                let mut osc = Oscillator::new(Waveform::Fm { ratio, index }, 2900., 44100);
+                let samples = osc.get_samples(44100, 1.);
+                let aliased = aliased_power_ratio(&samples, 2900., 44100);
+                assert!(
+                    aliased < 1e-5,
+                    "ratio {} index {} aliased power ratio: {}",
+                    ratio,
+                    index,
+                    aliased
+                );
+            }
+        }
+
+        #[test]
+        fn oscillators_share_tables() {
+            let morph = Waveform::Morph(vec![Waveform::Sine, Waveform::Saw]);
+            let tables = WaveformTables::new(&morph, 44100);
+            let oscillators: Vec<Oscillator> = (1..4)
+                .map(|harmonic| Oscillator::from_tables(&tables, 100. * harmonic as f32))
+                .collect();
+            match tables.tables {
+                Tables::Morph(ref morph_tables) => assert_eq!(Arc::strong_count(morph_tables), 4),
+                _ => panic!("expected morph tables"),
+            }
+            assert_almost_eq(oscillators[2].frequency(), 300.);
+        }
+
+        #[test]
+        fn set_frequency_retunes() {
+            let mut osc = Oscillator::new(Waveform::Saw, 440., 44100);
+            osc.get_samples(1000, 1.);
+            osc.set_frequency(660.);
+            assert_almost_eq(osc.frequency(), 660.);
+            let samples = osc.get_samples(44100 * 4, 1.);
+            assert!((measure_frequency(&samples, 44100) - 660.).abs() < 0.5);
+        }
+
+        #[test]
+        fn glide_passes_through_mean_frequency() {
+            let mut osc = Oscillator::new(Waveform::Sine, 100., 44100);
+            let samples = osc.get_samples_with_interpolated_freq(44100 * 4, 100., 300.);
+            assert!((measure_frequency(&samples, 44100) - 200.).abs() < 0.5);
+            assert_almost_eq(osc.frequency(), 300.);
+        }
+
+        #[test]
+        fn glide_matches_accumulated_phase() {
+            let mut osc = Oscillator::new(Waveform::Sine, 441., 44100);
+            let samples = osc.get_samples_with_interpolated_freq(100, 441., 882.);
+            let mut phase = 0f64;
+            for (i, sample) in samples.iter().enumerate() {
+                assert!((sample - (phase * 2. * ::std::f64::consts::PI).sin() as f32).abs() < 1e-4);
+                phase += (441. + (441. * (i + 1) as f64 / 100.)) / 44100.;
+            }
+        }
+
+        #[test]
+        fn chained_glides_are_continuous() {
+            let mut osc = Oscillator::new(Waveform::Triangle, 100., 44100);
+            let mut samples = Vec::<f32>::new();
+            let mut frequency = 100.;
+            for _ in 0..20 {
+                let end_frequency = frequency * 1.1;
+                samples.append(&mut osc.get_samples_with_interpolated_freq(
+                    441,
+                    frequency,
+                    end_frequency,
+                ));
+                frequency = end_frequency;
+            }
+            // A triangle's slope is 4 * frequency / sample_rate, plus up to ~9%
+            // of Gibbs overshoot once band-limited
+            let max_step = samples
+                .windows(2)
+                .map(|pair| (pair[1] - pair[0]).abs())
+                .fold(0., f32::max);
+            assert!(max_step < 1.2 * 4. * frequency / 44100., "discontinuity of {}", max_step);
+        }
+
+        #[test]
+        fn timbre_ignored_by_plain_waveforms() {
+            let mut osc = Oscillator::new(Waveform::Sine, 4410., 44100);
+            let samples = osc.get_samples_with_timbre_envelope(10, &[(10, 1.)]);
+            let mut plain = Oscillator::new(Waveform::Sine, 4410., 44100);
+            assert_almost_eq_by_element(samples, plain.get_samples(10, 1.));
+        }
+
+        #[test]
+        fn frequency_ratios_scale_frequency() {
+            let mut osc = Oscillator::new(Waveform::Saw, 441., 44100);
+            let samples = osc.get_samples_with_modulation(100, None, Some(&[2.; 100]));
+            let mut doubled = Oscillator::new(Waveform::Saw, 882., 44100);
+            assert_almost_eq_by_element(samples, doubled.get_samples(100, 1.));
+            assert_almost_eq(osc.frequency(), 441.);
+            // Phase carries on from where the modulated samples left it
+            doubled.set_frequency(441.);
+            assert_almost_eq_by_element(osc.get_samples(100, 1.), doubled.get_samples(100, 1.));
+        }
+
+        fn rms(samples: &[f32]) -> f32 {
+            (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt()
+        }
+
+        #[test]
+        fn noise_band_level_is_independent_of_width() {
+            for &(frequency, bandwidth) in &[(1000., 100.), (1000., 500.), (200., 50.)] {
+                // Long enough for narrow bands to settle to their average level
+                let mut osc = Oscillator::noise_band(frequency, bandwidth, 44100);
+                let level = rms(&osc.get_samples(176400, 1.)[4410..]);
+                assert!((level - NOISE_BAND_RMS).abs() < 0.1, "level of {}", level);
+            }
+        }
+
+        #[test]
+        fn noise_band_is_centered_on_frequency() {
+            let samples = Oscillator::noise_band(1000., 100., 44100).get_samples(44100, 1.);
+            let level_at = |frequency: f32| {
+                let mut filtered = samples.clone();
+                Biquad::new(FilterKind::BandPass, frequency, 10., 44100).process(&mut filtered);
+                rms(&filtered[4410..])
+            };
+            assert!(level_at(1000.) > 10. * level_at(250.));
+            assert!(level_at(1000.) > 10. * level_at(4000.));
+        }
+
+        #[test]
+        fn frequency_ratios_move_noise_band() {
+            let mut osc = Oscillator::noise_band(1000., 100., 44100);
+            let samples = osc.get_samples_with_modulation(44100, None, Some(&[4.; 44100]));
+            let level_at = |frequency: f32| {
+                let mut filtered = samples.clone();
+                Biquad::new(FilterKind::BandPass, frequency, 10., 44100).process(&mut filtered);
+                rms(&filtered[4410..])
+            };
+            assert!(level_at(4000.) > 10. * level_at(1000.));
+            assert_almost_eq(osc.frequency(), 1000.);
+        }
+
         #[test]
         #[ignore]
         fn test() {
@@ -195,7 +1160,7 @@ mod tests {
                 elapsed_ms
             );
             // 250_000_000 / s
-            assert!(false);
+            panic!();
         }
 
         #[test]
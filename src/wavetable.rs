@@ -0,0 +1,320 @@
+use std::f64::consts;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use hound;
+
+use fft;
+
+/// Length of the single-cycle tables oscillators read from.
+/// Large enough that linear interpolation of a sine is accurate to ~3e-7.
+pub const TABLE_LEN: usize = 4096;
+
+/// Highest fundamental covered by the lowest (most harmonically rich) table
+const LOWEST_TABLE_FREQUENCY: f32 = 20.;
+
+/// Read `table` at `phase` (between 0 and 1) with linear interpolation
+#[inline]
+pub fn read_interpolated(table: &[f32], phase: f64) -> f32 {
+    let position = phase * table.len() as f64;
+    let index = position as usize;
+    let fraction = (position - index as f64) as f32;
+    // Rounding can leave a wrapped phase at exactly 1
+    let current = table[index % table.len()];
+    let next = table[(index + 1) % table.len()];
+    current + ((next - current) * fraction)
+}
+
+/// Band-limited copies of a single cycle, one per octave of fundamental frequency
+///
+/// Each table keeps only the harmonics which stay below Nyquist at the
+/// highest fundamental it covers, so reading it at or below that
+/// frequency never aliases.
+pub struct MipMappedTable {
+    tables: Vec<Vec<f32>>,
+}
+
+impl MipMappedTable {
+    /// Build from a cycle `TABLE_LEN` samples long, for playback at `sample_rate`
+    pub fn from_cycle(cycle: &[f32], sample_rate: u32) -> MipMappedTable {
+        assert_eq!(cycle.len(), TABLE_LEN, "Cycle must be {} samples long", TABLE_LEN);
+        let mut spectrum_re: Vec<f64> = cycle.iter().map(|s| *s as f64).collect();
+        let mut spectrum_im = vec![0.; TABLE_LEN];
+        fft::fft(&mut spectrum_re, &mut spectrum_im, false);
+
+        let nyquist = sample_rate as f32 / 2.;
+        let mut tables = Vec::new();
+        let mut top_frequency = LOWEST_TABLE_FREQUENCY;
+        loop {
+            // Strictly below Nyquist, and within what the table itself can represent
+            let harmonics = (((nyquist / top_frequency).ceil() as usize).max(1) - 1)
+                .min((TABLE_LEN / 2) - 1);
+            tables.push(Self::band_limit(&spectrum_re, &spectrum_im, harmonics));
+            if harmonics == 0 {
+                break;
+            }
+            top_frequency *= 2.;
+        }
+        MipMappedTable { tables }
+    }
+
+    /// Resynthesize a cycle from its spectrum, keeping DC and the first `harmonics` partials
+    fn band_limit(spectrum_re: &[f64], spectrum_im: &[f64], harmonics: usize) -> Vec<f32> {
+        let mut re = spectrum_re.to_vec();
+        let mut im = spectrum_im.to_vec();
+        for bin in (harmonics + 1)..(TABLE_LEN - harmonics) {
+            re[bin] = 0.;
+            im[bin] = 0.;
+        }
+        fft::fft(&mut re, &mut im, true);
+        re.iter().map(|s| *s as f32).collect()
+    }
+
+    /// The richest table which does not alias when played at `frequency`
+    pub fn table_for(&self, frequency: f32) -> &[f32] {
+        let index = if frequency <= LOWEST_TABLE_FREQUENCY {
+            0
+        } else {
+            (frequency / LOWEST_TABLE_FREQUENCY).log2().ceil() as usize
+        };
+        &self.tables[index.min(self.tables.len() - 1)]
+    }
+}
+
+/// Resample one cycle to `len` samples by resynthesizing its harmonics
+///
+/// Unlike interpolation this adds no harmonics of its own, and drops
+/// any which `len` samples cannot represent.
+pub fn resample_cycle(cycle: &[f32], len: usize) -> Vec<f32> {
+    if cycle.len() == len {
+        return cycle.to_vec();
+    }
+    let harmonics = ((cycle.len() + 1) / 2).min((len + 1) / 2);
+    let cycle_len = cycle.len();
+
+    // Direct DFT of the harmonics we keep, reading from one cycle of sine and cosine
+    let cycle_phasors = unit_phasors(cycle_len);
+    let coefficients: Vec<(f64, f64)> = (0..harmonics)
+        .map(|k| {
+            let (mut re, mut im) = (0., 0.);
+            for (n, sample) in cycle.iter().enumerate() {
+                let (cos, sin) = cycle_phasors[(k * n) % cycle_len];
+                re += *sample as f64 * cos;
+                im -= *sample as f64 * sin;
+            }
+            (re / cycle_len as f64, im / cycle_len as f64)
+        })
+        .collect();
+
+    let phasors = unit_phasors(len);
+    (0..len)
+        .map(|n| {
+            let mut sample = coefficients[0].0;
+            for k in 1..harmonics {
+                let (cos, sin) = phasors[(k * n) % len];
+                let (re, im) = coefficients[k];
+                sample += 2. * ((re * cos) - (im * sin));
+            }
+            sample as f32
+        })
+        .collect()
+}
+
+/// `(cos, sin)` at `len` evenly spaced points around the unit circle
+fn unit_phasors(len: usize) -> Vec<(f64, f64)> {
+    (0..len)
+        .map(|i| {
+            let (sin, cos) = (2. * consts::PI * i as f64 / len as f64).sin_cos();
+            (cos, sin)
+        })
+        .collect()
+}
+
+/// Read the first channel of the WAV file at `path`, scaled to between -1 and 1,
+/// along with its sample rate
+pub fn read_first_channel<P: AsRef<Path>>(path: P) -> Result<(Vec<f32>, u32), hound::Error> {
+    let mut reader = hound::WavReader::open(path)?;
+    let spec = reader.spec();
+    let samples: Vec<f32> = match spec.sample_format {
+        hound::SampleFormat::Float => reader.samples::<f32>().collect::<Result<_, _>>()?,
+        hound::SampleFormat::Int => {
+            let full_scale = (1i64 << (spec.bits_per_sample - 1)) as f32;
+            reader
+                .samples::<i32>()
+                .map(|sample| sample.map(|s| s as f32 / full_scale))
+                .collect::<Result<_, _>>()?
+        }
+    };
+    let first_channel = samples.into_iter().step_by(spec.channels as usize).collect();
+    Ok((first_channel, spec.sample_rate))
+}
+
+/// A user-supplied single cycle, read from a WAV file
+///
+/// Deserializes from the file's path without reading it;
+/// `PipelineBuilder::build` loads the wavetables of every layer.
+/// Only the first channel of the file is used.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(from = "PathBuf")]
+pub struct Wavetable {
+    pub path: PathBuf,
+    // Resampled to `TABLE_LEN`, shared between every oscillator using it
+    cycle: Option<Arc<Vec<f32>>>,
+}
+
+impl From<PathBuf> for Wavetable {
+    fn from(path: PathBuf) -> Wavetable {
+        Wavetable { path, cycle: None }
+    }
+}
+
+impl Wavetable {
+    /// Read a wavetable from the WAV file at `path`
+    pub fn open<P: Into<PathBuf>>(path: P) -> Result<Wavetable, hound::Error> {
+        let mut wavetable = Wavetable::from(path.into());
+        wavetable.load()?;
+        Ok(wavetable)
+    }
+
+    /// Read the WAV file at `path` if it hasn't been already
+    pub fn load(&mut self) -> Result<(), hound::Error> {
+        if self.cycle.is_some() {
+            return Ok(());
+        }
+        let (first_channel, _) = read_first_channel(&self.path)?;
+        if first_channel.is_empty() {
+            return Err(hound::Error::FormatError("wavetable contains no samples"));
+        }
+        self.cycle = Some(Arc::new(resample_cycle(&first_channel, TABLE_LEN)));
+        Ok(())
+    }
+
+    /// The loaded cycle, `TABLE_LEN` samples long
+    ///
+    /// Panics if the wavetable has not been loaded.
+    pub fn cycle(&self) -> &[f32] {
+        match self.cycle {
+            Some(ref cycle) => cycle,
+            None => panic!("Wavetable {} has not been loaded", self.path.display()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::f32;
+    use test_utils::*;
+
+    fn sine_cycle(len: usize) -> Vec<f32> {
+        (0..len)
+            .map(|i| (i as f32 * 2. * f32::consts::PI / len as f32).sin())
+            .collect()
+    }
+
+    fn naive_square() -> Vec<f32> {
+        (0..TABLE_LEN)
+            .map(|i| if i < TABLE_LEN / 2 { 1. } else { -1. })
+            .collect()
+    }
+
+    #[test]
+    fn read_interpolated_between_samples() {
+        let table = vec![0., 1., 0., -1.];
+        assert_almost_eq(read_interpolated(&table, 0.), 0.);
+        assert_almost_eq(read_interpolated(&table, 0.125), 0.5);
+        assert_almost_eq(read_interpolated(&table, 0.875), -0.5);
+    }
+
+    #[test]
+    fn sine_survives_band_limiting() {
+        let sine = sine_cycle(TABLE_LEN);
+        let tables = MipMappedTable::from_cycle(&sine, 44100);
+        for frequency in &[1., 440., 10000., 20000.] {
+            for (actual, expected) in tables.table_for(*frequency).iter().zip(sine.iter()) {
+                assert!((actual - expected).abs() < 1e-5);
+            }
+        }
+    }
+
+    #[test]
+    fn higher_tables_are_smoother() {
+        let tables = MipMappedTable::from_cycle(&naive_square(), 44100);
+        // Largest jump between neighbouring samples shrinks as harmonics are removed
+        let max_step = |table: &[f32]| {
+            table.windows(2).map(|pair| (pair[1] - pair[0]).abs()).fold(0., f32::max)
+        };
+        let low = max_step(tables.table_for(20.));
+        let high = max_step(tables.table_for(5000.));
+        assert!(low > high * 10.);
+    }
+
+    #[test]
+    fn silent_above_nyquist() {
+        let tables = MipMappedTable::from_cycle(&naive_square(), 44100);
+        for sample in tables.table_for(30000.) {
+            assert!(sample.abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn resample_cycle_up() {
+        let resampled = resample_cycle(&sine_cycle(600), TABLE_LEN);
+        for (actual, expected) in resampled.iter().zip(sine_cycle(TABLE_LEN).iter()) {
+            assert!((actual - expected).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn resample_cycle_down_drops_unrepresentable_harmonics() {
+        // The 5th harmonic doesn't fit in 8 samples
+        let cycle: Vec<f32> = sine_cycle(64)
+            .iter()
+            .enumerate()
+            .map(|(i, s)| s + (i as f32 * 10. * f32::consts::PI / 64.).sin())
+            .collect();
+        assert_almost_eq_by_element(resample_cycle(&cycle, 8), sine_cycle(8));
+    }
+
+    #[test]
+    fn load_int_wav_first_channel() {
+        let path = env::temp_dir().join("spectrophoner_load_int_wav_first_channel.wav");
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: 44100,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        for sample in sine_cycle(TABLE_LEN) {
+            writer.write_sample((sample * 32767.) as i16).unwrap();
+            writer.write_sample(i16::max_value()).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let wavetable = Wavetable::open(path).unwrap();
+        for (actual, expected) in wavetable.cycle().iter().zip(sine_cycle(TABLE_LEN).iter()) {
+            assert!((actual - expected).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn load_resource() {
+        let wavetable = Wavetable::open("resources/wavetables/soft_saw.wav").unwrap();
+        assert_eq!(wavetable.cycle().len(), TABLE_LEN);
+        let peak = wavetable.cycle().iter().fold(0., |peak: f32, s| peak.max(s.abs()));
+        assert!((peak - 0.9).abs() < 0.01);
+    }
+
+    #[test]
+    fn load_missing_file_fails() {
+        assert!(Wavetable::open("resources/wavetables/missing.wav").is_err());
+    }
+
+    #[test]
+    #[should_panic]
+    fn unloaded_cycle_panics() {
+        Wavetable::from(PathBuf::from("resources/wavetables/soft_saw.wav")).cycle();
+    }
+}